@@ -0,0 +1,152 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result, anyhow};
+use serde::{Deserialize, Serialize};
+
+#[cfg(test)]
+use crate::testing::TempDir;
+use crate::touchpad_i2c::Brightness;
+
+const SYSFS_IIO_DEVICES: &str = "/sys/bus/iio/devices";
+
+/// The brightness to use from `lux` upwards, until the next threshold.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct LuxThreshold {
+    pub(crate) lux: u32,
+    pub(crate) brightness: Brightness,
+}
+
+/// Pick the brightness of the highest threshold that `lux` reaches.
+///
+/// Readings below every threshold use the lowest one.
+fn level_for(lux: f64, thresholds: &[LuxThreshold]) -> Option<Brightness> {
+    let lowest = thresholds.iter().min_by_key(|threshold| threshold.lux)?;
+    let reached = thresholds
+        .iter()
+        .filter(|threshold| threshold.lux as f64 <= lux)
+        .max_by_key(|threshold| threshold.lux);
+    Some(reached.unwrap_or(lowest).brightness)
+}
+
+fn read_value(path: &Path) -> Result<f64> {
+    std::fs::read_to_string(path)
+        .with_context(|| format!("Could not read {}", path.display()))?
+        .trim()
+        .parse()
+        .with_context(|| format!("Invalid value in {}", path.display()))
+}
+
+/// An ambient light sensor, read through the IIO sysfs interface.
+#[derive(Debug)]
+pub(crate) struct AmbientLight {
+    raw_path: PathBuf,
+    /// Multiplies the raw value into lux
+    scale: f64,
+    /// The level that was picked last
+    last: Option<Brightness>,
+}
+
+impl AmbientLight {
+    /// Find the first IIO device that measures illuminance.
+    pub(crate) fn find() -> Result<Self> {
+        Self::find_in(Path::new(SYSFS_IIO_DEVICES))
+    }
+
+    fn find_in(devices_dir: &Path) -> Result<Self> {
+        let entries = std::fs::read_dir(devices_dir)
+            .with_context(|| format!("Could not list {}", devices_dir.display()))?;
+        let mut dirs: Vec<_> = entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.join("in_illuminance_raw").exists())
+            .collect();
+        // the order of read_dir isn't stable
+        dirs.sort();
+        let dir = dirs
+            .into_iter()
+            .next()
+            .ok_or_else(|| anyhow!("No ambient light sensor in {}", devices_dir.display()))?;
+        // not all drivers have a scale, their raw value is in lux
+        let scale_path = dir.join("in_illuminance_scale");
+        let scale = if scale_path.exists() {
+            read_value(&scale_path)?
+        } else {
+            1.0
+        };
+        Ok(Self {
+            raw_path: dir.join("in_illuminance_raw"),
+            scale,
+            last: None,
+        })
+    }
+
+    /// Read the illuminance in lux.
+    pub(crate) fn lux(&self) -> Result<f64> {
+        Ok(read_value(&self.raw_path)? * self.scale)
+    }
+
+    /// Read the sensor, returning the level for it from `thresholds` if it
+    /// is different from the last time.
+    pub(crate) fn changed(&mut self, thresholds: &[LuxThreshold]) -> Result<Option<Brightness>> {
+        let level = level_for(self.lux()?, thresholds);
+        if level.is_none() || level == self.last {
+            return Ok(None);
+        }
+        self.last = level;
+        Ok(level)
+    }
+}
+
+#[test]
+fn test_level_for() {
+    let thresholds = [
+        LuxThreshold {
+            lux: 300,
+            brightness: Brightness::Full,
+        },
+        LuxThreshold {
+            lux: 10,
+            brightness: Brightness::Low,
+        },
+        LuxThreshold {
+            lux: 50,
+            brightness: Brightness::Half,
+        },
+    ];
+    assert_eq!(level_for(0.0, &thresholds), Some(Brightness::Low));
+    assert_eq!(level_for(10.0, &thresholds), Some(Brightness::Low));
+    assert_eq!(level_for(299.9, &thresholds), Some(Brightness::Half));
+    assert_eq!(level_for(5000.0, &thresholds), Some(Brightness::Full));
+    assert_eq!(level_for(100.0, &[]), None);
+}
+
+#[test]
+fn test_ambient_light() {
+    let dir = TempDir::new("iio");
+    let devices = dir.path();
+    assert!(AmbientLight::find_in(devices).is_err());
+    std::fs::create_dir_all(devices.join("iio:device0")).unwrap();
+    let sensor = devices.join("iio:device1");
+    std::fs::create_dir_all(&sensor).unwrap();
+    std::fs::write(sensor.join("in_illuminance_raw"), "200\n").unwrap();
+    std::fs::write(sensor.join("in_illuminance_scale"), "0.5\n").unwrap();
+
+    let thresholds = [
+        LuxThreshold {
+            lux: 0,
+            brightness: Brightness::Low,
+        },
+        LuxThreshold {
+            lux: 100,
+            brightness: Brightness::Full,
+        },
+    ];
+    let mut als = AmbientLight::find_in(devices).unwrap();
+    assert_eq!(als.lux().unwrap(), 100.0);
+    assert_eq!(als.changed(&thresholds).unwrap(), Some(Brightness::Full));
+    std::fs::write(sensor.join("in_illuminance_raw"), "220\n").unwrap();
+    assert_eq!(als.changed(&thresholds).unwrap(), None);
+    std::fs::write(sensor.join("in_illuminance_raw"), "20\n").unwrap();
+    assert_eq!(als.changed(&thresholds).unwrap(), Some(Brightness::Low));
+}
@@ -0,0 +1,256 @@
+use std::io::{BufRead, ErrorKind};
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+
+use anyhow::{Context, Result, anyhow};
+use evdev_rs::{
+    Device, GrabMode, ReadFlag,
+    enums::{EV_ABS, EV_KEY, EventCode},
+};
+use serde::Serialize;
+
+use crate::Point;
+use crate::devices::get_touchpad_bbox;
+use crate::numpad_layout::{BBox, CustomLayout, NumpadLayout, SupportedLayout, parse_key};
+
+/// The corners of the grid, in the order that they are touched.
+const CORNERS: [&str; 4] = ["top left", "top right", "bottom right", "bottom left"];
+
+/// The bbox of the grid, from touches on the corners in [`CORNERS`].
+fn grid_bbox(corners: &[Point; 4]) -> Result<BBox> {
+    let [top_left, top_right, bottom_right, bottom_left] = corners;
+    if top_left.x >= top_right.x
+        || bottom_left.x >= bottom_right.x
+        || top_left.y >= bottom_left.y
+        || top_right.y >= bottom_right.y
+    {
+        return Err(anyhow!(
+            "The corners are out of order, the left ones should be left of the right ones \
+            and the top ones above the bottom ones"
+        ));
+    }
+    Ok(BBox::new(
+        (top_left.x + bottom_left.x) / 2,
+        (top_right.x + bottom_right.x) / 2,
+        (top_left.y + top_right.y) / 2,
+        (bottom_left.y + bottom_right.y) / 2,
+    ))
+}
+
+/// Check that a touch on the key at `cell` landed on it in the grid of `layout`.
+///
+/// With the corners in order, this also keeps the keys in order.
+fn check_key_touch(layout: &NumpadLayout, pos: Point, cell: (usize, usize)) -> Result<()> {
+    match layout.get_cell(pos) {
+        Some(touched) if touched == cell => Ok(()),
+        Some((row, col)) => Err(anyhow!(
+            "That is in row {}, column {} of the grid",
+            row + 1,
+            col + 1
+        )),
+        None => Err(anyhow!("That is outside of the grid")),
+    }
+}
+
+/// The `[layout.custom]` table of `layout`, to paste into the config.
+fn layout_snippet(layout: CustomLayout) -> Result<String> {
+    #[derive(Serialize)]
+    struct Snippet {
+        layout: SupportedLayout,
+    }
+    toml::to_string(&Snippet {
+        layout: SupportedLayout::Custom(layout),
+    })
+    .context("Could not write the layout")
+}
+
+/// Wait for a finger to touch the touchpad and lift, returning where it was
+/// lifted. Only the first finger counts.
+fn read_touch(touchpad: &Device) -> Result<Point> {
+    // drop what came before the prompt
+    while touchpad.next_event(ReadFlag::NORMAL).is_ok() {}
+    let mut pos = Point::default();
+    let mut slot = 0;
+    let mut touching = false;
+    loop {
+        let mut fd = libc::pollfd {
+            fd: touchpad.file().as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        if unsafe { libc::poll(&mut fd, 1, -1) } == -1 {
+            let err = std::io::Error::last_os_error();
+            if err.kind() != ErrorKind::Interrupted {
+                return Err(err).context("Failed to poll the touchpad");
+            }
+            continue;
+        }
+        while let Ok((_, ev)) = touchpad.next_event(ReadFlag::NORMAL) {
+            match ev.event_code {
+                EventCode::EV_ABS(EV_ABS::ABS_MT_SLOT) => slot = ev.value,
+                EventCode::EV_ABS(EV_ABS::ABS_MT_POSITION_X) if slot == 0 => pos.x = ev.value,
+                EventCode::EV_ABS(EV_ABS::ABS_MT_POSITION_Y) if slot == 0 => pos.y = ev.value,
+                EventCode::EV_KEY(EV_KEY::BTN_TOUCH) if ev.value != 0 => touching = true,
+                EventCode::EV_KEY(EV_KEY::BTN_TOUCH) if touching => return Ok(pos),
+                _ => (),
+            }
+        }
+    }
+}
+
+/// Ask for the key of a cell until a valid one is given. An empty line keeps
+/// `default`, and `-` leaves the cell without a key.
+fn read_key(
+    lines: &mut impl Iterator<Item = std::io::Result<String>>,
+    default: Option<EV_KEY>,
+) -> Result<Option<EV_KEY>> {
+    loop {
+        match default {
+            Some(key) => eprint!("Key [{:?}]: ", key),
+            None => eprint!("Key [none]: "),
+        }
+        let line = lines
+            .next()
+            .ok_or_else(|| anyhow!("No more input"))?
+            .context("Could not read the key")?;
+        match line.trim() {
+            "" => return Ok(default),
+            "-" => return Ok(None),
+            name => match parse_key(name) {
+                Some(key) => return Ok(Some(key)),
+                None => eprintln!("Unknown key {:?}, e.g. KEY_KP7 or KP7", name),
+            },
+        }
+    }
+}
+
+/// Record touches on the corners and keys of the printed grid, and write
+/// the custom layout they make to `output`, or stdout.
+///
+/// `rows` and `columns` default to those of `base`, whose keys are offered
+/// for every cell when the grids match.
+pub(crate) fn calibrate(
+    mut touchpad: Device,
+    base: SupportedLayout,
+    rows: Option<usize>,
+    columns: Option<usize>,
+    output: Option<&Path>,
+) -> Result<()> {
+    let touchpad_bbox = get_touchpad_bbox(&touchpad)?;
+    let base_layout =
+        NumpadLayout::from_supported_layout(&base, touchpad_bbox.clone(), Default::default())?;
+    let base_keys = base_layout.keys();
+    let rows = rows.unwrap_or(base_keys.len());
+    let columns = columns.unwrap_or(base_keys[0].len());
+    if rows == 0 || columns == 0 {
+        return Err(anyhow!("The grid needs at least one row and column"));
+    }
+    let default_keys = if base_keys.len() == rows && base_keys[0].len() == columns {
+        base_keys.clone()
+    } else {
+        vec![vec![None; columns]; rows]
+    };
+    // keep the touches from moving the pointer
+    touchpad
+        .grab(GrabMode::Grab)
+        .context("Could not grab the touchpad")?;
+
+    eprintln!(
+        "Touch the outer corners of the {}x{} grid of keys, with one finger.",
+        rows, columns
+    );
+    let margins = loop {
+        let mut corners = [Point::default(); 4];
+        for (corner, name) in corners.iter_mut().zip(CORNERS) {
+            eprintln!("Touch the {} corner", name);
+            *corner = read_touch(&touchpad)?;
+        }
+        match grid_bbox(&corners) {
+            Ok(bbox) => break bbox.margins_within(&touchpad_bbox),
+            Err(err) => eprintln!("{}. Starting over.", err),
+        }
+    };
+
+    let empty = CustomLayout::new(base.clone(), vec![vec![None; columns]; rows], Some(margins));
+    let layout = NumpadLayout::from_supported_layout(
+        &SupportedLayout::Custom(empty),
+        touchpad_bbox,
+        Default::default(),
+    )?;
+    eprintln!("Now touch the middle of each key, and name it.");
+    eprintln!("Press enter to keep the key in brackets, or type - for none.");
+    let mut lines = std::io::stdin().lock().lines();
+    let mut keys = vec![vec![None; columns]; rows];
+    for (row, row_keys) in keys.iter_mut().enumerate() {
+        for (col, key) in row_keys.iter_mut().enumerate() {
+            loop {
+                eprintln!("Touch the key in row {}, column {}", row + 1, col + 1);
+                let pos = read_touch(&touchpad)?;
+                match check_key_touch(&layout, pos, (row, col)) {
+                    Ok(()) => break,
+                    Err(err) => eprintln!("{}. Try again.", err),
+                }
+            }
+            *key = read_key(&mut lines, default_keys[row][col])?;
+        }
+    }
+
+    let snippet = layout_snippet(CustomLayout::new(base, keys, Some(margins)))?;
+    match output {
+        Some(path) => {
+            std::fs::write(path, snippet)
+                .with_context(|| format!("Could not write {}", path.display()))?;
+            eprintln!("Wrote the layout to {}", path.display());
+        }
+        None => print!("{}", snippet),
+    }
+    Ok(())
+}
+
+#[test]
+fn test_grid_bbox() {
+    let point = |x, y| Point { x, y };
+    let corners = [
+        point(100, 110),
+        point(900, 90),
+        point(910, 700),
+        point(90, 700),
+    ];
+    let bbox = grid_bbox(&corners).unwrap();
+    let margins = bbox.margins_within(&BBox::new(0, 1000, 0, 1000));
+    let expected: crate::numpad_layout::CustomMargins =
+        toml::from_str("top = 10.0\nbottom = 30.0\nleft = 9.5\nright = 9.5").unwrap();
+    assert_eq!(margins, expected);
+
+    // swapped right corners
+    assert!(grid_bbox(&[corners[0], corners[2], corners[1], corners[3]]).is_err());
+}
+
+#[test]
+fn test_calibrated_layout() {
+    use EV_KEY::*;
+    let point = |x, y| Point { x, y };
+    let touchpad_bbox = BBox::new(0, 1000, 0, 1000);
+    let margins = BBox::new(100, 900, 200, 800).margins_within(&touchpad_bbox);
+    let keys = vec![
+        vec![Some(KEY_KP1), None],
+        vec![Some(KEY_LEFT), Some(KEY_KP2)],
+    ];
+    let custom = CustomLayout::new(SupportedLayout::GX701, keys, Some(margins));
+    let layout = NumpadLayout::from_supported_layout(
+        &SupportedLayout::Custom(custom.clone()),
+        touchpad_bbox,
+        Default::default(),
+    )
+    .unwrap();
+    assert!(check_key_touch(&layout, point(300, 300), (0, 0)).is_ok());
+    assert!(check_key_touch(&layout, point(300, 300), (0, 1)).is_err());
+    assert!(check_key_touch(&layout, point(50, 300), (0, 0)).is_err());
+    assert_eq!(layout.get_key(point(700, 700)), Some(KEY_KP2));
+
+    // ready for the config
+    let snippet = layout_snippet(custom.clone()).unwrap();
+    assert!(snippet.starts_with("[layout.custom]\n"), "{}", snippet);
+    let config: crate::config::Config = toml::from_str(&snippet).unwrap();
+    assert_eq!(config.layout(), &SupportedLayout::Custom(custom));
+}
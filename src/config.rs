@@ -1,9 +1,32 @@
+use std::ffi::OsStr;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{Context, Result, anyhow};
 use evdev_rs::enums::EV_KEY;
-use serde::Deserialize;
+use log::{debug, info};
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer, Serialize};
+
+use crate::als::LuxThreshold;
+use crate::control::default_socket_path;
+use crate::devices::read_model_names;
+use crate::numpad_layout::{ActivationCorner, KeyRemap, KeyStyle, SupportedLayout, parse_key};
+use crate::status_file::default_status_path;
+#[cfg(test)]
+use crate::testing::TempDir;
+use crate::touchpad_i2c::{
+    Brightness, BrightnessProtocol, DEFAULT_RETRY_ATTEMPTS, DEFAULT_RETRY_DELAY,
+};
+use crate::util::CustomDuration;
 
-use crate::numpad_layout::SupportedLayout;
+const CONFIG_FILE_NAME: &str = "asus_numpad.toml";
+/// Alternative to [`CONFIG_FILE_NAME`], in our own dir
+const CONFIG_DIR_FILE_NAME: &str = "asus-numpad/config.toml";
 
-#[derive(Debug, PartialEq, Eq, Hash, Deserialize)]
+#[derive(Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
 #[serde(rename_all = "snake_case", untagged)]
 pub(crate) enum CustomCommand {
     /// Press these keys
@@ -19,37 +42,572 @@ impl Default for CustomCommand {
     }
 }
 
-#[derive(Debug, PartialEq, Eq, Hash, Deserialize)]
+/// Options given on the command line, which take precedence over the config file.
+#[derive(Debug, Default, Clone)]
+pub(crate) struct Overrides {
+    pub(crate) layout: Option<SupportedLayout>,
+    pub(crate) toggle_hold_ms: Option<u64>,
+    pub(crate) toggle_corner_size_percent: Option<u32>,
+    pub(crate) fifo_path: Option<PathBuf>,
+    /// Whether the numpad starts on, over the saved state and `enable_on_start`
+    pub(crate) enabled: Option<bool>,
+    /// Print the keys and the messages to the touchpad instead of sending them
+    pub(crate) dry_run: bool,
+    /// The bus of the touchpad, over the one in `/proc` and `i2c_id`
+    pub(crate) i2c_id: Option<u32>,
+    pub(crate) touchpad_device: Option<PathBuf>,
+    /// The brightness to start at, over the saved one and `brightness`
+    pub(crate) brightness: Option<Brightness>,
+    /// Follow the keyboard backlight, even if `sync_kbd_backlight` is off
+    pub(crate) sync_kbd_backlight: bool,
+    /// The config file to read instead of looking for one
+    pub(crate) config_path: Option<PathBuf>,
+}
+
+/// What is held to toggle the numpad.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum ActivationMode {
+    /// One finger on the numlock key
+    #[default]
+    SingleCorner,
+    /// A finger on each of the numlock and calc keys at once, so that a
+    /// single finger never toggles it
+    BothCorners,
+}
+
+/// Which way the numpad is kept in sync with the system NumLock.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum NumlockSync {
+    #[default]
+    Both,
+    /// The numpad follows the NumLock LED, e.g. when set by another keyboard
+    FromSystem,
+    /// Toggling the numpad presses NumLock
+    ToSystem,
+    Off,
+}
+
+impl NumlockSync {
+    pub(crate) fn follows_system(self) -> bool {
+        matches!(self, Self::Both | Self::FromSystem)
+    }
+
+    pub(crate) fn drives_system(self) -> bool {
+        matches!(self, Self::Both | Self::ToSystem)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
 pub(crate) struct Config {
     layout: SupportedLayout,
 
+    #[serde(default)]
+    activation_corner: ActivationCorner,
+
+    #[serde(default)]
+    key_style: KeyStyle,
+
+    #[serde(default)]
+    remap: KeyRemap,
+
+    #[serde(default, deserialize_with = "deserialize_key_sequence")]
+    percent_keys: Option<Vec<EV_KEY>>,
+
+    #[serde(default, deserialize_with = "deserialize_key_sequence")]
+    equals_keys: Option<Vec<EV_KEY>>,
+
     #[serde(default = "default_numlock")]
     disable_numlock_on_start: bool,
 
+    #[serde(default)]
+    numlock_sync: NumlockSync,
+
     #[serde(default)]
     calc_start_command: CustomCommand,
 
     calc_stop_command: Option<CustomCommand>,
 
+    #[serde(default)]
+    calc_on_hold: bool,
+
+    #[serde(default)]
+    disable_calc_key: bool,
+
+    #[serde(default)]
+    lock_activation: bool,
+
     #[serde(default)]
     disable_numpad: bool,
+
+    #[serde(default)]
+    brightness: Brightness,
+
+    #[serde(default = "default_persist_brightness")]
+    persist_brightness: bool,
+
+    #[serde(default = "default_persist_enabled")]
+    persist_enabled: bool,
+
+    #[serde(default)]
+    fade: bool,
+
+    #[serde(default = "default_fade_duration_ms")]
+    fade_duration_ms: u64,
+
+    #[serde(default)]
+    enable_on_start: bool,
+
+    #[serde(default = "default_hold_duration_ms")]
+    hold_duration_ms: u64,
+
+    toggle_hold_ms: Option<u64>,
+
+    #[serde(
+        default = "default_toggle_corner_size_percent",
+        deserialize_with = "deserialize_toggle_corner_size_percent"
+    )]
+    toggle_corner_size_percent: u32,
+
+    #[serde(default)]
+    toggle_on_tap: bool,
+
+    #[serde(default)]
+    activation_mode: ActivationMode,
+
+    #[serde(default = "default_one_finger_brightness")]
+    one_finger_brightness: bool,
+
+    #[serde(default = "default_toggle_grace_ms")]
+    toggle_grace_ms: u64,
+
+    #[serde(default = "default_debounce_ms")]
+    debounce_ms: u64,
+
+    #[serde(
+        default = "default_drag_tolerance_percent",
+        deserialize_with = "deserialize_drag_tolerance_percent"
+    )]
+    drag_tolerance_percent: u32,
+
+    #[serde(default)]
+    key_gap_percent: u32,
+
+    #[serde(default)]
+    swipe_cancel_percent: u32,
+
+    #[serde(default = "default_palm_size_percent")]
+    palm_size_percent: u32,
+
+    #[serde(default)]
+    palm_pressure: u32,
+
+    #[serde(default)]
+    typing_timeout_ms: u64,
+
+    #[serde(default = "default_repeat_delay_ms")]
+    repeat_delay_ms: u64,
+
+    #[serde(default = "default_repeat_rate_ms")]
+    repeat_rate_ms: u64,
+
+    #[serde(default)]
+    disable_pointer: bool,
+
+    #[serde(default)]
+    pointer_passthrough: bool,
+
+    #[serde(default)]
+    disable_session_pointer: bool,
+
+    #[serde(default)]
+    auto_disable_secs: u64,
+
+    #[serde(default)]
+    brightness_slider: bool,
+
+    #[serde(default = "default_slider_height")]
+    slider_height: u32,
+
+    #[serde(default)]
+    sync_kbd_backlight: bool,
+
+    #[serde(default = "default_kbd_backlight_poll_ms")]
+    kbd_backlight_poll_ms: u64,
+
+    #[serde(default)]
+    als_brightness: bool,
+
+    #[serde(default = "default_als_poll_ms")]
+    als_poll_ms: u64,
+
+    #[serde(default = "default_als_thresholds")]
+    als_thresholds: Vec<LuxThreshold>,
+
+    battery_brightness: Option<Brightness>,
+
+    ac_brightness: Option<Brightness>,
+
+    #[serde(default = "default_power_poll_ms")]
+    power_poll_ms: u64,
+
+    #[serde(default = "default_retry_attempts")]
+    retry_attempts: u32,
+
+    #[serde(default = "default_retry_delay_ms")]
+    retry_delay_ms: u64,
+
+    #[serde(default)]
+    i2c_timeout_ms: u64,
+
+    brightness_protocol: Option<BrightnessProtocol>,
+
+    #[serde(default = "default_reconnect_attempts")]
+    reconnect_attempts: u32,
+
+    i2c_id: Option<u32>,
+
+    touchpad_device: Option<PathBuf>,
+
+    keyboard_device: Option<PathBuf>,
+
+    socket_path: Option<PathBuf>,
+
+    fifo_path: Option<PathBuf>,
+
+    status_path: Option<PathBuf>,
+
+    #[serde(default = "default_socket_mode")]
+    socket_mode: u32,
+
+    socket_group: Option<String>,
 }
 
 fn default_numlock() -> bool {
     true
 }
 
+fn default_persist_brightness() -> bool {
+    true
+}
+
+fn default_persist_enabled() -> bool {
+    true
+}
+
+fn default_one_finger_brightness() -> bool {
+    true
+}
+
+fn default_fade_duration_ms() -> u64 {
+    150
+}
+
+fn default_hold_duration_ms() -> u64 {
+    250
+}
+
+fn default_repeat_delay_ms() -> u64 {
+    500
+}
+
+fn default_repeat_rate_ms() -> u64 {
+    33
+}
+
+fn default_toggle_grace_ms() -> u64 {
+    300
+}
+
+fn default_debounce_ms() -> u64 {
+    200
+}
+
+fn default_toggle_corner_size_percent() -> u32 {
+    100
+}
+
+/// The numlock bbox can't get too small to hit, or bigger than the touchpad.
+fn deserialize_toggle_corner_size_percent<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<u32, D::Error> {
+    let percent = u32::deserialize(deserializer)?;
+    if !(10..=1000).contains(&percent) {
+        return Err(D::Error::custom(format!(
+            "toggle_corner_size_percent {} is out of range (10-1000)",
+            percent
+        )));
+    }
+    Ok(percent)
+}
+
+fn default_palm_size_percent() -> u32 {
+    150
+}
+
+fn default_drag_tolerance_percent() -> u32 {
+    20
+}
+
+/// Any more than a key and the finger would end up on a key that isn't next
+/// to the one it started on.
+fn deserialize_drag_tolerance_percent<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<u32, D::Error> {
+    let percent = u32::deserialize(deserializer)?;
+    if percent > 100 {
+        return Err(D::Error::custom(format!(
+            "drag_tolerance_percent {} is out of range (0-100)",
+            percent
+        )));
+    }
+    Ok(percent)
+}
+
+/// A non-empty list of key names, like `["LEFTSHIFT", "KEY_5"]`.
+fn deserialize_key_sequence<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Vec<EV_KEY>>, D::Error> {
+    const FORMAT: &str =
+        "Use a list of evdev key names, modifiers first, like [\"KEY_LEFTSHIFT\", \"KEY_5\"]";
+    let names = Vec::<String>::deserialize(deserializer)?;
+    if names.is_empty() {
+        return Err(D::Error::custom(format!(
+            "The list of keys is empty. {}",
+            FORMAT
+        )));
+    }
+    names
+        .iter()
+        .map(|name| {
+            parse_key(name)
+                .ok_or_else(|| D::Error::custom(format!("Unknown key {:?}. {}", name, FORMAT)))
+        })
+        .collect::<Result<_, _>>()
+        .map(Some)
+}
+
+fn default_retry_attempts() -> u32 {
+    DEFAULT_RETRY_ATTEMPTS
+}
+
+fn default_retry_delay_ms() -> u64 {
+    DEFAULT_RETRY_DELAY.as_millis() as u64
+}
+
+/// Pick the layout from the DMI model names, for configs without one.
+fn detect_layout() -> Result<SupportedLayout> {
+    let models = read_model_names().context("Could not detect the layout")?;
+    let layout = SupportedLayout::detect(&models)?;
+    info!(
+        "Detected layout {} for {}",
+        layout.name(),
+        models.join(" / ")
+    );
+    Ok(layout)
+}
+
+fn default_slider_height() -> u32 {
+    100
+}
+
+fn default_als_poll_ms() -> u64 {
+    1000
+}
+
+fn default_power_poll_ms() -> u64 {
+    2000
+}
+
+fn default_als_thresholds() -> Vec<LuxThreshold> {
+    [
+        (0, Brightness::Low),
+        (50, Brightness::Half),
+        (300, Brightness::Full),
+    ]
+    .into_iter()
+    .map(|(lux, brightness)| LuxThreshold { lux, brightness })
+    .collect()
+}
+
+fn default_kbd_backlight_poll_ms() -> u64 {
+    500
+}
+
+fn default_reconnect_attempts() -> u32 {
+    30
+}
+
+fn default_socket_mode() -> u32 {
+    0o660
+}
+
+/// Find the config file according to the XDG Base Dir Spec.
+///
+/// Looks in `$XDG_CONFIG_HOME`, followed by `$XDG_CONFIG_DIRS` (`/etc/xdg` by default).
+/// In each dir, `asus_numpad.toml` is preferred over `asus-numpad/config.toml`.
+fn find_config_file() -> Option<PathBuf> {
+    let non_empty = |var| std::env::var_os(var).filter(|val| !val.is_empty());
+    let config_home = non_empty("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| non_empty("HOME").map(|home| PathBuf::from(home).join(".config")));
+    let config_dirs = non_empty("XDG_CONFIG_DIRS").unwrap_or_else(|| "/etc/xdg".into());
+    config_home
+        .into_iter()
+        .chain(std::env::split_paths(&config_dirs))
+        .flat_map(|dir| [dir.join(CONFIG_FILE_NAME), dir.join(CONFIG_DIR_FILE_NAME)])
+        .find(|path| path.is_file())
+}
+
 impl Config {
+    /// Path of the config file that is in use, if any: the one given in
+    /// `overrides`, or else the one found in the XDG dirs.
+    pub(crate) fn path(overrides: &Overrides) -> Option<PathBuf> {
+        overrides.config_path.clone().or_else(find_config_file)
+    }
+
+    /// Read the config file, if one exists, with `overrides` instead of what
+    /// is in it.
+    ///
+    /// Missing options take their default values, but unknown or invalid ones are an error.
+    pub(crate) fn load(overrides: &Overrides) -> Result<Self> {
+        let data = match Self::path(overrides) {
+            Some(path) => {
+                debug!("Reading config from {}", path.display());
+                std::fs::read_to_string(&path)
+                    .with_context(|| format!("Could not read config file {}", path.display()))?
+            }
+            None => {
+                debug!("No config file found, using defaults");
+                String::new()
+            }
+        };
+        Self::parse(&data, overrides, detect_layout)
+    }
+
+    /// Parse `data` with `overrides`, using the layout from `detect` if
+    /// neither has one.
+    fn parse(
+        data: &str,
+        overrides: &Overrides,
+        detect: fn() -> Result<SupportedLayout>,
+    ) -> Result<Self> {
+        let mut table: toml::Table = toml::from_str(data).context("Invalid config")?;
+        let file_has_layout = table.contains_key("layout");
+        let layout = match &overrides.layout {
+            Some(layout) => Some(layout.clone()),
+            None if !table.contains_key("layout") => Some(detect()?),
+            None => None,
+        };
+        if let Some(layout) = layout {
+            table.insert("layout".to_owned(), layout.name().into());
+        }
+        if let Some(toggle_hold_ms) = overrides.toggle_hold_ms {
+            table.insert(
+                "toggle_hold_ms".to_owned(),
+                toml::Value::from(toggle_hold_ms as i64),
+            );
+        }
+        if let Some(percent) = overrides.toggle_corner_size_percent {
+            table.insert(
+                "toggle_corner_size_percent".to_owned(),
+                toml::Value::from(percent as i64),
+            );
+        }
+        if let Some(path) = &overrides.fifo_path {
+            table.insert(
+                "fifo_path".to_owned(),
+                toml::Value::from(path.to_string_lossy().into_owned()),
+            );
+        }
+        if let Some(i2c_id) = overrides.i2c_id {
+            table.insert("i2c_id".to_owned(), toml::Value::from(i2c_id as i64));
+        }
+        if let Some(brightness) = overrides.brightness {
+            table.insert(
+                "brightness".to_owned(),
+                toml::Value::try_from(brightness).context("Invalid brightness")?,
+            );
+        }
+        if overrides.sync_kbd_backlight {
+            table.insert("sync_kbd_backlight".to_owned(), toml::Value::from(true));
+        }
+        if let Some(path) = &overrides.touchpad_device {
+            table.insert(
+                "touchpad_device".to_owned(),
+                toml::Value::from(path.to_string_lossy().into_owned()),
+            );
+        }
+        if overrides.dry_run {
+            // these create devices or write files of their own
+            for option in [
+                "pointer_passthrough",
+                "disable_session_pointer",
+                "persist_brightness",
+                "persist_enabled",
+            ] {
+                table.insert(option.to_owned(), toml::Value::from(false));
+            }
+        }
+        table
+            .try_into()
+            .map_err(|err| {
+                // the table has lost the lines, so report the error in the
+                // file itself if there is one, which says where it is
+                match toml::from_str::<Self>(data) {
+                    Err(file_err)
+                        if file_has_layout || file_err.message() != "missing field `layout`" =>
+                    {
+                        file_err
+                    }
+                    _ => err,
+                }
+            })
+            .context("Invalid config")
+    }
+
     /// Get a reference to the config's layout.
     pub(crate) fn layout(&self) -> &SupportedLayout {
         &self.layout
     }
 
+    /// The corner of the touchpad that toggles the numpad.
+    pub(crate) fn activation_corner(&self) -> ActivationCorner {
+        self.activation_corner
+    }
+
+    /// Which keycodes the numpad types.
+    pub(crate) fn key_style(&self) -> KeyStyle {
+        self.key_style
+    }
+
+    /// The keys to put in place of the layout's.
+    pub(crate) fn remap(&self) -> &KeyRemap {
+        &self.remap
+    }
+
+    /// The keys typed for the `%` key, if not Shift+5.
+    pub(crate) fn percent_keys(&self) -> Option<&[EV_KEY]> {
+        self.percent_keys.as_deref()
+    }
+
+    /// The keys typed for the `=` key, if not the keypad one.
+    pub(crate) fn equals_keys(&self) -> Option<&[EV_KEY]> {
+        self.equals_keys.as_deref()
+    }
+
     /// Get a reference to the config's disable numlock on start.
     pub(crate) fn disable_numlock_on_start(&self) -> bool {
         self.disable_numlock_on_start
     }
 
+    /// Which way the numpad follows or drives the system NumLock.
+    pub(crate) fn numlock_sync(&self) -> NumlockSync {
+        self.numlock_sync
+    }
+
     /// Get a reference to the config's calc start command.
     pub(crate) fn calc_start_command(&self) -> &CustomCommand {
         &self.calc_start_command
@@ -60,7 +618,767 @@ impl Config {
         self.calc_stop_command.as_ref()
     }
 
+    /// Whether holding the calc key runs the calc commands, instead of cycling
+    /// the brightness.
+    pub(crate) fn calc_on_hold(&self) -> bool {
+        self.calc_on_hold
+    }
+
+    pub(crate) fn disable_calc_key(&self) -> bool {
+        self.disable_calc_key
+    }
+
+    /// Whether touches start out unable to toggle the numpad or change its brightness.
+    pub(crate) fn lock_activation(&self) -> bool {
+        self.lock_activation
+    }
+
+    /// The keys pressed by the calc commands, which the dummy keyboard must support.
+    pub(crate) fn calc_keys(&self) -> Vec<EV_KEY> {
+        [
+            Some(&self.calc_start_command),
+            self.calc_stop_command.as_ref(),
+        ]
+        .into_iter()
+        .flatten()
+        .flat_map(|command| match command {
+            CustomCommand::Keys(keys) => keys.as_slice(),
+            CustomCommand::Command { .. } => &[],
+        })
+        .copied()
+        .collect()
+    }
+
     pub(crate) fn disable_numpad(&self) -> bool {
         self.disable_numpad
     }
+
+    /// Get the config's initial brightness.
+    pub(crate) fn brightness(&self) -> Brightness {
+        self.brightness
+    }
+
+    /// Whether the last brightness should be restored on start, instead of `brightness`.
+    pub(crate) fn persist_brightness(&self) -> bool {
+        self.persist_brightness
+    }
+
+    /// Whether the numpad should be turned back on at start if it was on when exiting.
+    pub(crate) fn persist_enabled(&self) -> bool {
+        self.persist_enabled
+    }
+
+    /// How long brightness changes should fade for, if enabled.
+    pub(crate) fn fade_duration(&self) -> Option<Duration> {
+        self.fade
+            .then(|| Duration::from_millis(self.fade_duration_ms))
+    }
+
+    /// Whether the numpad should be turned on when starting up.
+    pub(crate) fn enable_on_start(&self) -> bool {
+        self.enable_on_start
+    }
+
+    /// How long the numlock/calc keys need to be held to activate them.
+    pub(crate) fn hold_duration(&self) -> CustomDuration {
+        CustomDuration::from_millis(self.hold_duration_ms)
+    }
+
+    /// How long the numlock bbox needs to be held to toggle the numpad.
+    pub(crate) fn toggle_hold_duration(&self) -> CustomDuration {
+        CustomDuration::from_millis(self.toggle_hold_ms.unwrap_or(self.hold_duration_ms))
+    }
+
+    /// Size of the numlock bbox, in percent of the layout's own.
+    pub(crate) fn toggle_corner_size_percent(&self) -> u32 {
+        self.toggle_corner_size_percent
+    }
+
+    /// Whether a quick tap on the numlock bbox toggles the numpad, besides holding it.
+    pub(crate) fn toggle_on_tap(&self) -> bool {
+        self.toggle_on_tap
+    }
+
+    /// Whether one finger or a finger on each top corner toggles the numpad.
+    pub(crate) fn activation_mode(&self) -> ActivationMode {
+        self.activation_mode
+    }
+
+    /// Whether a single finger on a corner changes the brightness, which it
+    /// always does when it is also what toggles the numpad.
+    pub(crate) fn one_finger_brightness(&self) -> bool {
+        self.one_finger_brightness || self.activation_mode == ActivationMode::SingleCorner
+    }
+
+    /// How long touches are ignored for after the numpad is toggled.
+    pub(crate) fn toggle_grace_duration(&self) -> Duration {
+        Duration::from_millis(self.toggle_grace_ms)
+    }
+
+    /// How long after a corner gesture another one is taken for a bounce, if at all.
+    pub(crate) fn debounce_duration(&self) -> Option<Duration> {
+        (self.debounce_ms != 0).then(|| Duration::from_millis(self.debounce_ms))
+    }
+
+    /// How wide the borders between keys are where touches are ignored, in
+    /// percent of a key.
+    pub(crate) fn key_gap_percent(&self) -> u32 {
+        self.key_gap_percent
+    }
+
+    /// How far a finger can move from where it went down on a key, in percent
+    /// of a key, before the press ends.
+    pub(crate) fn drag_tolerance_percent(&self) -> u32 {
+        self.drag_tolerance_percent
+    }
+
+    /// How far a finger can travel in total, in percent of a key, before its
+    /// touch counts as a swipe that shouldn't type anything.
+    ///
+    /// `None` if keys are pressed as soon as they are touched.
+    pub(crate) fn swipe_cancel_percent(&self) -> Option<u32> {
+        (self.swipe_cancel_percent != 0).then_some(self.swipe_cancel_percent)
+    }
+
+    /// How long a contact can be, in percent of a key, before it is taken for
+    /// a palm.
+    ///
+    /// `None` if only the touchpad should tell palms apart.
+    pub(crate) fn palm_size_percent(&self) -> Option<u32> {
+        (self.palm_size_percent != 0).then_some(self.palm_size_percent)
+    }
+
+    /// How hard a contact can press, in the touchpad's units, before it is
+    /// taken for a palm, if at all.
+    pub(crate) fn palm_pressure(&self) -> Option<i32> {
+        (self.palm_pressure != 0).then_some(self.palm_pressure as i32)
+    }
+
+    /// How long the numpad ignores touches for after a key is pressed on the
+    /// keyboard, if at all.
+    pub(crate) fn typing_timeout(&self) -> Option<Duration> {
+        (self.typing_timeout_ms != 0).then(|| Duration::from_millis(self.typing_timeout_ms))
+    }
+
+    /// How long a key is held before it repeats, and the time between repeats.
+    ///
+    /// `None` if held keys shouldn't repeat, when either of them is `0`.
+    pub(crate) fn key_repeat(&self) -> Option<(CustomDuration, CustomDuration)> {
+        (self.repeat_delay_ms != 0 && self.repeat_rate_ms != 0).then(|| {
+            (
+                CustomDuration::from_millis(self.repeat_delay_ms),
+                CustomDuration::from_millis(self.repeat_rate_ms),
+            )
+        })
+    }
+
+    /// Whether the pointer should be frozen while the numpad is active.
+    pub(crate) fn disable_pointer(&self) -> bool {
+        self.disable_pointer
+    }
+
+    /// Whether touches outside of the keys should move the pointer through a
+    /// virtual touchpad while the numpad is active, instead of the real one.
+    pub(crate) fn pointer_passthrough(&self) -> bool {
+        self.pointer_passthrough
+    }
+
+    /// Whether the touchpad should also be disabled in the display server
+    /// while the numpad is active.
+    pub(crate) fn disable_session_pointer(&self) -> bool {
+        self.disable_session_pointer
+    }
+
+    /// How long the numpad can go unused before being turned off, if at all.
+    pub(crate) fn auto_disable_duration(&self) -> Option<Duration> {
+        (self.auto_disable_secs != 0).then(|| Duration::from_secs(self.auto_disable_secs))
+    }
+
+    /// Height of the brightness slider along the top of the touchpad, in
+    /// touchpad units, if it is enabled.
+    pub(crate) fn slider_height(&self) -> Option<i32> {
+        self.brightness_slider
+            .then(|| self.slider_height.min(i32::MAX as u32) as i32)
+    }
+
+    /// How often to check the keyboard backlight, if the numpad follows it.
+    pub(crate) fn kbd_backlight_poll_interval(&self) -> Option<Duration> {
+        self.sync_kbd_backlight
+            .then(|| Duration::from_millis(self.kbd_backlight_poll_ms))
+    }
+
+    /// How often to read the ambient light sensor, if the brightness follows it.
+    pub(crate) fn als_poll_interval(&self) -> Option<Duration> {
+        self.als_brightness
+            .then(|| Duration::from_millis(self.als_poll_ms))
+    }
+
+    /// The brightness to use for each ambient light level.
+    pub(crate) fn als_thresholds(&self) -> &[LuxThreshold] {
+        &self.als_thresholds
+    }
+
+    /// The brightness to switch to when the laptop is unplugged, if it should.
+    pub(crate) fn battery_brightness(&self) -> Option<Brightness> {
+        self.battery_brightness
+    }
+
+    /// The brightness to switch to when the laptop is plugged in again, or
+    /// `None` to go back to the one from before it was unplugged.
+    pub(crate) fn ac_brightness(&self) -> Option<Brightness> {
+        self.ac_brightness
+    }
+
+    /// How often to check whether the laptop is plugged in, if the brightness
+    /// depends on it.
+    pub(crate) fn power_poll_interval(&self) -> Option<Duration> {
+        self.battery_brightness
+            .map(|_| Duration::from_millis(self.power_poll_ms))
+    }
+
+    /// How many times an I2C write is tried before giving up.
+    pub(crate) fn retry_attempts(&self) -> u32 {
+        self.retry_attempts
+    }
+
+    /// How long to wait before retrying a failed I2C write. Doubles on each retry.
+    pub(crate) fn retry_delay(&self) -> Duration {
+        Duration::from_millis(self.retry_delay_ms)
+    }
+
+    /// The message that sets the brightness on this model.
+    pub(crate) fn brightness_protocol(&self) -> BrightnessProtocol {
+        self.brightness_protocol.unwrap_or_default()
+    }
+
+    /// How long an I2C transfer may take before it fails, instead of the
+    /// kernel default.
+    pub(crate) fn i2c_timeout(&self) -> Option<Duration> {
+        (self.i2c_timeout_ms != 0).then(|| Duration::from_millis(self.i2c_timeout_ms))
+    }
+
+    /// How many times to look for the touchpad after it disappears, a second apart.
+    pub(crate) fn reconnect_attempts(&self) -> u32 {
+        self.reconnect_attempts
+    }
+
+    /// The I2C bus of the touchpad, for when it can't be detected.
+    pub(crate) fn i2c_id(&self) -> Option<u32> {
+        self.i2c_id
+    }
+
+    /// The evdev of the touchpad, for when the wrong one is detected.
+    pub(crate) fn touchpad_device(&self) -> Option<&Path> {
+        self.touchpad_device.as_deref()
+    }
+
+    /// The evdev of the keyboard, for when the wrong one is detected.
+    pub(crate) fn keyboard_device(&self) -> Option<&Path> {
+        self.keyboard_device.as_deref()
+    }
+
+    /// Where to create a named pipe for control commands, if at all.
+    pub(crate) fn fifo_path(&self) -> Option<&Path> {
+        self.fifo_path.as_deref()
+    }
+
+    /// Where to keep the status of the numpad for status bars.
+    pub(crate) fn status_path(&self) -> PathBuf {
+        self.status_path.clone().unwrap_or_else(default_status_path)
+    }
+
+    /// Where to listen for control commands.
+    pub(crate) fn socket_path(&self) -> PathBuf {
+        self.socket_path.clone().unwrap_or_else(default_socket_path)
+    }
+
+    /// Permissions of the control socket.
+    pub(crate) fn socket_mode(&self) -> u32 {
+        self.socket_mode
+    }
+
+    /// Group that should own the control socket, if not our own.
+    pub(crate) fn socket_group(&self) -> Option<&str> {
+        self.socket_group.as_deref()
+    }
+}
+
+/// Watches the directory of the config file for changes with inotify.
+///
+/// The directory is watched instead of the file itself, since editors usually
+/// save by replacing the file.
+#[derive(Debug)]
+pub(crate) struct ConfigWatcher {
+    fd: OwnedFd,
+    file_name: PathBuf,
+}
+
+impl ConfigWatcher {
+    pub(crate) fn new(path: &Path) -> Result<Self> {
+        let dir = path.parent().unwrap_or_else(|| Path::new("."));
+        let file_name = path
+            .file_name()
+            .ok_or_else(|| anyhow!("Invalid config path {}", path.display()))?
+            .into();
+        let fd = unsafe { libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC) };
+        if fd < 0 {
+            return Err(std::io::Error::last_os_error()).context("Could not initialize inotify");
+        }
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+        let c_dir = std::ffi::CString::new(dir.as_os_str().as_bytes())?;
+        let wd = unsafe {
+            libc::inotify_add_watch(
+                fd.as_raw_fd(),
+                c_dir.as_ptr(),
+                libc::IN_CLOSE_WRITE | libc::IN_MOVED_TO,
+            )
+        };
+        if wd < 0 {
+            return Err(std::io::Error::last_os_error())
+                .with_context(|| format!("Could not watch config dir {}", dir.display()));
+        }
+        Ok(Self { fd, file_name })
+    }
+
+    /// Read all pending events, returning whether the config file was among them.
+    pub(crate) fn changed(&self) -> bool {
+        // aligned, since the events are read straight out of it
+        #[repr(align(8))]
+        struct Buf([u8; 4096]);
+        let mut buf = Buf([0; 4096]);
+        let header_len = std::mem::size_of::<libc::inotify_event>();
+        let mut changed = false;
+        loop {
+            let len =
+                unsafe { libc::read(self.fd.as_raw_fd(), buf.0.as_mut_ptr().cast(), buf.0.len()) };
+            if len <= 0 {
+                // EAGAIN, no more events
+                return changed;
+            }
+            let mut offset = 0;
+            while offset + header_len <= len as usize {
+                let event: libc::inotify_event =
+                    unsafe { std::ptr::read_unaligned(buf.0.as_ptr().add(offset).cast()) };
+                let name = &buf.0[offset + header_len..offset + header_len + event.len as usize];
+                // the name is padded with NULs
+                let name = name.split(|&b| b == 0).next().unwrap_or_default();
+                changed |= Path::new(OsStr::from_bytes(name)) == self.file_name;
+                offset += header_len + event.len as usize;
+            }
+        }
+    }
+}
+
+impl AsRawFd for ConfigWatcher {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd.as_raw_fd()
+    }
+}
+
+#[test]
+fn test_config_brightness() {
+    let parse = |brightness: &str| {
+        toml::from_str::<Config>(&format!(
+            "layout = \"UX433FA\"\nbrightness = {}",
+            brightness
+        ))
+        .map(|config| config.brightness())
+    };
+    assert_eq!(parse("\"half\"").unwrap(), Brightness::Half);
+    assert_eq!(parse("20").unwrap(), Brightness::Custom(20));
+    assert_eq!(parse("24").unwrap(), Brightness::Half);
+    // outside of what the firmware accepts
+    let err = parse("70").unwrap_err().to_string();
+    assert!(err.contains("out of range"), "{}", err);
+    assert!(parse("\"dim\"").is_err());
+}
+
+#[test]
+fn test_config_errors() {
+    // errors in the file say where they are, also with overrides applied
+    let parse = |data, overrides: &Overrides| {
+        let err = Config::parse(data, overrides, || Ok(SupportedLayout::UX433FA)).unwrap_err();
+        format!("{:#}", err)
+    };
+    let err = parse("layout = \"UX433FA\"\nbrightnes = 1", &Overrides::default());
+    assert!(err.contains("line 2"), "{}", err);
+    assert!(err.contains("unknown field `brightnes`"), "{}", err);
+    let overrides = Overrides {
+        toggle_hold_ms: Some(1000),
+        dry_run: true,
+        ..Default::default()
+    };
+    let err = parse(
+        "enable_on_start = true\nhold_duration_ms = \"abc\"",
+        &overrides,
+    );
+    assert!(err.contains("line 2"), "{}", err);
+    assert!(err.contains("invalid type"), "{}", err);
+    // and the ones in the overrides are still reported
+    let overrides = Overrides {
+        toggle_corner_size_percent: Some(5),
+        ..Default::default()
+    };
+    let err = parse("", &overrides);
+    assert!(err.contains("out of range"), "{}", err);
+
+    let err = toml::from_str::<Config>("layout = \"UX433FA\"\nbrightnes = 1")
+        .unwrap_err()
+        .to_string();
+    assert!(err.contains("line 2"), "{}", err);
+    assert!(err.contains("unknown field `brightnes`"), "{}", err);
+
+    let err = toml::from_str::<Config>("").unwrap_err().to_string();
+    assert!(err.contains("missing field `layout`"), "{}", err);
+
+    let config: Config = toml::from_str("layout = \"GX701\"").unwrap();
+    assert!(!config.enable_on_start());
+    assert_eq!(config.hold_duration(), CustomDuration::from_millis(250));
+    assert_eq!(config.activation_corner(), ActivationCorner::TopRight);
+    assert_eq!(config.drag_tolerance_percent(), 20);
+    assert_eq!(config.toggle_grace_duration(), Duration::from_millis(300));
+    assert_eq!(config.debounce_duration(), Some(Duration::from_millis(200)));
+    assert_eq!(config.swipe_cancel_percent(), None);
+    assert_eq!(config.palm_size_percent(), Some(150));
+    assert_eq!(config.palm_pressure(), None);
+    assert_eq!(config.typing_timeout(), None);
+    assert_eq!(config.i2c_timeout(), None);
+    assert_eq!(config.brightness_protocol(), BrightnessProtocol::default());
+    assert_eq!(config.power_poll_interval(), None);
+    let config: Config = toml::from_str("layout = \"GX701\"\nswipe_cancel_percent = 150").unwrap();
+    assert_eq!(config.swipe_cancel_percent(), Some(150));
+    let err = toml::from_str::<Config>("layout = \"GX701\"\ndrag_tolerance_percent = 101")
+        .unwrap_err()
+        .to_string();
+    assert!(err.contains("out of range (0-100)"), "{}", err);
+
+    let config: Config =
+        toml::from_str("layout = \"GX701\"\nactivation_corner = \"top-left\"").unwrap();
+    assert_eq!(config.activation_corner(), ActivationCorner::TopLeft);
+    assert_eq!(config.key_style(), KeyStyle::Keypad);
+    let config: Config = toml::from_str("layout = \"GX701\"\nkey_style = \"toprow\"").unwrap();
+    assert_eq!(config.key_style(), KeyStyle::Toprow);
+    assert_eq!(config.percent_keys(), None);
+    assert_eq!(config.remap(), &KeyRemap::default());
+    let err = toml::from_str::<Config>("layout = \"GX701\"\n[remap]\nKEY_KP1 = \"KEY_NOPE\"")
+        .unwrap_err()
+        .to_string();
+    assert!(
+        err.contains("Unknown key \"KEY_NOPE\" for KEY_KP1"),
+        "{}",
+        err
+    );
+
+    let config: Config =
+        toml::from_str("layout = \"M433IA\"\npercent_keys = [\"LEFTSHIFT\", \"KEY_APOSTROPHE\"]")
+            .unwrap();
+    assert_eq!(
+        config.percent_keys(),
+        Some(&[EV_KEY::KEY_LEFTSHIFT, EV_KEY::KEY_APOSTROPHE][..])
+    );
+    for keys in ["[\"KEY_PERCENT\"]", "[]"] {
+        let err = toml::from_str::<Config>(&format!("layout = \"M433IA\"\nequals_keys = {}", keys))
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("modifiers first"), "{}", err);
+    }
+
+    assert_eq!(config.numlock_sync(), NumlockSync::Both);
+    let config: Config =
+        toml::from_str("layout = \"GX701\"\nnumlock_sync = \"from-system\"").unwrap();
+    assert!(config.numlock_sync().follows_system());
+    assert!(!config.numlock_sync().drives_system());
+    assert!(toml::from_str::<Config>("layout = \"GX701\"\nnumlock_sync = \"sideways\"").is_err());
+}
+
+#[test]
+fn test_calc_keys() {
+    let config: Config = toml::from_str("layout = \"GX701\"").unwrap();
+    assert_eq!(config.calc_keys(), [EV_KEY::KEY_CALC]);
+    let config: Config = toml::from_str(
+        "layout = \"GX701\"\n\
+        calc_start_command = [\"KEY_LEFTMETA\", \"KEY_C\"]\n\
+        calc_stop_command = {cmd = \"pkill\", args = [\"calc\"]}",
+    )
+    .unwrap();
+    assert_eq!(config.calc_keys(), [EV_KEY::KEY_LEFTMETA, EV_KEY::KEY_C]);
+}
+
+#[test]
+fn test_config_watcher() {
+    let dir = TempDir::new("config");
+    let path = dir.join(CONFIG_FILE_NAME);
+    std::fs::write(&path, "layout = \"UX433FA\"").unwrap();
+    let watcher = ConfigWatcher::new(&path).unwrap();
+    assert!(!watcher.changed());
+
+    std::fs::write(dir.join("other.toml"), "").unwrap();
+    assert!(!watcher.changed());
+
+    // editors usually write a temp file and move it over the original
+    let tmp_path = dir.join("asus_numpad.toml.swp");
+    std::fs::write(&tmp_path, "layout = \"GX701\"").unwrap();
+    std::fs::rename(&tmp_path, &path).unwrap();
+    assert!(watcher.changed());
+    assert!(!watcher.changed());
+}
+
+#[test]
+fn test_sample_config() {
+    let sample = include_str!("../tools/asus_numpad.toml");
+    // set every option that is commented out
+    let uncommented: String = sample
+        .lines()
+        .map(|line| match line.strip_prefix("# ") {
+            Some(option)
+                if option.split_once(" = ").is_some_and(|(key, _)| {
+                    key.chars().all(|c| c.is_ascii_lowercase() || c == '_')
+                }) =>
+            {
+                option
+            }
+            _ => line,
+        })
+        .map(|line| format!("{}\n", line))
+        .collect();
+    let uncommented: Config = toml::from_str(&uncommented).unwrap();
+    let uncommented = toml::Table::try_from(&uncommented).unwrap();
+
+    // options without a default are skipped
+    let defaults: Config = toml::from_str(sample).unwrap();
+    let defaults = toml::Table::try_from(&defaults).unwrap();
+    for (key, value) in &defaults {
+        assert_eq!(uncommented.get(key), Some(value), "{}", key);
+    }
+    assert!(uncommented.len() > defaults.len());
+}
+
+#[test]
+fn test_config_layout_override() {
+    let layout = |layout| Overrides {
+        layout: Some(layout),
+        ..Default::default()
+    };
+    let config = Config::parse(
+        "brightness = \"half\"",
+        &layout(SupportedLayout::GX531),
+        || unreachable!(),
+    )
+    .unwrap();
+    assert_eq!(config.layout(), &SupportedLayout::GX531);
+    assert_eq!(config.brightness(), Brightness::Half);
+    let config = Config::parse(
+        "layout = \"UX433FA\"",
+        &layout(SupportedLayout::M433IA),
+        || unreachable!(),
+    )
+    .unwrap();
+    assert_eq!(config.layout(), &SupportedLayout::M433IA);
+    assert!(Config::parse("", &Overrides::default(), || Err(anyhow!("Unknown model"))).is_err());
+}
+
+#[test]
+fn test_config_brightness_override() {
+    let brightness = |brightness| Overrides {
+        brightness: Some(brightness),
+        ..Default::default()
+    };
+    let data = "layout = \"UX433FA\"\nbrightness = \"half\"";
+    let config = Config::parse(data, &Overrides::default(), || unreachable!()).unwrap();
+    assert_eq!(config.brightness(), Brightness::Half);
+    // the command line wins over the config, which wins over the default
+    let config = Config::parse(data, &brightness(Brightness::Low), || unreachable!()).unwrap();
+    assert_eq!(config.brightness(), Brightness::Low);
+    let config = Config::parse(data, &brightness(Brightness::Zero), || unreachable!()).unwrap();
+    assert_eq!(config.brightness(), Brightness::Zero);
+    let config =
+        Config::parse(data, &brightness(Brightness::Custom(20)), || unreachable!()).unwrap();
+    assert_eq!(config.brightness(), Brightness::Custom(20));
+    let config = Config::parse(
+        "layout = \"UX433FA\"",
+        &Overrides::default(),
+        || unreachable!(),
+    )
+    .unwrap();
+    assert_eq!(config.brightness(), Brightness::Full);
+}
+
+#[test]
+fn test_config_sync_kbd_backlight_override() {
+    let overrides = Overrides {
+        sync_kbd_backlight: true,
+        ..Default::default()
+    };
+    let data = "layout = \"UX433FA\"\nsync_kbd_backlight = false";
+    let config = Config::parse(data, &Overrides::default(), || unreachable!()).unwrap();
+    assert!(config.kbd_backlight_poll_interval().is_none());
+    let config = Config::parse(data, &overrides, || unreachable!()).unwrap();
+    assert!(config.kbd_backlight_poll_interval().is_some());
+}
+
+#[test]
+fn test_toggle_options() {
+    let layout = "layout = \"UX433FA\"\nhold_duration_ms = 300\n";
+    let config = Config::parse(layout, &Overrides::default(), || unreachable!()).unwrap();
+    assert_eq!(
+        config.toggle_hold_duration(),
+        CustomDuration::from_millis(300)
+    );
+    assert_eq!(config.toggle_corner_size_percent(), 100);
+    assert!(!config.toggle_on_tap());
+    assert_eq!(config.activation_mode(), ActivationMode::SingleCorner);
+    assert!(config.one_finger_brightness());
+
+    // only both corners can stop one finger from changing the brightness
+    let data = format!("{}one_finger_brightness = false", layout);
+    let config = Config::parse(&data, &Overrides::default(), || unreachable!()).unwrap();
+    assert!(config.one_finger_brightness());
+    let data = format!(
+        "{}activation_mode = \"both-corners\"\none_finger_brightness = false",
+        layout
+    );
+    let config = Config::parse(&data, &Overrides::default(), || unreachable!()).unwrap();
+    assert_eq!(config.activation_mode(), ActivationMode::BothCorners);
+    assert!(!config.one_finger_brightness());
+
+    let data = format!(
+        "{}toggle_hold_ms = 800\ntoggle_corner_size_percent = 150",
+        layout
+    );
+    let config = Config::parse(&data, &Overrides::default(), || unreachable!()).unwrap();
+    assert_eq!(
+        config.toggle_hold_duration(),
+        CustomDuration::from_millis(800)
+    );
+    assert_eq!(config.hold_duration(), CustomDuration::from_millis(300));
+    assert_eq!(config.toggle_corner_size_percent(), 150);
+
+    let overrides = Overrides {
+        toggle_hold_ms: Some(1000),
+        toggle_corner_size_percent: Some(200),
+        i2c_id: Some(3),
+        touchpad_device: Some("/dev/input/event9".into()),
+        ..Default::default()
+    };
+    let config = Config::parse(&data, &overrides, || unreachable!()).unwrap();
+    assert_eq!(
+        config.toggle_hold_duration(),
+        CustomDuration::from_millis(1000)
+    );
+    assert_eq!(config.toggle_corner_size_percent(), 200);
+    assert_eq!(config.i2c_id(), Some(3));
+    assert_eq!(
+        config.touchpad_device(),
+        Some(Path::new("/dev/input/event9"))
+    );
+
+    let overrides = Overrides {
+        dry_run: true,
+        ..Default::default()
+    };
+    let data = format!(
+        "{}pointer_passthrough = true\npersist_enabled = true",
+        layout
+    );
+    let config = Config::parse(&data, &overrides, || unreachable!()).unwrap();
+    assert!(!config.pointer_passthrough());
+    assert!(!config.persist_enabled());
+
+    let data = format!("{}toggle_corner_size_percent = 5", layout);
+    let err = Config::parse(&data, &Overrides::default(), || unreachable!()).unwrap_err();
+    assert!(format!("{:#}", err).contains("out of range"), "{:#}", err);
+}
+
+#[test]
+fn test_key_repeat() {
+    let layout = "layout = \"UX433FA\"\n";
+    let config = Config::parse(layout, &Overrides::default(), || unreachable!()).unwrap();
+    assert_eq!(
+        config.key_repeat(),
+        Some((
+            CustomDuration::from_millis(500),
+            CustomDuration::from_millis(33)
+        ))
+    );
+    let data = format!("{}repeat_delay_ms = 300\nrepeat_rate_ms = 50", layout);
+    let config = Config::parse(&data, &Overrides::default(), || unreachable!()).unwrap();
+    assert_eq!(
+        config.key_repeat(),
+        Some((
+            CustomDuration::from_millis(300),
+            CustomDuration::from_millis(50)
+        ))
+    );
+    for option in ["repeat_delay_ms", "repeat_rate_ms"] {
+        let data = format!("{}{} = 0", layout, option);
+        let config = Config::parse(&data, &Overrides::default(), || unreachable!()).unwrap();
+        assert_eq!(config.key_repeat(), None, "{}", option);
+    }
+}
+
+#[test]
+fn test_config_detect_layout() {
+    let detect = || Ok(SupportedLayout::UX582);
+    let config = Config::parse("", &Overrides::default(), detect).unwrap();
+    assert_eq!(config.layout(), &SupportedLayout::UX582);
+    let config = Config::parse("layout = \"GX701\"", &Overrides::default(), detect).unwrap();
+    assert_eq!(config.layout(), &SupportedLayout::GX701);
+    let config = Config::parse(
+        "",
+        &Overrides {
+            layout: Some(SupportedLayout::G533),
+            ..Default::default()
+        },
+        || unreachable!(),
+    )
+    .unwrap();
+    assert_eq!(config.layout(), &SupportedLayout::G533);
+}
+
+#[test]
+fn test_custom_layout() {
+    let parse = |keys: &str| {
+        toml::from_str::<Config>(&format!(
+            "[layout.custom]\nbase = \"M433IA\"\nrows = 2\ncolumns = 2\nkeys = {}",
+            keys
+        ))
+    };
+    let config = parse(r#"[["KEY_KP1", "KEY_KP2"], ["KEY_LEFT", ""]]"#).unwrap();
+    assert_eq!(config.layout().name(), "custom");
+    // written back the same way
+    let table = toml::Table::try_from(&config).unwrap();
+    assert_eq!(
+        table["layout"]["custom"]["keys"][1],
+        toml::Value::from(vec!["KEY_LEFT", ""])
+    );
+
+    let err = parse(r#"[["KEY_KP1", "KEY_KP2"], ["KEY_LEFT", "KEY_LFET"]]"#)
+        .unwrap_err()
+        .to_string();
+    assert!(
+        err.contains("Unknown key \"KEY_LFET\" in row 2, column 2"),
+        "{}",
+        err
+    );
+    let err = parse(r#"[["KEY_KP1", "KEY_KP2"], ["KEY_LEFT"]]"#)
+        .unwrap_err()
+        .to_string();
+    assert!(
+        err.contains("Row 2 of the custom layout has 1 keys"),
+        "{}",
+        err
+    );
+    assert!(parse(r#"[["KEY_KP1", "KEY_KP2"]]"#).is_err());
+
+    let margins = "\nmargins_percent = { top = 10, bottom = 2.5, left = 5, right = 5 }";
+    let keys = r#"[["KEY_KP1", "KEY_KP2"], ["KEY_LEFT", ""]]"#;
+    assert!(parse(&format!("{}{}", keys, margins)).is_ok());
+    assert!(
+        parse(&format!(
+            "{}{}",
+            keys,
+            margins.replace("top = 10", "top = 98")
+        ))
+        .is_err()
+    );
 }
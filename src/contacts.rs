@@ -0,0 +1,278 @@
+use std::collections::BTreeMap;
+
+use evdev_rs::enums::{EV_ABS, EV_SYN, EventCode};
+
+use crate::Point;
+
+/// `ABS_MT_TOOL_TYPE` of a contact that the firmware takes for a palm.
+const MT_TOOL_PALM: i32 = 2;
+
+/// Contacts above either of these are taken for palms.
+#[derive(Debug, Default, Clone, Copy)]
+pub(crate) struct PalmLimits {
+    /// Length along the major axis, in touchpad units
+    pub(crate) size: Option<i32>,
+    pub(crate) pressure: Option<i32>,
+}
+
+/// What happened to the finger that drives the numpad.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Contact {
+    /// It went down, and its position is in
+    Down,
+    /// It was lifted
+    Up,
+    /// It turned out to be a palm
+    Palm,
+}
+
+/// Follows the slots of a multitouch touchpad, so that only the first finger
+/// down drives the numpad.
+///
+/// Every slot reports its own position, so another finger or a palm resting
+/// on the touchpad would otherwise move the touch around.
+#[derive(Debug, Default)]
+pub(crate) struct Contacts {
+    /// The slot that the next events are about
+    slot: i32,
+    /// The slot of the first finger down
+    owner: Option<i32>,
+    /// The first finger went down during this frame
+    started: bool,
+    /// [`Contact::Down`] was reported for the first finger
+    down: bool,
+    /// The first finger is a palm
+    palm: bool,
+    /// Where the contact in each slot is, for gestures with more than one finger
+    positions: BTreeMap<i32, Point>,
+}
+
+impl Contacts {
+    /// Whether the events coming in are about the first finger.
+    pub(crate) fn in_owner_slot(&self) -> bool {
+        self.owner == Some(self.slot)
+    }
+
+    /// Where the contacts that are down are, the first finger included, in
+    /// the order of their slots.
+    pub(crate) fn positions(&self) -> impl Iterator<Item = Point> + '_ {
+        self.positions.values().copied()
+    }
+
+    fn track(&mut self, code: &EventCode, value: i32) {
+        match code {
+            EventCode::EV_ABS(EV_ABS::ABS_MT_TRACKING_ID) if value >= 0 => {
+                self.positions.insert(self.slot, Point::default());
+            }
+            EventCode::EV_ABS(EV_ABS::ABS_MT_TRACKING_ID) => {
+                self.positions.remove(&self.slot);
+            }
+            EventCode::EV_ABS(EV_ABS::ABS_MT_POSITION_X) => {
+                if let Some(pos) = self.positions.get_mut(&self.slot) {
+                    pos.x = value;
+                }
+            }
+            EventCode::EV_ABS(EV_ABS::ABS_MT_POSITION_Y) => {
+                if let Some(pos) = self.positions.get_mut(&self.slot) {
+                    pos.y = value;
+                }
+            }
+            _ => (),
+        }
+    }
+
+    /// Follow a touchpad event, telling what happened to the first finger if
+    /// anything did.
+    ///
+    /// Contacts above the `palm` limits are palms. Palms that are down from
+    /// the start are never reported at all.
+    pub(crate) fn update(
+        &mut self,
+        code: &EventCode,
+        value: i32,
+        palm: PalmLimits,
+    ) -> Option<Contact> {
+        self.track(code, value);
+        match code {
+            EventCode::EV_ABS(EV_ABS::ABS_MT_SLOT) => self.slot = value,
+            EventCode::EV_ABS(EV_ABS::ABS_MT_TRACKING_ID) if value >= 0 && self.owner.is_none() => {
+                self.owner = Some(self.slot);
+                self.started = true;
+                self.palm = false;
+            }
+            EventCode::EV_ABS(EV_ABS::ABS_MT_TRACKING_ID) if value < 0 && self.in_owner_slot() => {
+                self.owner = None;
+                self.started = false;
+                return std::mem::take(&mut self.down).then_some(Contact::Up);
+            }
+            EventCode::EV_ABS(EV_ABS::ABS_MT_TOUCH_MAJOR)
+                if self.in_owner_slot() && palm.size.is_some_and(|size| value > size) =>
+            {
+                return self.on_palm();
+            }
+            EventCode::EV_ABS(EV_ABS::ABS_MT_PRESSURE)
+                if self.in_owner_slot()
+                    && palm.pressure.is_some_and(|pressure| value > pressure) =>
+            {
+                return self.on_palm();
+            }
+            EventCode::EV_ABS(EV_ABS::ABS_MT_TOOL_TYPE)
+                if self.in_owner_slot() && value == MT_TOOL_PALM =>
+            {
+                return self.on_palm();
+            }
+            // the position comes after the tracking id, so wait for the whole frame
+            EventCode::EV_SYN(EV_SYN::SYN_REPORT)
+                if !self.palm && std::mem::take(&mut self.started) =>
+            {
+                self.down = true;
+                return Some(Contact::Down);
+            }
+            _ => (),
+        }
+        None
+    }
+
+    fn on_palm(&mut self) -> Option<Contact> {
+        if std::mem::replace(&mut self.palm, true) {
+            return None;
+        }
+        self.down.then_some(Contact::Palm)
+    }
+}
+
+#[cfg(test)]
+fn replay(contacts: &mut Contacts, events: &[(EV_ABS, i32)]) -> Vec<Contact> {
+    let palm = PalmLimits {
+        size: Some(500),
+        pressure: Some(200),
+    };
+    let mut changes = Vec::new();
+    for &(axis, value) in events {
+        changes.extend(contacts.update(&EventCode::EV_ABS(axis), value, palm));
+    }
+    changes.extend(contacts.update(&EventCode::EV_SYN(EV_SYN::SYN_REPORT), 0, palm));
+    changes
+}
+
+#[test]
+fn test_contacts_second_finger() {
+    use EV_ABS::*;
+    let mut contacts = Contacts::default();
+    let changes = replay(
+        &mut contacts,
+        &[
+            (ABS_MT_SLOT, 0),
+            (ABS_MT_TRACKING_ID, 10),
+            (ABS_MT_POSITION_X, 100),
+        ],
+    );
+    assert_eq!(changes, [Contact::Down]);
+    assert!(contacts.in_owner_slot());
+
+    // a second finger comes and goes without taking over
+    assert!(replay(&mut contacts, &[(ABS_MT_SLOT, 1), (ABS_MT_TRACKING_ID, 11)]).is_empty());
+    assert!(!contacts.in_owner_slot());
+    assert!(
+        replay(
+            &mut contacts,
+            &[(ABS_MT_POSITION_X, 900), (ABS_MT_TRACKING_ID, -1)]
+        )
+        .is_empty()
+    );
+    assert!(replay(&mut contacts, &[(ABS_MT_SLOT, 0)]).is_empty());
+    assert!(contacts.in_owner_slot());
+
+    // the first one lifting first leaves the second one ignored
+    replay(&mut contacts, &[(ABS_MT_SLOT, 1), (ABS_MT_TRACKING_ID, 12)]);
+    let changes = replay(&mut contacts, &[(ABS_MT_SLOT, 0), (ABS_MT_TRACKING_ID, -1)]);
+    assert_eq!(changes, [Contact::Up]);
+    assert!(replay(&mut contacts, &[(ABS_MT_SLOT, 1), (ABS_MT_POSITION_X, 50)]).is_empty());
+    assert!(!contacts.in_owner_slot());
+    assert!(replay(&mut contacts, &[(ABS_MT_TRACKING_ID, -1)]).is_empty());
+
+    // and the next finger down is followed again
+    let changes = replay(&mut contacts, &[(ABS_MT_TRACKING_ID, 13)]);
+    assert_eq!(changes, [Contact::Down]);
+}
+
+#[test]
+fn test_contacts_positions() {
+    use EV_ABS::*;
+    let mut contacts = Contacts::default();
+    replay(
+        &mut contacts,
+        &[
+            (ABS_MT_SLOT, 0),
+            (ABS_MT_TRACKING_ID, 1),
+            (ABS_MT_POSITION_X, 100),
+            (ABS_MT_POSITION_Y, 50),
+            (ABS_MT_SLOT, 1),
+            (ABS_MT_TRACKING_ID, 2),
+            (ABS_MT_POSITION_X, 900),
+            (ABS_MT_POSITION_Y, 60),
+        ],
+    );
+    let positions: Vec<_> = contacts.positions().collect();
+    assert_eq!(
+        positions,
+        [Point { x: 100, y: 50 }, Point { x: 900, y: 60 }]
+    );
+
+    // only the slot that the events are about moves
+    replay(&mut contacts, &[(ABS_MT_POSITION_Y, 70)]);
+    assert_eq!(contacts.positions().nth(1), Some(Point { x: 900, y: 70 }));
+    assert_eq!(contacts.positions().next(), Some(Point { x: 100, y: 50 }));
+
+    // and lifting either finger leaves the other one
+    replay(&mut contacts, &[(ABS_MT_SLOT, 0), (ABS_MT_TRACKING_ID, -1)]);
+    let positions: Vec<_> = contacts.positions().collect();
+    assert_eq!(positions, [Point { x: 900, y: 70 }]);
+}
+
+#[test]
+fn test_contacts_palm() {
+    use EV_ABS::*;
+    let mut contacts = Contacts::default();
+    replay(
+        &mut contacts,
+        &[(ABS_MT_TRACKING_ID, 1), (ABS_MT_TOUCH_MAJOR, 300)],
+    );
+    let changes = replay(&mut contacts, &[(ABS_MT_TOUCH_MAJOR, 600)]);
+    assert_eq!(changes, [Contact::Palm]);
+    // only once, and the lift is still reported
+    assert!(replay(&mut contacts, &[(ABS_MT_TOOL_TYPE, MT_TOOL_PALM)]).is_empty());
+    assert_eq!(
+        replay(&mut contacts, &[(ABS_MT_TRACKING_ID, -1)]),
+        [Contact::Up]
+    );
+
+    // a palm from the start isn't a touch at all
+    let changes = replay(
+        &mut contacts,
+        &[(ABS_MT_TRACKING_ID, 2), (ABS_MT_TOUCH_MAJOR, 800)],
+    );
+    assert!(changes.is_empty());
+    assert!(replay(&mut contacts, &[(ABS_MT_TRACKING_ID, -1)]).is_empty());
+
+    assert!(replay(&mut contacts, &[(ABS_MT_TRACKING_ID, -1)]).is_empty());
+
+    // pressing hard counts too
+    replay(
+        &mut contacts,
+        &[(ABS_MT_TRACKING_ID, 3), (ABS_MT_PRESSURE, 150)],
+    );
+    let changes = replay(&mut contacts, &[(ABS_MT_PRESSURE, 201)]);
+    assert_eq!(changes, [Contact::Palm]);
+    assert_eq!(
+        replay(&mut contacts, &[(ABS_MT_TRACKING_ID, -1)]),
+        [Contact::Up]
+    );
+
+    // nor is one that the firmware takes for a palm
+    let changes = replay(
+        &mut contacts,
+        &[(ABS_MT_TRACKING_ID, 3), (ABS_MT_TOOL_TYPE, MT_TOOL_PALM)],
+    );
+    assert!(changes.is_empty());
+}
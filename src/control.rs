@@ -0,0 +1,541 @@
+use std::fs::{File, FileType};
+use std::io::{ErrorKind, Read, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{FileTypeExt, OpenOptionsExt, PermissionsExt};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result, anyhow};
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+
+#[cfg(test)]
+use crate::testing::TempDir;
+use crate::touchpad_i2c::Brightness;
+
+/// Longest line accepted from a client, to not buffer garbage forever.
+const MAX_LINE_LEN: usize = 256;
+
+/// A request sent by a client, one per line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Command {
+    Toggle,
+    On,
+    Off,
+    Brightness(Brightness),
+    /// Step up to the next brightness level
+    Cycle,
+    /// Stop the numpad from being toggled or dimmed by touch
+    Lock,
+    Unlock,
+    /// Reply with the current state as JSON
+    Status,
+}
+
+impl TryFrom<&str> for Command {
+    type Error = anyhow::Error;
+
+    fn try_from(line: &str) -> Result<Self> {
+        let mut words = line.split_whitespace();
+        let first = words.next();
+        // `brightness:half` works too, as written to the FIFO
+        let (name, arg) = match first.and_then(|word| word.split_once(':')) {
+            Some((name, arg)) => (Some(name), Some(arg)),
+            None => (first, words.next()),
+        };
+        let command = match (name, arg) {
+            (Some("toggle"), None) => Self::Toggle,
+            (Some("on"), None) => Self::On,
+            (Some("off"), None) => Self::Off,
+            (Some("status"), None) => Self::Status,
+            (Some("cycle"), None) => Self::Cycle,
+            (Some("lock"), None) => Self::Lock,
+            (Some("unlock"), None) => Self::Unlock,
+            (Some("brightness"), Some(level)) => Self::Brightness(Brightness::try_from(level)?),
+            _ => return Err(anyhow!("Unknown command {:?}", line.trim())),
+        };
+        if words.next().is_some() {
+            return Err(anyhow!("Unexpected arguments in {:?}", line.trim()));
+        }
+        Ok(command)
+    }
+}
+
+/// The reply to [`Command::Status`], as JSON.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub(crate) struct Status {
+    pub(crate) enabled: bool,
+    pub(crate) brightness: Brightness,
+    /// Whether touches can't toggle the numpad or change its brightness
+    #[serde(default)]
+    pub(crate) locked: bool,
+}
+
+impl std::fmt::Display for Status {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Numpad is {}, brightness {} ({}%){}",
+            if self.enabled { "on" } else { "off" },
+            self.brightness,
+            self.brightness.to_percent(),
+            if self.locked { ", locked" } else { "" }
+        )
+    }
+}
+
+impl std::fmt::Display for Command {
+    /// Format as the line that [`Command::try_from`] parses.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Toggle => write!(f, "toggle"),
+            Self::On => write!(f, "on"),
+            Self::Off => write!(f, "off"),
+            Self::Brightness(brightness) => match brightness {
+                // `Custom(20)` isn't accepted back
+                Brightness::Custom(raw) => write!(f, "brightness {}", raw),
+                _ => write!(f, "brightness {}", brightness),
+            },
+            Self::Cycle => write!(f, "cycle"),
+            Self::Lock => write!(f, "lock"),
+            Self::Unlock => write!(f, "unlock"),
+            Self::Status => write!(f, "status"),
+        }
+    }
+}
+
+/// Send `command` to the daemon listening at `path`, returning its reply.
+pub(crate) fn send_command(path: &Path, command: Command) -> Result<String> {
+    let mut stream = UnixStream::connect(path).with_context(|| {
+        format!(
+            "Could not connect to {}. Is the daemon running?",
+            path.display()
+        )
+    })?;
+    stream.set_read_timeout(Some(std::time::Duration::from_secs(5)))?;
+    writeln!(stream, "{}", command)?;
+    stream.shutdown(std::net::Shutdown::Write)?;
+    let mut reply = String::new();
+    stream
+        .read_to_string(&mut reply)
+        .context("Could not read the reply")?;
+    let reply = reply.trim_end();
+    match reply.strip_prefix("error: ") {
+        Some(err) => Err(anyhow!("{}", err)),
+        None => Ok(reply.to_owned()),
+    }
+}
+
+/// Get the default path of the socket.
+///
+/// Uses the `RuntimeDirectory` set up by systemd if available. Clients don't
+/// get that variable, so they look for the directory itself.
+pub(crate) fn default_socket_path() -> PathBuf {
+    const RUNTIME_DIR: &str = "/run/asus-numpad";
+    match std::env::var_os("RUNTIME_DIRECTORY") {
+        Some(dir) => PathBuf::from(dir).join("asus-numpad.sock"),
+        None if Path::new(RUNTIME_DIR).is_dir() => Path::new(RUNTIME_DIR).join("asus-numpad.sock"),
+        None => PathBuf::from("/run/asus-numpad.sock"),
+    }
+}
+
+#[derive(Debug)]
+struct Client {
+    stream: UnixStream,
+    buf: Vec<u8>,
+}
+
+/// Accepts line-based [`Command`]s on a Unix socket.
+///
+/// Everything is non-blocking, so that a stuck client can't hold up the event loop.
+#[derive(Debug)]
+pub(crate) struct ControlSocket {
+    listener: UnixListener,
+    path: PathBuf,
+    clients: Vec<Client>,
+}
+
+impl ControlSocket {
+    /// Listen at `path`, giving access to `group` (if any) according to `mode`.
+    pub(crate) fn bind(path: &Path, mode: u32, group: Option<&str>) -> Result<Self> {
+        remove_stale(path, "socket", FileType::is_socket)?;
+        let listener = UnixListener::bind(path)
+            .with_context(|| format!("Could not listen on {}", path.display()))?;
+        listener.set_nonblocking(true)?;
+        let socket = Self {
+            listener,
+            path: path.to_owned(),
+            clients: Vec::new(),
+        };
+        if let Some(group) = group {
+            set_group(path, group)?;
+        }
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))
+            .with_context(|| format!("Could not set permissions of {}", path.display()))?;
+        Ok(socket)
+    }
+
+    /// The fds to be polled: the listener followed by every client.
+    pub(crate) fn pollfds(&self) -> impl Iterator<Item = libc::pollfd> + '_ {
+        std::iter::once(self.listener.as_raw_fd())
+            .chain(self.clients.iter().map(|client| client.stream.as_raw_fd()))
+            .map(|fd| libc::pollfd {
+                fd,
+                events: libc::POLLIN,
+                revents: 0,
+            })
+    }
+
+    /// Accept new clients and answer the commands of the ones in `fds`
+    /// that are readable, using `handler` to get the reply to each.
+    ///
+    /// `fds` must be the result of polling [`ControlSocket::pollfds`].
+    pub(crate) fn process(
+        &mut self,
+        fds: &[libc::pollfd],
+        mut handler: impl FnMut(Command) -> String,
+    ) {
+        // disconnected clients are removed after going through all of them,
+        // so that the indices keep matching `fds`
+        let mut keep = Vec::with_capacity(self.clients.len());
+        for (client, fd) in self.clients.iter_mut().zip(&fds[1..]) {
+            keep.push(fd.revents == 0 || client.serve(&mut handler));
+        }
+        let mut keep = keep.into_iter();
+        self.clients.retain(|_| keep.next().unwrap_or(true));
+
+        if fds[0].revents & libc::POLLIN != 0 {
+            loop {
+                match self.listener.accept() {
+                    Ok((stream, _)) => {
+                        if let Err(err) = stream.set_nonblocking(true) {
+                            warn!("Dropping control client: {}", err);
+                            continue;
+                        }
+                        debug!("New control client");
+                        self.clients.push(Client {
+                            stream,
+                            buf: Vec::new(),
+                        });
+                    }
+                    Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+                    Err(err) => {
+                        warn!("Failed to accept control client: {}", err);
+                        break;
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Drop for ControlSocket {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+impl Client {
+    /// Read the available data and reply to any complete lines.
+    ///
+    /// Returns whether the client should be kept around.
+    fn serve(&mut self, handler: &mut impl FnMut(Command) -> String) -> bool {
+        // only read once, poll will tell us if there is more
+        let mut data = [0; MAX_LINE_LEN];
+        match self.stream.read(&mut data) {
+            Ok(0) => return false,
+            Ok(len) => self.buf.extend_from_slice(&data[..len]),
+            Err(err) if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::Interrupted) => {
+                return true;
+            }
+            Err(err) => {
+                debug!("Control client error: {}", err);
+                return false;
+            }
+        }
+        while let Some(end) = self.buf.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = self.buf.drain(..=end).collect();
+            let reply = match std::str::from_utf8(&line)
+                .map_err(anyhow::Error::new)
+                .and_then(Command::try_from)
+            {
+                Ok(command) => {
+                    debug!("Control command {:?}", command);
+                    handler(command)
+                }
+                Err(err) => format!("error: {:#}", err),
+            };
+            // replies are tiny, so not being able to write them at once means
+            // that the client isn't reading them
+            if let Err(err) = self.stream.write_all(format!("{}\n", reply).as_bytes()) {
+                debug!("Dropping control client: {}", err);
+                return false;
+            }
+        }
+        if self.buf.len() > MAX_LINE_LEN {
+            debug!("Dropping control client: line too long");
+            return false;
+        }
+        true
+    }
+}
+
+/// Reads [`Command`]s from a named pipe, one per line, for scripts that
+/// don't need a reply.
+///
+/// It is opened for writing too, so that it doesn't look closed whenever no
+/// one else has it open, and it is read without blocking.
+#[derive(Debug)]
+pub(crate) struct ControlFifo {
+    file: File,
+    path: PathBuf,
+    buf: Vec<u8>,
+}
+
+impl ControlFifo {
+    /// Create the FIFO at `path`, giving access to `group` (if any) according to `mode`.
+    pub(crate) fn create(path: &Path, mode: u32, group: Option<&str>) -> Result<Self> {
+        remove_stale(path, "FIFO", FileType::is_fifo)?;
+        let c_path = std::ffi::CString::new(path.as_os_str().as_bytes())?;
+        if unsafe { libc::mkfifo(c_path.as_ptr(), 0o600) } != 0 {
+            return Err(std::io::Error::last_os_error())
+                .with_context(|| format!("Could not create FIFO {}", path.display()));
+        }
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open(path)
+            .with_context(|| format!("Could not open FIFO {}", path.display()))?;
+        let fifo = Self {
+            file,
+            path: path.to_owned(),
+            buf: Vec::new(),
+        };
+        if let Some(group) = group {
+            set_group(path, group)?;
+        }
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))
+            .with_context(|| format!("Could not set permissions of {}", path.display()))?;
+        Ok(fifo)
+    }
+
+    pub(crate) fn as_raw_fd(&self) -> RawFd {
+        self.file.as_raw_fd()
+    }
+
+    /// Run the complete commands that were written, using `handler`.
+    ///
+    /// There is no one to reply to, so errors are logged instead.
+    pub(crate) fn process(&mut self, mut handler: impl FnMut(Command) -> String) {
+        let mut data = [0; MAX_LINE_LEN];
+        loop {
+            match self.file.read(&mut data) {
+                Ok(0) => break,
+                Ok(len) => self.buf.extend_from_slice(&data[..len]),
+                Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+                Err(err) => {
+                    warn!("Failed to read FIFO {}: {}", self.path.display(), err);
+                    break;
+                }
+            }
+        }
+        while let Some(end) = self.buf.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = self.buf.drain(..=end).collect();
+            match std::str::from_utf8(&line)
+                .map_err(anyhow::Error::new)
+                .and_then(Command::try_from)
+            {
+                Ok(command) => {
+                    debug!("FIFO command {:?}", command);
+                    let reply = handler(command);
+                    match reply.strip_prefix("error: ") {
+                        Some(err) => warn!("FIFO command {} failed: {}", command, err),
+                        None if command == Command::Status => info!("{}", reply),
+                        None => (),
+                    }
+                }
+                Err(err) => warn!("Ignoring FIFO line: {:#}", err),
+            }
+        }
+        // a writer without newlines shouldn't fill up the memory
+        if self.buf.len() > MAX_LINE_LEN {
+            warn!("Ignoring FIFO line: too long");
+            self.buf.clear();
+        }
+    }
+}
+
+impl Drop for ControlFifo {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Remove the `what` left at `path` if the previous run didn't exit cleanly.
+///
+/// Anything else that `is_kind` doesn't match is an error rather than being
+/// removed, since the path might have a typo in it.
+fn remove_stale(path: &Path, what: &str, is_kind: fn(&FileType) -> bool) -> Result<()> {
+    let metadata = match std::fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(err) => return Err(err).with_context(|| format!("Could not check {}", path.display())),
+    };
+    if !is_kind(&metadata.file_type()) {
+        return Err(anyhow!(
+            "{} is not a {}, refusing to replace it",
+            path.display(),
+            what
+        ));
+    }
+    std::fs::remove_file(path)
+        .with_context(|| format!("Could not remove old {} {}", what, path.display()))
+}
+
+/// Change the group owning `path` to `group`.
+fn set_group(path: &Path, group: &str) -> Result<()> {
+    let c_group = std::ffi::CString::new(group)?;
+    let entry = unsafe { libc::getgrnam(c_group.as_ptr()) };
+    if entry.is_null() {
+        return Err(anyhow!("Unknown group {:?}", group));
+    }
+    let gid = unsafe { (*entry).gr_gid };
+    std::os::unix::fs::chown(path, None, Some(gid))
+        .with_context(|| format!("Could not change group of {} to {}", path.display(), group))
+}
+
+#[test]
+fn test_parse_command() {
+    let parse = |line| Command::try_from(line);
+    assert_eq!(parse("toggle\n").unwrap(), Command::Toggle);
+    assert_eq!(parse(" status ").unwrap(), Command::Status);
+    assert_eq!(
+        parse("brightness half").unwrap(),
+        Command::Brightness(Brightness::Half)
+    );
+    assert_eq!(
+        parse("brightness 20").unwrap(),
+        Command::Brightness(Brightness::Custom(20))
+    );
+    assert!(parse("brightness").is_err());
+    assert!(parse("brightness blinding").is_err());
+    assert!(parse("on now").is_err());
+    assert!(parse("").is_err());
+    assert_eq!(
+        parse("brightness:50%").unwrap(),
+        Command::Brightness(Brightness::Half)
+    );
+    assert!(parse("off:now").is_err());
+
+    for command in [
+        Command::Cycle,
+        Command::Lock,
+        Command::Brightness(Brightness::Low),
+        Command::Brightness(Brightness::Custom(20)),
+    ] {
+        assert_eq!(Command::try_from(&*command.to_string()).unwrap(), command);
+    }
+}
+
+#[test]
+fn test_control_socket() {
+    let dir = TempDir::new("control");
+    let path = dir.join("asus-numpad.sock");
+    let mut socket = ControlSocket::bind(&path, 0o600, None).unwrap();
+    let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o600);
+
+    let poll = |socket: &ControlSocket| {
+        let mut fds: Vec<_> = socket.pollfds().collect();
+        unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as _, 1000) };
+        fds
+    };
+    let mut client = UnixStream::connect(&path).unwrap();
+    let fds = poll(&socket);
+    socket.process(&fds, |_| unreachable!());
+    assert_eq!(socket.clients.len(), 1);
+
+    client.write_all(b"status\nbogus\n").unwrap();
+    let fds = poll(&socket);
+    let mut commands = Vec::new();
+    socket.process(&fds, |command| {
+        commands.push(command);
+        "ok".to_owned()
+    });
+    assert_eq!(commands, [Command::Status]);
+    let mut reply = [0; 64];
+    let len = client.read(&mut reply).unwrap();
+    let reply = std::str::from_utf8(&reply[..len]).unwrap();
+    assert_eq!(reply, "ok\nerror: Unknown command \"bogus\"\n");
+
+    drop(client);
+    let fds = poll(&socket);
+    socket.process(&fds, |_| unreachable!());
+    assert!(socket.clients.is_empty());
+
+    drop(socket);
+    assert!(!path.exists());
+
+    // a socket left behind is replaced, but nothing else is
+    let listener = UnixListener::bind(&path).unwrap();
+    drop(listener);
+    drop(ControlSocket::bind(&path, 0o600, None).unwrap());
+    std::fs::write(&path, "not a socket").unwrap();
+    let err = ControlSocket::bind(&path, 0o600, None).unwrap_err();
+    assert!(
+        format!("{:#}", err).contains("is not a socket"),
+        "{:#}",
+        err
+    );
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "not a socket");
+}
+
+#[test]
+fn test_control_fifo() {
+    let dir = TempDir::new("control");
+    let path = dir.join("asus-numpad.fifo");
+    let mut fifo = ControlFifo::create(&path, 0o620, None).unwrap();
+    let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o620);
+
+    let mut commands = Vec::new();
+    let mut writer = std::fs::OpenOptions::new().write(true).open(&path).unwrap();
+    writer
+        .write_all(b"toggle\nbogus\nbrightness:half\ncyc")
+        .unwrap();
+    fifo.process(|command| {
+        commands.push(command);
+        "ok".to_owned()
+    });
+    assert_eq!(
+        commands,
+        [Command::Toggle, Command::Brightness(Brightness::Half)]
+    );
+    // the rest of the line comes later, from another writer
+    drop(writer);
+    let mut writer = std::fs::OpenOptions::new().write(true).open(&path).unwrap();
+    writer.write_all(b"le\n").unwrap();
+    commands.clear();
+    fifo.process(|command| {
+        commands.push(command);
+        "ok".to_owned()
+    });
+    assert_eq!(commands, [Command::Cycle]);
+    // nothing left doesn't block
+    fifo.process(|_| unreachable!());
+
+    drop(fifo);
+    assert!(!path.exists());
+
+    // a FIFO left behind is replaced, but nothing else is
+    let c_path = std::ffi::CString::new(path.as_os_str().as_bytes()).unwrap();
+    assert_eq!(unsafe { libc::mkfifo(c_path.as_ptr(), 0o600) }, 0);
+    drop(ControlFifo::create(&path, 0o600, None).unwrap());
+    std::fs::write(&path, "not a fifo").unwrap();
+    let err = ControlFifo::create(&path, 0o600, None).unwrap_err();
+    assert!(format!("{:#}", err).contains("is not a FIFO"), "{:#}", err);
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "not a fifo");
+}
@@ -0,0 +1,221 @@
+use std::io::{ErrorKind, Read, Write};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::sync::mpsc::{Receiver, Sender, channel};
+
+use anyhow::{Context, Result};
+use log::debug;
+use zbus::blocking::Connection;
+use zbus::fdo;
+use zbus::object_server::SignalEmitter;
+
+use crate::control::Command;
+use crate::touchpad_i2c::Brightness;
+
+const BUS_NAME: &str = "org.asus.Numpad";
+const OBJECT_PATH: &str = "/org/asus/Numpad";
+
+/// The object served on the bus.
+///
+/// Method calls are handled on zbus' own thread, so they are only forwarded
+/// to the event loop, which is woken up by writing to `wake`.
+struct NumpadInterface {
+    commands: Sender<Command>,
+    wake: UnixStream,
+    enabled: bool,
+    brightness: Brightness,
+}
+
+impl NumpadInterface {
+    fn send(&self, command: Command) -> fdo::Result<()> {
+        self.commands
+            .send(command)
+            .map_err(|_| fdo::Error::Failed("Numpad is shutting down".to_owned()))?;
+        (&self.wake)
+            .write_all(&[0])
+            .map_err(|err| fdo::Error::Failed(err.to_string()))
+    }
+
+    fn send_brightness(&self, level: &str) -> fdo::Result<()> {
+        let brightness =
+            Brightness::try_from(level).map_err(|err| fdo::Error::InvalidArgs(err.to_string()))?;
+        self.send(Command::Brightness(brightness))
+    }
+}
+
+#[zbus::interface(name = "org.asus.Numpad")]
+impl NumpadInterface {
+    /// Turn the numpad on or off.
+    fn toggle(&self) -> fdo::Result<()> {
+        self.send(Command::Toggle)
+    }
+
+    fn enable(&self) -> fdo::Result<()> {
+        self.send(Command::On)
+    }
+
+    fn disable(&self) -> fdo::Result<()> {
+        self.send(Command::Off)
+    }
+
+    /// Stop touches from toggling the numpad or changing its brightness.
+    fn lock(&self) -> fdo::Result<()> {
+        self.send(Command::Lock)
+    }
+
+    fn unlock(&self) -> fdo::Result<()> {
+        self.send(Command::Unlock)
+    }
+
+    /// Same as the `Enabled` property, for clients that don't use properties.
+    fn get_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    fn set_enabled(&self, enabled: bool) -> fdo::Result<()> {
+        self.send(if enabled { Command::On } else { Command::Off })
+    }
+
+    /// Same as the `Brightness` property, for clients that don't use properties.
+    fn get_brightness(&self) -> String {
+        self.brightness.to_string()
+    }
+
+    /// Change the brightness level, given by its name, raw value or percentage.
+    #[zbus(name = "SetBrightness")]
+    fn set_brightness_level(&self, level: &str) -> fdo::Result<()> {
+        self.send_brightness(level)
+    }
+
+    #[zbus(property)]
+    fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    #[zbus(property)]
+    fn brightness(&self) -> String {
+        self.brightness.to_string()
+    }
+
+    #[zbus(property)]
+    fn set_brightness(&mut self, level: &str) -> fdo::Result<()> {
+        self.send_brightness(level)
+    }
+
+    /// Emitted whenever the numpad is toggled or its brightness changes.
+    #[zbus(signal)]
+    async fn state_changed(
+        emitter: &SignalEmitter<'_>,
+        enabled: bool,
+        brightness: &str,
+    ) -> zbus::Result<()>;
+
+    /// Emitted whenever the brightness level changes.
+    ///
+    /// Renamed in Rust, to not clash with the property change notification.
+    #[zbus(signal, name = "BrightnessChanged")]
+    async fn brightness_changed_signal(
+        emitter: &SignalEmitter<'_>,
+        brightness: &str,
+    ) -> zbus::Result<()>;
+}
+
+/// Serves [`NumpadInterface`] on the system bus.
+pub(crate) struct DbusService {
+    conn: Connection,
+    commands: Receiver<Command>,
+    wake: UnixStream,
+    /// The state last published on the bus
+    published: Option<(bool, Brightness)>,
+}
+
+impl std::fmt::Debug for DbusService {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DbusService")
+            .field("name", &BUS_NAME)
+            .field("published", &self.published)
+            .finish()
+    }
+}
+
+impl DbusService {
+    pub(crate) fn start() -> Result<Self> {
+        let (sender, commands) = channel();
+        let (wake, wake_writer) = UnixStream::pair()?;
+        wake.set_nonblocking(true)?;
+        let interface = NumpadInterface {
+            commands: sender,
+            wake: wake_writer,
+            enabled: false,
+            brightness: Brightness::Zero,
+        };
+        let conn = zbus::blocking::connection::Builder::system()?
+            .name(BUS_NAME)?
+            .serve_at(OBJECT_PATH, interface)?
+            .build()
+            .with_context(|| format!("Could not register {} on the system bus", BUS_NAME))?;
+        Ok(Self {
+            conn,
+            commands,
+            wake,
+            published: None,
+        })
+    }
+
+    /// Take the commands received since the last call.
+    pub(crate) fn commands(&mut self) -> Vec<Command> {
+        let mut buf = [0; 64];
+        loop {
+            match self.wake.read(&mut buf) {
+                Ok(0) => break,
+                Ok(_) => continue,
+                Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                Err(_) => break,
+            }
+        }
+        self.commands.try_iter().collect()
+    }
+
+    /// Update the properties, emitting signals if the state is different from before.
+    pub(crate) fn publish(&mut self, enabled: bool, brightness: Brightness) -> Result<()> {
+        if self.published == Some((enabled, brightness)) {
+            return Ok(());
+        }
+        let brightness_changed = self
+            .published
+            .is_some_and(|(_, published)| published != brightness);
+        let first = self.published.is_none();
+        self.published = Some((enabled, brightness));
+        let iface = self
+            .conn
+            .object_server()
+            .interface::<_, NumpadInterface>(OBJECT_PATH)?;
+        {
+            let mut iface = iface.get_mut();
+            iface.enabled = enabled;
+            iface.brightness = brightness;
+        }
+        if first {
+            return Ok(());
+        }
+        debug!("Publishing state on D-Bus");
+        let emitter = iface.signal_emitter();
+        zbus::block_on(async {
+            let iface = iface.get();
+            iface.enabled_changed(emitter).await?;
+            iface.brightness_changed(emitter).await?;
+            let brightness = brightness.to_string();
+            if brightness_changed {
+                NumpadInterface::brightness_changed_signal(emitter, &brightness).await?;
+            }
+            NumpadInterface::state_changed(emitter, enabled, &brightness).await
+        })
+        .context("Could not emit D-Bus signals")
+    }
+}
+
+impl AsRawFd for DbusService {
+    fn as_raw_fd(&self) -> RawFd {
+        self.wake.as_raw_fd()
+    }
+}
@@ -3,9 +3,11 @@ use evdev_rs::{
     Device, DeviceWrapper,
     enums::{EV_ABS, EventCode},
 };
-use std::{fs::OpenOptions, os::unix::prelude::OpenOptionsExt};
+use log::{debug, info};
+use std::{fs::OpenOptions, os::unix::prelude::OpenOptionsExt, path::Path};
 
 use crate::numpad_layout::BBox;
+use crate::touchpad_i2c::touchpad_rank;
 
 fn parse_id(line: &str, search_str: &str) -> Result<u32> {
     let pos = line
@@ -30,111 +32,167 @@ fn parse_id(line: &str, search_str: &str) -> Result<u32> {
     Ok(line[start_idx..end_idx].parse()?)
 }
 
-/// Parse `/proc/bus/input/devices` to find the keyboard and touchpad devices.
-/// Returns the evdev handles for keybard and touchpad, along with I2C ID of touchpad.
-pub(crate) fn read_proc_input() -> Result<(u32, u32, u32)> {
-    #[derive(Debug, PartialEq, Eq)]
-    enum Detection {
-        NotDetected,
-        Parsing,
-        Finished,
-    }
-    let mut touchpad_detection = Detection::NotDetected;
-    let mut keyboard_detection = Detection::NotDetected;
-
-    let mut touchpad_i2c_id: Option<u32> = None;
-    let mut touchpad_ev_id: Option<u32> = None;
-    let mut keyboard_ev_id: Option<u32> = None;
+/// A touchpad listed in `/proc/bus/input/devices`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct TouchpadInput {
+    pub(crate) name: String,
+    pub(crate) evdev_id: u32,
+    /// The I2C bus it is on, if it is on one
+    pub(crate) i2c_id: Option<u32>,
+}
 
-    let data = std::fs::read_to_string("/proc/bus/input/devices")
-        .context("Could not read devices file")?;
+fn is_touchpad_name(name: &str) -> bool {
+    touchpad_rank(name).is_some() && name.contains("Touchpad")
+}
 
-    for line in data.lines() {
-        match touchpad_detection {
-            // FIXME: Should give priority to ASUE,ASUP etc. before ELAN
-            // In general, need to parse the entire thing, and use proper filtering
-            Detection::NotDetected => {
-                if line.starts_with("N:")
-                    && (line.contains("Name=\"ASUE")
-                        || line.contains("Name=\"ELAN")
-                        || line.contains("Name=\"ASUP")
-                        || line.contains("Name=\"ASCP"))
-                    && line.contains("Touchpad")
-                {
-                    touchpad_detection = Detection::Parsing;
-                    continue;
-                }
-            }
-            Detection::Parsing => {
-                if line.starts_with("S:") {
-                    touchpad_i2c_id =
-                        Some(parse_id(line, "i2c-").context("Could not parse u32 ID")?);
-                    continue;
-                } else if line.starts_with("H:") {
-                    touchpad_ev_id =
-                        Some(parse_id(line, "event").context("Could not parse u32 ID")?);
-                    continue;
-                } else if line.is_empty() {
-                    // reset since we reached end of device info
-                    touchpad_detection = Detection::NotDetected;
-                }
-                if touchpad_i2c_id.is_some() && touchpad_ev_id.is_some() {
-                    touchpad_detection = Detection::Finished;
-                }
-            }
-            _ => (),
-        }
+fn is_keyboard_name(name: &str) -> bool {
+    name.starts_with("AT Translated Set 2 keyboard")
+        || ((name.starts_with("ASUE") || name.starts_with("Asus")) && name.contains("Keyboard"))
+}
 
-        match keyboard_detection {
-            Detection::NotDetected => {
-                if line.starts_with("N:")
-                    && (line.contains("Name=\"AT Translated Set 2 keyboard")
-                        || ((line.contains("Name=\"ASUE") || line.contains("Name=\"Asus"))
-                            && line.contains("Keyboard")))
-                {
-                    keyboard_detection = Detection::Parsing;
-                    continue;
-                }
-            }
-            Detection::Parsing => {
-                if line.starts_with("H:") {
-                    keyboard_ev_id =
-                        Some(parse_id(line, "event").context("Could not parse u32 ID")?);
-                    // TODO: We should verify that the device actually supports KEY_NUMLOCK using evdev
-                    keyboard_detection = Detection::Finished;
-                    continue;
-                } else if line.is_empty() {
-                    // reset since we reached end of device info
-                    keyboard_detection = Detection::NotDetected;
-                }
-            }
-            _ => (),
-        }
-        if touchpad_detection == Detection::Finished && keyboard_detection == Detection::Finished {
-            break;
+/// Parse the contents of `/proc/bus/input/devices`, like
+/// [`read_proc_input`].
+fn parse_proc_input(data: &str) -> (Option<u32>, Vec<TouchpadInput>) {
+    let mut keyboard_ev_id = None;
+    let mut touchpads = Vec::new();
+    // every device is a block of lines, separated by an empty one
+    for block in data.split("\n\n") {
+        let field = |prefix| block.lines().find_map(|line| line.strip_prefix(prefix));
+        let Some(name) = field("N: Name=") else {
+            continue;
+        };
+        let name = name.trim_matches('"');
+        let Some(evdev_id) = field("H: Handlers=").and_then(|line| parse_id(line, "event").ok())
+        else {
+            continue;
+        };
+        if is_touchpad_name(name) {
+            touchpads.push(TouchpadInput {
+                name: name.to_owned(),
+                evdev_id,
+                i2c_id: field("S: Sysfs=").and_then(|line| parse_id(line, "i2c-").ok()),
+            });
+        } else if keyboard_ev_id.is_none() && is_keyboard_name(name) {
+            // TODO: We should verify that the device actually supports KEY_NUMLOCK using evdev
+            keyboard_ev_id = Some(evdev_id);
         }
     }
-    Ok((
-        keyboard_ev_id.ok_or(anyhow!("Can't find keyboard evdev"))?,
-        touchpad_ev_id.ok_or(anyhow!("Can't find touchpad evdev"))?,
-        touchpad_i2c_id.ok_or(anyhow!("Can't find touchpad I2C ID"))?,
-    ))
+    // ASUE and ASUP before ELAN, keeping the order of /proc otherwise
+    touchpads.sort_by_key(|touchpad| touchpad_rank(&touchpad.name));
+    (keyboard_ev_id, touchpads)
 }
 
-pub(crate) fn open_input_evdev(evdev_id: u32) -> Result<Device> {
-    let path = format!("/dev/input/event{}", evdev_id);
+/// Parse `/proc/bus/input/devices` to find the keyboard and touchpad devices.
+/// Returns the evdev handle for keybard, if it could be found, along with
+/// every touchpad that was found, most likely first.
+pub(crate) fn read_proc_input() -> Result<(Option<u32>, Vec<TouchpadInput>)> {
+    let data = std::fs::read_to_string("/proc/bus/input/devices")
+        .context("Could not read devices file")?;
+    Ok(parse_proc_input(&data))
+}
+
+fn open_input_evdev(evdev_id: u32) -> Result<Device> {
+    open_input_path(Path::new(&format!("/dev/input/event{}", evdev_id)))
+}
+
+fn open_input_path(path: &Path) -> Result<Device> {
     let file = OpenOptions::new()
         .read(true)
         .write(true)
         .custom_flags(libc::O_NONBLOCK)
-        .open(&path)
-        .with_context(|| path.clone())
+        .open(path)
+        .with_context(|| path.display().to_string())
         .context("Couldn't open device event handle")?;
     Device::new_from_file(file)
-        .with_context(|| path)
+        .with_context(|| path.display().to_string())
         .context("Unable to open evdev device")
 }
 
+/// Open the keyboard evdev, `forced` if given, e.g. from the config.
+pub(crate) fn open_keyboard(keyboard_ev_id: Option<u32>, forced: Option<&Path>) -> Result<Device> {
+    match (forced, keyboard_ev_id) {
+        (Some(path), _) => open_input_path(path),
+        (None, Some(evdev_id)) => open_input_evdev(evdev_id),
+        (None, None) => Err(anyhow!("Can't find keyboard evdev")),
+    }
+}
+
+/// Whether the evdev reports the multitouch positions that the numpad is
+/// driven by.
+fn has_touch_axes(evdev: &Device) -> bool {
+    [EV_ABS::ABS_MT_POSITION_X, EV_ABS::ABS_MT_POSITION_Y]
+        .into_iter()
+        .all(|axis| evdev.has_event_code(&EventCode::EV_ABS(axis)))
+}
+
+/// Open the touchpad evdev, returning it along with the I2C bus that
+/// `/proc` lists for it.
+///
+/// `forced` is used instead of the `touchpads` if given, e.g. from the
+/// config. Otherwise, if there are several of them, the first one with the
+/// multitouch axes is picked.
+pub(crate) fn open_touchpad(
+    touchpads: &[TouchpadInput],
+    forced: Option<&Path>,
+) -> Result<(Device, Option<u32>)> {
+    if let Some(path) = forced {
+        let evdev = open_input_path(path)?;
+        if !has_touch_axes(&evdev) {
+            return Err(anyhow!(
+                "{} doesn't report touch positions, is it a touchpad?",
+                path.display()
+            ));
+        }
+        // a link like /dev/input/by-path/... leads to an eventN that /proc knows
+        let evdev_id = std::fs::canonicalize(path)
+            .ok()
+            .and_then(|path| parse_id(&path.to_string_lossy(), "event").ok());
+        let i2c_id = touchpads
+            .iter()
+            .find(|touchpad| Some(touchpad.evdev_id) == evdev_id)
+            .and_then(|touchpad| touchpad.i2c_id);
+        return Ok((evdev, i2c_id));
+    }
+    if touchpads.len() > 1 {
+        for touchpad in touchpads {
+            info!(
+                "Found touchpad {} at /dev/input/event{}",
+                touchpad.name, touchpad.evdev_id
+            );
+        }
+    }
+    for touchpad in touchpads {
+        match open_input_evdev(touchpad.evdev_id) {
+            Ok(evdev) if has_touch_axes(&evdev) => return Ok((evdev, touchpad.i2c_id)),
+            Ok(_) => debug!("{} doesn't report touch positions", touchpad.name),
+            Err(err) if touchpads.len() > 1 => debug!("{:#}", err),
+            Err(err) => return Err(err),
+        }
+    }
+    Err(anyhow!("Can't find touchpad evdev"))
+}
+
+/// Get the model names of the laptop from DMI: the product name, followed
+/// by the board name.
+///
+/// Only fails if neither can be read.
+pub(crate) fn read_model_names() -> Result<Vec<String>> {
+    let mut names = Vec::new();
+    let mut last_err = None;
+    for field in ["product_name", "board_name"] {
+        let path = format!("/sys/class/dmi/id/{}", field);
+        match std::fs::read_to_string(&path) {
+            Ok(name) if !name.trim().is_empty() => names.push(name.trim().to_owned()),
+            Ok(_) => (),
+            Err(err) => last_err = Some(anyhow!(err).context(format!("Could not read {}", path))),
+        }
+    }
+    match last_err {
+        Some(err) if names.is_empty() => Err(err),
+        _ => Ok(names),
+    }
+}
+
 pub(crate) fn get_touchpad_bbox(touchpad_evdev: &Device) -> Result<BBox> {
     let absx = touchpad_evdev
         .abs_info(&EventCode::EV_ABS(EV_ABS::ABS_X))
@@ -149,3 +207,46 @@ pub(crate) fn get_touchpad_bbox(touchpad_evdev: &Device) -> Result<BBox> {
         absy.maximum,
     ))
 }
+
+#[test]
+fn test_parse_proc_input() {
+    let data = "\
+I: Bus=0011 Vendor=0001 Product=0001 Version=ab83
+N: Name=\"AT Translated Set 2 keyboard\"
+S: Sysfs=/devices/platform/i8042/serio0/input/input3
+H: Handlers=sysrq kbd leds event3
+
+I: Bus=0018 Vendor=04f3 Product=3101 Version=0100
+N: Name=\"ELAN1406:00 04F3:3101 Touchpad\"
+S: Sysfs=/devices/pci0000:00/0000:00:15.0/i2c_designware.0/i2c-0/i2c-ELAN1406:00/input/input15
+H: Handlers=mouse1 event14
+
+I: Bus=0018 Vendor=04f3 Product=3101 Version=0100
+N: Name=\"ELAN1406:00 04F3:3101 Mouse\"
+S: Sysfs=/devices/pci0000:00/0000:00:15.0/i2c_designware.0/i2c-0/i2c-ELAN1406:00/input/input14
+H: Handlers=mouse0 event13
+
+I: Bus=0018 Vendor=0b05 Product=0220 Version=0100
+N: Name=\"ASUE140D:00 04F3:31B9 Touchpad\"
+S: Sysfs=/devices/pci0000:00/0000:00:15.1/i2c_designware.1/i2c-1/i2c-ASUE140D:00/input/input21
+H: Handlers=mouse2 event16
+";
+    let (keyboard_ev_id, touchpads) = parse_proc_input(data);
+    assert_eq!(keyboard_ev_id, Some(3));
+    assert_eq!(
+        touchpads,
+        [
+            TouchpadInput {
+                name: "ASUE140D:00 04F3:31B9 Touchpad".to_owned(),
+                evdev_id: 16,
+                i2c_id: Some(1),
+            },
+            TouchpadInput {
+                name: "ELAN1406:00 04F3:3101 Touchpad".to_owned(),
+                evdev_id: 14,
+                i2c_id: Some(0),
+            },
+        ]
+    );
+    assert_eq!(parse_proc_input(""), (None, Vec::new()));
+}
@@ -5,7 +5,7 @@ use evdev_rs::{
     DeviceWrapper, InputEvent, TimeVal, UInputDevice, UninitDevice,
     enums::{EV_KEY, EV_SYN, EventCode},
 };
-use log::trace;
+use log::debug;
 
 use crate::numpad_layout::NumpadLayout;
 
@@ -22,18 +22,21 @@ impl std::fmt::Debug for DummyKeyboard {
 }
 
 impl DummyKeyboard {
-    pub(crate) fn new(layout: &NumpadLayout) -> Result<Self> {
+    /// Create a keyboard that can press the keys of `layout`, and `extra_keys`.
+    pub(crate) fn new(layout: &NumpadLayout, extra_keys: &[EV_KEY]) -> Result<Self> {
         let dev = UninitDevice::new().context("Unable to create uninit evdev device.")?;
         dev.set_name("asus_numpad");
         let default_keys = [EV_KEY::KEY_LEFTSHIFT, EV_KEY::KEY_NUMLOCK, EV_KEY::KEY_CALC];
-        for key in default_keys {
+        for key in default_keys.iter().chain(extra_keys).copied() {
             dev.enable(EventCode::EV_KEY(key))
                 .with_context(|| format!("Unable to enable key {:?}", key))?;
         }
         for row in layout.keys().iter() {
-            for key in row {
-                dev.enable(EventCode::EV_KEY(*key))
-                    .with_context(|| format!("Unable to enable key {:?}", key))?;
+            for &key in row.iter().flatten() {
+                for code in layout.key_codes(key) {
+                    dev.enable(EventCode::EV_KEY(code))
+                        .with_context(|| format!("Unable to enable key {:?}", code))?;
+                }
             }
         }
         let udev = UInputDevice::create_from_device(&dev).map_err(|err| {
@@ -61,12 +64,12 @@ pub(crate) trait KeyEvents {
     fn multi_keyup(&self, keys: &[EV_KEY]);
 
     fn keypress(&self, key: EV_KEY) {
-        trace!("Pressing {:?}", key);
+        debug!("Pressing {:?}", key);
         self.keydown(key);
         self.keyup(key);
     }
     fn multi_keypress(&self, keys: &[EV_KEY]) {
-        trace!("Pressing {:?}", keys);
+        debug!("Pressing {:?}", keys);
         self.multi_keydown(keys);
         self.multi_keyup(keys);
     }
@@ -133,7 +136,8 @@ impl KeyEvents for DummyKeyboard {
     }
 
     fn multi_keyup(&self, keys: &[EV_KEY]) {
-        for key in keys {
+        // release modifiers last
+        for key in keys.iter().rev() {
             self.udev
                 .write_event(&InputEvent::new(
                     &Self::DUMMY_TIMEVAL,
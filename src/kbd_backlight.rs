@@ -0,0 +1,105 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result, anyhow};
+
+#[cfg(test)]
+use crate::testing::TempDir;
+use crate::touchpad_i2c::Brightness;
+
+const SYSFS_LEDS: &str = "/sys/class/leds";
+
+fn read_value(path: &Path) -> Result<u32> {
+    std::fs::read_to_string(path)
+        .with_context(|| format!("Could not read {}", path.display()))?
+        .trim()
+        .parse()
+        .with_context(|| format!("Invalid value in {}", path.display()))
+}
+
+/// Map a keyboard backlight level from `0..=max` onto the numpad levels.
+fn to_numpad_brightness(value: u32, max: u32) -> Brightness {
+    match value {
+        0 => Brightness::Zero,
+        _ if value * 3 <= max => Brightness::Low,
+        _ if value * 3 <= max * 2 => Brightness::Half,
+        _ => Brightness::Full,
+    }
+}
+
+/// The keyboard backlight LED, so that the numpad can follow it.
+#[derive(Debug)]
+pub(crate) struct KbdBacklight {
+    brightness_path: PathBuf,
+    max: u32,
+    /// The value that was read last
+    last: Option<u32>,
+}
+
+impl KbdBacklight {
+    /// Find the LED named like `asus::kbd_backlight`.
+    pub(crate) fn find() -> Result<Self> {
+        Self::find_in(Path::new(SYSFS_LEDS))
+    }
+
+    fn find_in(leds_dir: &Path) -> Result<Self> {
+        let entries = std::fs::read_dir(leds_dir)
+            .with_context(|| format!("Could not list {}", leds_dir.display()))?;
+        let dir = entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .find(|path| {
+                path.file_name()
+                    .and_then(|name| name.to_str())
+                    .is_some_and(|name| name.ends_with("::kbd_backlight"))
+            })
+            .ok_or_else(|| anyhow!("No keyboard backlight in {}", leds_dir.display()))?;
+        let max = read_value(&dir.join("max_brightness"))?;
+        if max == 0 {
+            return Err(anyhow!("Keyboard backlight {} can't be lit", dir.display()));
+        }
+        Ok(Self {
+            brightness_path: dir.join("brightness"),
+            max,
+            last: None,
+        })
+    }
+
+    /// Read the keyboard backlight, returning the numpad brightness to match
+    /// it if it changed since the last time.
+    pub(crate) fn changed(&mut self) -> Result<Option<Brightness>> {
+        let value = read_value(&self.brightness_path)?;
+        if self.last == Some(value) {
+            return Ok(None);
+        }
+        self.last = Some(value);
+        Ok(Some(to_numpad_brightness(value, self.max)))
+    }
+}
+
+#[test]
+fn test_to_numpad_brightness() {
+    assert_eq!(to_numpad_brightness(0, 3), Brightness::Zero);
+    assert_eq!(to_numpad_brightness(1, 3), Brightness::Low);
+    assert_eq!(to_numpad_brightness(2, 3), Brightness::Half);
+    assert_eq!(to_numpad_brightness(3, 3), Brightness::Full);
+    assert_eq!(to_numpad_brightness(1, 255), Brightness::Low);
+    assert_eq!(to_numpad_brightness(255, 255), Brightness::Full);
+}
+
+#[test]
+fn test_kbd_backlight() {
+    let dir = TempDir::new("leds");
+    let leds = dir.path();
+    assert!(KbdBacklight::find_in(leds).is_err());
+    let led = leds.join("asus::kbd_backlight");
+    std::fs::create_dir_all(&led).unwrap();
+    std::fs::create_dir_all(leds.join("input3::capslock")).unwrap();
+    std::fs::write(led.join("max_brightness"), "3\n").unwrap();
+    std::fs::write(led.join("brightness"), "2\n").unwrap();
+
+    let mut backlight = KbdBacklight::find_in(leds).unwrap();
+    assert_eq!(backlight.changed().unwrap(), Some(Brightness::Half));
+    assert_eq!(backlight.changed().unwrap(), None);
+    std::fs::write(led.join("brightness"), "0\n").unwrap();
+    assert_eq!(backlight.changed().unwrap(), Some(Brightness::Zero));
+}
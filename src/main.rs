@@ -1,22 +1,63 @@
+mod als;
+mod calibrate;
 mod config;
+mod contacts;
+mod control;
+#[cfg(feature = "dbus")]
+mod dbus;
 mod devices;
 mod dummy_keyboard;
+mod kbd_backlight;
 mod numpad_layout;
+mod output;
+mod pointer;
+mod power;
+mod power_supply;
+mod service;
+#[cfg(feature = "session")]
+mod session;
+mod signals;
+mod startup;
+mod state;
+mod status_file;
+mod tap;
+#[cfg(test)]
+mod testing;
 mod touchpad_i2c;
+mod udev;
 mod util;
 
 use std::fmt::Display;
-use std::hint::unreachable_unchecked;
-use std::os::unix::io::AsRawFd;
+use std::io::Write;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::process::CommandExt;
+use std::path::PathBuf;
 use std::process::Command;
+use std::time::{Duration, Instant};
 
-use crate::config::{Config, CustomCommand};
-use crate::devices::{get_touchpad_bbox, open_input_evdev, read_proc_input};
+use crate::als::AmbientLight;
+use crate::config::{ActivationMode, Config, ConfigWatcher, CustomCommand, Overrides};
+use crate::contacts::{Contact, Contacts, PalmLimits};
+use crate::control::{Command as ControlCommand, ControlFifo, ControlSocket, Status, send_command};
+use crate::devices::{
+    get_touchpad_bbox, open_keyboard, open_touchpad, read_model_names, read_proc_input,
+};
 use crate::dummy_keyboard::{DummyKeyboard, KeyEvents};
-use crate::numpad_layout::NumpadLayout;
-use crate::touchpad_i2c::{Brightness, TouchpadI2C};
+use crate::kbd_backlight::KbdBacklight;
+use crate::numpad_layout::{BBox, CellCommand, NumpadLayout, SupportedLayout};
+use crate::output::{I2CBus, Keyboard};
+use crate::pointer::PointerMirror;
+use crate::power::ResumeWatcher;
+use crate::power_supply::AcAdapter;
+use crate::signals::{SignalAction, SignalPipe};
+use crate::startup::{Startup, restore_brightness};
+use crate::state::State;
+use crate::status_file::{FileStatus, StatusFile};
+use crate::tap::{Tap, ToggleGuard, TouchDown};
+use crate::touchpad_i2c::{Brightness, BrightnessCycler, DeviceError, TouchpadI2C, is_device_lost};
 use crate::util::{CustomDuration, ElapsedSince};
-use anyhow::{Context, Result};
+use anyhow::{Context, Result, anyhow};
+use clap::{ArgGroup, Parser, Subcommand};
 use evdev_rs::{
     Device, DeviceWrapper, InputEvent, ReadFlag, TimeVal,
     enums::{EV_ABS, EV_KEY, EV_LED, EV_MSC, EventCode},
@@ -50,8 +91,9 @@ impl Point {
 }
 
 /// Represents the key being pressed currently
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
 pub(crate) enum CurKey {
+    #[default]
     None,
     Numlock,
     Calc,
@@ -66,12 +108,6 @@ impl CurKey {
     }
 }
 
-impl Default for CurKey {
-    fn default() -> Self {
-        Self::None
-    }
-}
-
 #[derive(Debug)]
 struct TouchpadState {
     pos: Point,
@@ -79,12 +115,37 @@ struct TouchpadState {
     numlock: bool,
     cur_key: CurKey,
     tap_started_at: TimeVal,
-    tap_start_pos: Point,
+    tap: Tap,
     tapped_outside_numlock_bbox: bool,
     finger_dragged_too_much: bool,
     dragged_finger_lifted_at: TimeVal,
-    brightness: Brightness,
+    /// Start of the last quick tap on the calc bbox, for detecting double taps
+    calc_tapped_at: TimeVal,
+    /// When the finger last entered the calc bbox, for holding it to cycle brightness
+    calc_hold_started_at: Option<TimeVal>,
+    /// Holding the calc bbox has cycled the brightness during this touch
+    calc_cycled: bool,
+    /// When a finger came to rest on each of the numlock and calc bboxes, for
+    /// toggling with both corners
+    both_corners_held_at: Option<TimeVal>,
+    /// Holding both corners has toggled the numpad, until a finger leaves them
+    both_corners_toggled: bool,
+    brightness: BrightnessCycler,
     calc_open: bool,
+    /// The touch started outside of the keys, so the grab was released for it
+    pointer_passthrough: bool,
+    /// The finger is on the brightness slider
+    sliding: bool,
+    /// When the slider last changed the brightness, to not flood the bus
+    slider_moved_at: TimeVal,
+    /// When the held numpad key was pressed or last repeated, unless it stopped repeating
+    key_pressed_at: Option<TimeVal>,
+    /// The held numpad key has started repeating
+    key_repeating: bool,
+    /// The keycodes sent for the held numpad key, so that the same ones are released
+    pressed_keys: Vec<EV_KEY>,
+    /// The touched numpad key waits to be pressed until the touch can't be a swipe
+    key_pending: bool,
 }
 
 impl TouchpadState {
@@ -106,101 +167,880 @@ impl Default for TouchpadState {
                 tv_sec: 0,
                 tv_usec: 0,
             },
-            tap_start_pos: Default::default(),
+            tap: Default::default(),
             tapped_outside_numlock_bbox: false,
             finger_dragged_too_much: false,
             dragged_finger_lifted_at: TimeVal {
                 tv_sec: 0,
                 tv_usec: 0,
             },
+            calc_tapped_at: TimeVal {
+                tv_sec: 0,
+                tv_usec: 0,
+            },
+            calc_hold_started_at: None,
+            calc_cycled: false,
+            both_corners_held_at: None,
+            both_corners_toggled: false,
             brightness: Default::default(),
             calc_open: false,
+            pointer_passthrough: false,
+            sliding: false,
+            slider_moved_at: TimeVal {
+                tv_sec: 0,
+                tv_usec: 0,
+            },
+            key_pressed_at: None,
+            key_repeating: false,
+            pressed_keys: Vec::new(),
+            key_pending: false,
         }
     }
 }
 
 struct Numpad {
     evdev: Device,
+    /// Whether we have grabbed `evdev`, keeping its events from the rest of the system
+    grabbed: bool,
+    /// Moves the pointer with the touches outside of the keys while `evdev` is grabbed
+    pointer_mirror: Option<PointerMirror>,
+    /// Disables the touchpad in the display server while `evdev` is grabbed
+    #[cfg(feature = "session")]
+    session_pointer: Option<session::SessionPointer>,
     keyboard_evdev: Device,
-    touchpad_i2c: TouchpadI2C,
-    dummy_kb: DummyKeyboard,
+    touchpad_i2c: TouchpadI2C<I2CBus>,
+    dummy_kb: Keyboard,
     layout: NumpadLayout,
     state: TouchpadState,
+    /// Which contact on the touchpad is the finger on the numpad
+    contacts: Contacts,
     config: Config,
+    /// The options given on the command line, which take precedence over the config
+    overrides: Overrides,
+    config_watcher: Option<ConfigWatcher>,
+    /// The layout in the config was changed, but the numpad was in use
+    layout_outdated: bool,
+    control: Option<ControlSocket>,
+    fifo: Option<ControlFifo>,
+    signals: Option<SignalPipe>,
+    resume_watcher: Option<ResumeWatcher>,
+    #[cfg(feature = "dbus")]
+    dbus: Option<dbus::DbusService>,
+    /// Whether the numpad was on when we last exited
+    saved_enabled: Option<bool>,
+    /// What was last written to the state file, to only write it when something changes
+    saved_state: State,
+    /// Not written in a dry run
+    status_file: Option<StatusFile>,
+    /// The name of the layout in use, which lags behind the config while it is outdated
+    layout_name: String,
+    /// When the numpad was last turned on or a key on it was pressed
+    numpad_used_at: Instant,
+    /// When a key was last pressed on the keyboard, to ignore touches while typing
+    typed_at: Option<Instant>,
+    /// Ignores touches right after a gesture toggled the numpad
+    toggle_guard: ToggleGuard,
+    /// When a gesture on a corner last toggled the numpad or changed its brightness
+    gesture_at: Option<Instant>,
+    /// Touches can't toggle the numpad or change its brightness, e.g. during a presentation
+    locked: bool,
+    /// We turned the system NumLock on along with the numpad
+    numlock_pressed: bool,
+    /// The keyboard backlight, if the numpad follows it
+    kbd_backlight: Option<KbdBacklight>,
+    /// When to check the keyboard backlight next
+    kbd_backlight_check_at: Instant,
+    /// The ambient light sensor, if the brightness follows it
+    als: Option<AmbientLight>,
+    /// When to read the ambient light sensor next
+    als_check_at: Instant,
+    /// The AC adapter, if the brightness depends on it
+    ac_adapter: Option<AcAdapter>,
+    /// When to check the AC adapter next
+    ac_check_at: Instant,
+    /// The level from before the laptop was unplugged, to go back to
+    brightness_on_ac: Option<Brightness>,
+    /// When the brightness was last changed by the user, which automatic changes don't override
+    brightness_chosen_at: Option<Instant>,
 }
 
 impl std::fmt::Debug for Numpad {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.debug_struct("Numpad")
-            .field("evdev", &self.evdev.file())
+        let mut f = f.debug_struct("Numpad");
+        f.field("evdev", &self.evdev.file())
+            .field("pointer_mirror", &self.pointer_mirror)
             .field("keyboard_evdev", &self.keyboard_evdev.file())
             .field("dummy_keyboard", &self.dummy_kb)
             .field("touchpad_i2c", &self.touchpad_i2c)
             .field("state", &self.state)
             .field("layout", &self.layout)
-            .finish()
+            .field("config_watcher", &self.config_watcher)
+            .field("control", &self.control)
+            .field("signals", &self.signals)
+            .field("resume_watcher", &self.resume_watcher);
+        #[cfg(feature = "dbus")]
+        f.field("dbus", &self.dbus);
+        f.finish()
     }
 }
 
 impl Numpad {
-    const HOLD_DURATION: CustomDuration = CustomDuration::from_millis(250);
-
-    /// Min Euclidean distance (squared) that a finger needs to move for a tap
-    /// to be changed into a drag.  
-    const TAP_JITTER_DIST: i32 = 10000;
-
     /// Min Euclidean distance (squared) that a finger needs to be dragged to
     /// trigger the calculator key when numlock isn't active.
     const CALC_DRAG_DIST: i32 = 90000;
 
+    /// Max time between the starts of two taps on the calc bbox for them to
+    /// count as a double tap, which steps the brightness down.
+    const DOUBLE_TAP_DURATION: CustomDuration = CustomDuration::from_millis(400);
+
+    /// How often the brightness keeps cycling while the calc bbox is held.
+    const CYCLE_REPEAT_INTERVAL: CustomDuration = CustomDuration::from_millis(1000);
+
+    /// How long a brightness chosen by the user is kept, even if the laptop
+    /// is plugged in or unplugged.
+    const MANUAL_BRIGHTNESS_DURATION: Duration = Duration::from_secs(5);
+
+    /// Min time between two brightness changes from the slider.
+    const SLIDER_INTERVAL: CustomDuration = CustomDuration::from_millis(50);
+
+    /// How long to keep trying to restore the brightness after resuming,
+    /// while the touchpad comes back.
+    const RESUME_RESTORE_TIMEOUT: Duration = Duration::from_secs(2);
+
     fn new(
         evdev: Device,
         keyboard_evdev: Device,
-        touchpad_i2c: TouchpadI2C,
-        dummy_kb: DummyKeyboard,
+        touchpad_i2c: TouchpadI2C<I2CBus>,
+        dummy_kb: Keyboard,
         layout: NumpadLayout,
         config: Config,
+        overrides: Overrides,
     ) -> Self {
+        let config_watcher = Config::path(&overrides).and_then(|path| {
+            ConfigWatcher::new(&path)
+                .map_err(|err| warn!("Config changes won't be applied until restart: {:#}", err))
+                .ok()
+        });
+        let socket_path = config.socket_path();
+        // in a dry run, the socket of a daemon that is running stays with it
+        let control = (!overrides.dry_run)
+            .then(|| {
+                match ControlSocket::bind(&socket_path, config.socket_mode(), config.socket_group())
+                {
+                    Ok(control) => {
+                        info!("Listening for commands on {}", socket_path.display());
+                        Some(control)
+                    }
+                    Err(err) => {
+                        warn!("Control socket disabled: {:#}", err);
+                        None
+                    }
+                }
+            })
+            .flatten();
+        // creating the fifo replaces whatever is at its path
+        let fifo = config
+            .fifo_path()
+            .filter(|_| !overrides.dry_run)
+            .and_then(|path| {
+                match ControlFifo::create(path, config.socket_mode(), config.socket_group()) {
+                    Ok(fifo) => {
+                        info!("Reading commands from {}", path.display());
+                        Some(fifo)
+                    }
+                    Err(err) => {
+                        warn!("Control FIFO disabled: {:#}", err);
+                        None
+                    }
+                }
+            });
+        let signals = SignalPipe::install(&SignalAction::SIGNALS)
+            .map_err(|err| warn!("Signals won't be handled: {:#}", err))
+            .ok();
+        let resume_watcher = ResumeWatcher::new()
+            .map_err(|err| warn!("Brightness won't be restored after suspend: {:#}", err))
+            .ok();
+        #[cfg(feature = "dbus")]
+        let dbus = (!overrides.dry_run)
+            .then(|| {
+                dbus::DbusService::start()
+                    .map_err(|err| warn!("D-Bus service disabled: {:#}", err))
+                    .ok()
+            })
+            .flatten();
+        let saved = if config.persist_brightness() || config.persist_enabled() {
+            State::load(&State::path())
+        } else {
+            State::default()
+        };
+        // the command line takes precedence over the saved brightness too
+        let saved_brightness = saved
+            .brightness
+            .filter(|_| config.persist_brightness() && overrides.brightness.is_none());
+        let saved_enabled = saved.enabled.filter(|_| config.persist_enabled());
+        let saved_state = saved;
+        let status_file = (!overrides.dry_run).then(|| StatusFile::new(config.status_path()));
+        let layout_name = config.layout().name();
+        let kbd_backlight = Self::find_kbd_backlight(&config);
+        let als = Self::find_als(&config);
+        let ac_adapter = Self::find_ac_adapter(&config);
+        let pointer_mirror = config
+            .pointer_passthrough()
+            .then(|| {
+                PointerMirror::new(&evdev)
+                    .map_err(|err| warn!("Pointer passthrough disabled: {:#}", err))
+                    .ok()
+            })
+            .flatten();
+        #[cfg(feature = "session")]
+        let session_pointer = config
+            .disable_session_pointer()
+            .then(|| session::SessionPointer::new(evdev.name().unwrap_or_default()))
+            .flatten();
+        #[cfg(not(feature = "session"))]
+        if config.disable_session_pointer() {
+            warn!("disable_session_pointer needs the daemon to be built with --features session");
+        }
+        let locked = config.lock_activation();
         Self {
             evdev,
+            grabbed: false,
+            pointer_mirror,
+            #[cfg(feature = "session")]
+            session_pointer,
             keyboard_evdev,
             touchpad_i2c,
             dummy_kb,
             layout,
-            state: TouchpadState::default(),
+            state: TouchpadState {
+                brightness: BrightnessCycler::new(
+                    saved_brightness.unwrap_or_else(|| config.brightness()),
+                ),
+                ..Default::default()
+            },
+            contacts: Contacts::default(),
             config,
+            overrides,
+            config_watcher,
+            layout_outdated: false,
+            control,
+            fifo,
+            signals,
+            resume_watcher,
+            #[cfg(feature = "dbus")]
+            dbus,
+            saved_enabled,
+            saved_state,
+            status_file,
+            layout_name,
+            numpad_used_at: Instant::now(),
+            toggle_guard: ToggleGuard::default(),
+            gesture_at: None,
+            typed_at: None,
+            locked,
+            numlock_pressed: false,
+            kbd_backlight,
+            kbd_backlight_check_at: Instant::now(),
+            als,
+            als_check_at: Instant::now(),
+            ac_adapter,
+            ac_check_at: Instant::now(),
+            brightness_on_ac: None,
+            brightness_chosen_at: None,
+        }
+    }
+
+    /// Look for the keyboard backlight if the numpad should follow it.
+    fn find_kbd_backlight(config: &Config) -> Option<KbdBacklight> {
+        config.kbd_backlight_poll_interval()?;
+        KbdBacklight::find()
+            .map_err(|err| warn!("Not following the keyboard backlight: {:#}", err))
+            .ok()
+    }
+
+    /// Time left until the keyboard backlight should be checked, if it is followed.
+    fn kbd_backlight_timeout(&self) -> Option<Duration> {
+        self.kbd_backlight.as_ref()?;
+        Some(
+            self.kbd_backlight_check_at
+                .saturating_duration_since(Instant::now()),
+        )
+    }
+
+    /// Match the numpad brightness to the keyboard backlight, if it is due
+    /// to be checked and has changed.
+    ///
+    /// When the keyboard backlight is off, the numpad goes dark without being
+    /// disabled, and keeps its level for when the keyboard lights up again.
+    fn check_kbd_backlight(&mut self) -> Result<()> {
+        let (Some(kbd_backlight), Some(interval)) = (
+            &mut self.kbd_backlight,
+            self.config.kbd_backlight_poll_interval(),
+        ) else {
+            return Ok(());
+        };
+        if self.kbd_backlight_check_at > Instant::now() {
+            return Ok(());
+        }
+        self.kbd_backlight_check_at = Instant::now() + interval;
+        let brightness = match kbd_backlight.changed() {
+            Ok(Some(brightness)) => brightness,
+            Ok(None) => return Ok(()),
+            Err(err) => {
+                warn!("Not following the keyboard backlight anymore: {:#}", err);
+                self.kbd_backlight = None;
+                return Ok(());
+            }
+        };
+        debug!(
+            "Keyboard backlight changed, numpad brightness {}",
+            brightness
+        );
+        if brightness == Brightness::Zero {
+            self.set_brightness(brightness)
+        } else {
+            let brightness = self.state.brightness.set_level(brightness);
+            self.set_brightness(brightness)
+        }
+    }
+
+    /// Look for the ambient light sensor if the brightness should follow it.
+    fn find_als(config: &Config) -> Option<AmbientLight> {
+        config.als_poll_interval()?;
+        AmbientLight::find()
+            .map_err(|err| warn!("Not following the ambient light: {:#}", err))
+            .ok()
+    }
+
+    /// Time left until the ambient light sensor should be read, if it is followed.
+    fn als_timeout(&self) -> Option<Duration> {
+        self.als.as_ref()?;
+        Some(self.als_check_at.saturating_duration_since(Instant::now()))
+    }
+
+    fn find_ac_adapter(config: &Config) -> Option<AcAdapter> {
+        config.power_poll_interval()?;
+        AcAdapter::find()
+            .map_err(|err| warn!("Not following the power supply: {:#}", err))
+            .ok()
+    }
+
+    /// Time left until the AC adapter should be checked, if it is followed.
+    fn ac_adapter_timeout(&self) -> Option<Duration> {
+        self.ac_adapter.as_ref()?;
+        Some(self.ac_check_at.saturating_duration_since(Instant::now()))
+    }
+
+    /// Switch to `battery_brightness` when the laptop is unplugged, and back
+    /// when it is plugged in, unless the user just chose a brightness.
+    fn check_ac_adapter(&mut self) -> Result<()> {
+        let (Some(ac_adapter), Some(interval)) =
+            (&mut self.ac_adapter, self.config.power_poll_interval())
+        else {
+            return Ok(());
+        };
+        if self.ac_check_at > Instant::now() {
+            return Ok(());
+        }
+        self.ac_check_at = Instant::now() + interval;
+        let online = match ac_adapter.changed() {
+            Ok(Some(online)) => online,
+            Ok(None) => return Ok(()),
+            Err(err) => {
+                warn!("Not following the power supply anymore: {:#}", err);
+                self.ac_adapter = None;
+                return Ok(());
+            }
+        };
+        if self
+            .brightness_chosen_at
+            .is_some_and(|at| at.elapsed() < Self::MANUAL_BRIGHTNESS_DURATION)
+        {
+            debug!("Keeping the brightness that was just chosen");
+            self.brightness_on_ac = None;
+            return Ok(());
+        }
+        let level = if online {
+            match self.config.ac_brightness().or(self.brightness_on_ac.take()) {
+                Some(level) => level,
+                None => return Ok(()),
+            }
+        } else {
+            self.brightness_on_ac = Some(self.state.brightness.level());
+            match self.config.battery_brightness() {
+                Some(level) => level,
+                None => return Ok(()),
+            }
+        };
+        debug!(
+            "{}, numpad brightness {}",
+            if online { "Plugged in" } else { "On battery" },
+            level
+        );
+        let brightness = self.state.brightness.set_level(level);
+        self.set_brightness(brightness)
+    }
+
+    /// Set the brightness for the ambient light, if it is due to be read and
+    /// has crossed a threshold. Like with the keyboard backlight, a zero level
+    /// only darkens the numpad.
+    fn check_als(&mut self) -> Result<()> {
+        let (Some(als), Some(interval)) = (&mut self.als, self.config.als_poll_interval()) else {
+            return Ok(());
+        };
+        if self.als_check_at > Instant::now() {
+            return Ok(());
+        }
+        self.als_check_at = Instant::now() + interval;
+        let brightness = match als.changed(self.config.als_thresholds()) {
+            Ok(Some(brightness)) => brightness,
+            Ok(None) => return Ok(()),
+            Err(err) => {
+                warn!("Not following the ambient light anymore: {:#}", err);
+                self.als = None;
+                return Ok(());
+            }
+        };
+        debug!("Ambient light changed, numpad brightness {}", brightness);
+        if brightness == Brightness::Zero {
+            self.set_brightness(brightness)
+        } else {
+            let brightness = self.state.brightness.set_level(brightness);
+            self.set_brightness(brightness)
+        }
+    }
+
+    /// Time left until the numpad is turned off for being idle, if it is on.
+    fn idle_timeout(&self) -> Option<Duration> {
+        let auto_disable = self.config.auto_disable_duration()?;
+        self.state
+            .numlock
+            .then(|| auto_disable.saturating_sub(self.numpad_used_at.elapsed()))
+    }
+
+    /// Turn off the numpad if it hasn't been used for a while.
+    fn check_idle(&mut self) -> Result<()> {
+        if self.idle_timeout() == Some(Duration::ZERO) {
+            info!("Numpad unused for too long, turning it off");
+            self.toggle_numlock()?;
+        }
+        Ok(())
+    }
+
+    /// Act on the signals that have arrived, returning whether we should exit.
+    ///
+    /// SIGUSR1 cycles the brightness, and SIGUSR2 turns the numpad off.
+    /// SIGTERM and SIGINT leave the state to be saved by [`Numpad::shutdown`].
+    fn handle_signals(&mut self) -> Result<bool> {
+        let Some(signals) = &mut self.signals else {
+            return Ok(false);
+        };
+        for signum in signals.pending() {
+            match SignalAction::for_signal(signum) {
+                Some(SignalAction::Exit) => return Ok(true),
+                Some(SignalAction::CycleBrightness) => {
+                    debug!("SIGUSR1 - cycle brightness");
+                    self.brightness_chosen_at = Some(Instant::now());
+                    let brightness = self.state.brightness.cycle();
+                    self.set_brightness(brightness)?;
+                }
+                Some(SignalAction::TurnOff) => {
+                    debug!("SIGUSR2 - turn numlock off");
+                    if self.state.numlock {
+                        self.toggle_numlock()?;
+                    }
+                }
+                Some(SignalAction::ReloadConfig) => {
+                    info!("SIGHUP - reloading config");
+                    self.reload_config()?;
+                }
+                None => (),
+            }
+        }
+        Ok(false)
+    }
+
+    /// Put the touchpad back the way it was before a suspend.
+    ///
+    /// The firmware forgets the backlight while suspended, and the grab may
+    /// have been lost along with it. Failing to restore the backlight isn't
+    /// fatal, since it's set again on the next change.
+    ///
+    /// If the touchpad didn't come back at all, the error is returned so that
+    /// it is reconnected to, like when it goes away while in use.
+    fn on_resume(&mut self) -> Result<()> {
+        info!("Resumed from suspend, restoring the numpad");
+        match self.touchpad_i2c.get_brightness(false) {
+            Ok(brightness) => debug!("Brightness after resume: {}", brightness),
+            Err(err) => debug!("Can't tell the brightness after resume: {:#}", err),
+        }
+        if let Err(err) = self.touchpad_i2c.restore(Self::RESUME_RESTORE_TIMEOUT) {
+            if is_device_lost(&err) {
+                return Err(err.context("Touchpad didn't come back after resume"));
+            }
+            error!("Failed to restore the brightness: {:#}", err);
+        }
+        if self.state.numlock && self.grabs_pointer() {
+            // grabbing again fails if the grab is still there
+            let _ = self.evdev.grab(evdev_rs::GrabMode::Ungrab);
+            self.grabbed = false;
+            self.grab();
+        }
+        Ok(())
+    }
+
+    /// Turn off the backlight and release the touchpad before exiting.
+    ///
+    /// Failures are only logged, since we are exiting anyway.
+    fn shutdown(&mut self) {
+        if let Err(err) = self.touchpad_i2c.turn_off() {
+            warn!("Failed to turn off the backlight: {:#}", err);
+        }
+        self.ungrab();
+        self.save_state();
+        let mut status = self.file_status();
+        status.status.enabled = false;
+        if let Some(status_file) = &mut self.status_file {
+            status_file.update(status);
+        }
+    }
+
+    /// The fd that becomes readable when D-Bus commands arrive, or -1 if
+    /// there is no D-Bus service.
+    fn dbus_fd(&self) -> RawFd {
+        #[cfg(feature = "dbus")]
+        if let Some(dbus) = &self.dbus {
+            return dbus.as_raw_fd();
+        }
+        -1
+    }
+
+    /// Carry out the commands received over D-Bus.
+    #[cfg(feature = "dbus")]
+    fn handle_dbus_commands(&mut self) {
+        let Some(dbus) = &mut self.dbus else {
+            return;
+        };
+        for command in dbus.commands() {
+            if let Err(err) = self.handle_control_command(command) {
+                warn!("Failed to handle D-Bus command {:?}: {:#}", command, err);
+            }
+        }
+    }
+
+    /// Let D-Bus clients know about changes to the numpad state.
+    #[cfg(feature = "dbus")]
+    fn publish_dbus_state(&mut self) {
+        let (enabled, brightness) = (self.state.numlock, self.state.brightness.level());
+        if let Some(dbus) = &mut self.dbus {
+            if let Err(err) = dbus.publish(enabled, brightness) {
+                warn!("{:#}", err);
+            }
+        }
+    }
+
+    /// Carry out a command received on the control socket, returning the reply.
+    fn handle_control_command(&mut self, command: ControlCommand) -> Result<String> {
+        match command {
+            ControlCommand::Toggle => self.toggle_numlock()?,
+            ControlCommand::On if !self.state.numlock => self.toggle_numlock()?,
+            ControlCommand::Off if self.state.numlock => self.toggle_numlock()?,
+            ControlCommand::On | ControlCommand::Off => (),
+            ControlCommand::Brightness(level) => {
+                self.brightness_chosen_at = Some(Instant::now());
+                let brightness = self.state.brightness.set_level(level);
+                self.set_brightness(brightness)?;
+            }
+            ControlCommand::Cycle => {
+                self.brightness_chosen_at = Some(Instant::now());
+                let brightness = self.state.brightness.cycle();
+                self.set_brightness(brightness)?;
+            }
+            ControlCommand::Lock => self.set_locked(true),
+            ControlCommand::Unlock => self.set_locked(false),
+            ControlCommand::Status => {
+                return Ok(serde_json::to_string(&self.status())?);
+            }
+        }
+        Ok("ok".to_owned())
+    }
+
+    /// Lock or unlock the numlock and calc gestures.
+    ///
+    /// The numpad stays as it is, and can still be used and controlled
+    /// through signals or commands.
+    fn set_locked(&mut self, locked: bool) {
+        if locked != self.locked {
+            info!(
+                "{} the numpad gestures",
+                if locked { "Locked" } else { "Unlocked" }
+            );
+            self.locked = locked;
+        }
+    }
+
+    /// Apply the changes made to the config file.
+    ///
+    /// An invalid config is logged and ignored, keeping the previous one.
+    fn reload_config(&mut self) -> Result<()> {
+        let config = match Config::load(&self.overrides) {
+            Ok(config) => config,
+            Err(err) => {
+                error!("Keeping previous config: {:#}", err);
+                return Ok(());
+            }
+        };
+        if config == self.config {
+            return Ok(());
+        }
+        let old_config = std::mem::replace(&mut self.config, config);
+        self.touchpad_i2c
+            .set_retry(self.config.retry_attempts(), self.config.retry_delay());
+        self.touchpad_i2c
+            .set_protocol(self.config.brightness_protocol());
+        if old_config.i2c_id() != self.config.i2c_id() {
+            self.switch_i2c_bus();
+        }
+        if old_config.socket_path() != self.config.socket_path()
+            || old_config.socket_mode() != self.config.socket_mode()
+            || old_config.socket_group() != self.config.socket_group()
+        {
+            warn!("Control socket changes take effect after a restart");
+        }
+        if old_config.disable_numpad() != self.config.disable_numpad() {
+            self.touchpad_i2c
+                .enable_numpad(!self.config.disable_numpad())?;
+        }
+        if old_config.kbd_backlight_poll_interval().is_some()
+            != self.config.kbd_backlight_poll_interval().is_some()
+        {
+            self.kbd_backlight = Self::find_kbd_backlight(&self.config);
+            self.kbd_backlight_check_at = Instant::now();
+        }
+        if old_config.als_poll_interval().is_some() != self.config.als_poll_interval().is_some() {
+            self.als = Self::find_als(&self.config);
+            self.als_check_at = Instant::now();
+        }
+        if old_config.power_poll_interval().is_some() != self.config.power_poll_interval().is_some()
+        {
+            self.ac_adapter = Self::find_ac_adapter(&self.config);
+            self.ac_check_at = Instant::now();
+        }
+        if old_config.lock_activation() != self.config.lock_activation() {
+            self.set_locked(self.config.lock_activation());
+        }
+        if old_config.brightness() != self.config.brightness() {
+            let brightness = self.state.brightness.set_level(self.config.brightness());
+            self.set_brightness(brightness)?;
+        }
+        if old_config.layout() != self.config.layout()
+            || old_config.activation_corner() != self.config.activation_corner()
+            || old_config.toggle_corner_size_percent() != self.config.toggle_corner_size_percent()
+            || old_config.calc_keys() != self.config.calc_keys()
+            || old_config.key_style() != self.config.key_style()
+            || old_config.key_gap_percent() != self.config.key_gap_percent()
+            || old_config.remap() != self.config.remap()
+            || old_config.percent_keys() != self.config.percent_keys()
+            || old_config.equals_keys() != self.config.equals_keys()
+        {
+            self.layout_outdated = true;
+            // swapping the layout while a key is held would leave it pressed
+            if !self.state.numlock {
+                self.update_layout()?;
+            }
+        }
+        info!("Reloaded config: {:?}", self.config);
+        Ok(())
+    }
+
+    /// Open the touchpad's I2C bus again if the config now points elsewhere.
+    ///
+    /// The bus from `/proc` still takes precedence, as on startup, and one
+    /// given on the command line always does. If the new bus can't be opened,
+    /// the old one stays in use.
+    fn switch_i2c_bus(&mut self) {
+        if self.overrides.dry_run || self.overrides.i2c_id.is_some() {
+            return;
+        }
+        let proc_i2c_id = read_proc_input()
+            .map_err(|err| debug!("{:#}", err))
+            .ok()
+            .and_then(|(_, touchpads)| touchpads.iter().find_map(|touchpad| touchpad.i2c_id));
+        let result = find_i2c_id(proc_i2c_id, self.config.i2c_id()).and_then(|i2c_id| {
+            if i2c_id == self.touchpad_i2c.i2c_id() {
+                return Ok(());
+            }
+            let touchpad_i2c = open_touchpad_i2c(Some(i2c_id), &self.config, &self.overrides)?;
+            info!("Switching to touchpad I2C at /dev/i2c-{}", i2c_id);
+            self.touchpad_i2c = touchpad_i2c;
+            self.restore_after_reconnect()
+        });
+        if let Err(err) = result {
+            warn!(
+                "Keeping touchpad I2C at /dev/i2c-{}: {:#}",
+                self.touchpad_i2c.i2c_id(),
+                err
+            );
+        }
+    }
+
+    /// Switch to the layout from the config, if it was changed since it was loaded.
+    fn update_layout(&mut self) -> Result<()> {
+        if !self.layout_outdated {
+            return Ok(());
+        }
+        let bbox = get_touchpad_bbox(&self.evdev)?;
+        self.layout_outdated = false;
+        // e.g. a remapped position that is outside of the new layout
+        self.layout = match build_layout(&self.config, bbox) {
+            Ok(layout) => layout,
+            Err(err) => {
+                error!("Keeping the previous layout: {:#}", err);
+                return Ok(());
+            }
+        };
+        // the new layout might need keys that the dummy keyboard doesn't have
+        self.dummy_kb = create_keyboard(&self.layout, &self.config, self.overrides.dry_run)?;
+        self.layout_name = self.config.layout().name();
+        debug!("Switched to layout {:?}", self.config.layout());
+        Ok(())
+    }
+
+    /// The state reported by the `status` command.
+    fn status(&self) -> Status {
+        Status {
+            enabled: self.state.numlock,
+            brightness: self.state.brightness.level(),
+            locked: self.locked,
+        }
+    }
+
+    fn file_status(&self) -> FileStatus {
+        FileStatus {
+            status: self.status(),
+            layout: self.layout_name.clone(),
+        }
+    }
+
+    /// Remember the current brightness level and numpad state for the next start.
+    ///
+    /// Only writes the state file if they changed since the last time. Failures
+    /// are only logged, and not retried until the state changes again.
+    fn save_state(&mut self) {
+        if !self.config.persist_brightness() && !self.config.persist_enabled() {
+            return;
         }
+        let state = State {
+            brightness: Some(self.state.brightness.level())
+                .filter(|_| self.config.persist_brightness()),
+            enabled: Some(self.state.numlock).filter(|_| self.config.persist_enabled()),
+        };
+        if state == self.saved_state {
+            return;
+        }
+        let path = State::path();
+        match state.save(&path) {
+            Ok(()) => debug!("Saved state to {}", path.display()),
+            Err(err) => warn!("Failed to save state: {:#}", err),
+        }
+        self.saved_state = state;
+    }
+
+    /// Set the touchpad brightness, fading to it if configured.
+    fn set_brightness(&mut self, brightness: Brightness) -> Result<()> {
+        info!("Brightness {}", brightness);
+        match self.config.fade_duration() {
+            Some(duration) => self.touchpad_i2c.set_brightness_faded(brightness, duration),
+            None => self.touchpad_i2c.set_brightness(brightness),
+        }
+    }
+
+    /// Toggle numlock for a finger on the touchpad, which shouldn't touch the
+    /// numpad again as it lifts.
+    fn toggle_numlock_by_gesture(&mut self) -> Result<()> {
+        self.toggle_guard.toggled(Instant::now());
+        self.toggle_numlock()
     }
 
     /// Toggle numlock when user presses the numlock bbox on touchpad.
     fn toggle_numlock(&mut self) -> Result<()> {
         if self.state.toggle_numlock() {
-            self.touchpad_i2c.set_brightness(self.state.brightness)?;
-            // don't grab touchpad - allow moving pointer even if active
+            info!("Numpad on");
+            if self.overrides.dry_run {
+                println!("Numpad on");
+            }
+            self.numpad_used_at = Instant::now();
+            // unless configured, don't grab touchpad - allow moving pointer even if active
+            if self.grabs_pointer() {
+                self.grab();
+            }
         } else {
-            self.touchpad_i2c.set_brightness(Brightness::Zero)?;
+            info!("Numpad off");
+            if self.overrides.dry_run {
+                println!("Numpad off");
+            }
             // we might still be grabbing the touchpad. release it.
             self.ungrab();
         }
-        // Tell the system that we want to toggle the numlock
-        self.dummy_kb.keypress(EV_KEY::KEY_NUMLOCK);
+        if self.config.numlock_sync().drives_system() {
+            self.sync_system_numlock();
+        }
+        // last, so that the numpad is toggled even if the touchpad doesn't respond
+        let brightness = self.state.brightness.toggle_off();
+        self.set_brightness(brightness)?;
+        if !self.state.numlock {
+            self.update_layout()?;
+        }
         Ok(())
     }
 
+    /// Press NumLock if the system state doesn't match the numpad.
+    ///
+    /// NumLock that was already on when the numpad was turned on is left on,
+    /// unless the numpad follows the system state and would turn on again.
+    fn sync_system_numlock(&mut self) {
+        let led = self
+            .keyboard_evdev
+            .event_value(&EventCode::EV_LED(EV_LED::LED_NUML))
+            .map(|value| value != 0);
+        let press = if self.state.numlock {
+            self.numlock_pressed = led != Some(true);
+            self.numlock_pressed
+        } else if self.config.numlock_sync().follows_system() {
+            led != Some(false)
+        } else {
+            std::mem::take(&mut self.numlock_pressed) && led != Some(false)
+        };
+        if press {
+            // Tell the system that we want to toggle the numlock
+            self.dummy_kb.keypress(EV_KEY::KEY_NUMLOCK);
+        }
+    }
+
     /// Handle numlock pressed *from an external keyboard*.
     ///
     /// This is to keep the touchpad state in sync with system's numlock.
     fn handle_numlock_pressed(&mut self, val: i32) -> Result<()> {
         if val == 0 {
-            debug!("Setting numpad off");
+            info!("Setting numpad off");
             self.state.numlock = false;
             // we might still be grabbing the touchpad. release it.
             self.ungrab();
-            self.touchpad_i2c.set_brightness(Brightness::Zero)
+            let brightness = self.state.brightness.set_off(true);
+            self.set_brightness(brightness)?;
+            self.update_layout()
         } else {
-            debug!("Setting numpad on {}", self.state.brightness);
+            let level = self.state.brightness.level();
+            info!("Setting numpad on {} ({}%)", level, level.to_percent());
+            if !self.state.numlock {
+                self.numpad_used_at = Instant::now();
+            }
             self.state.numlock = true;
-            self.touchpad_i2c.set_brightness(self.state.brightness)
+            if self.grabs_pointer() {
+                self.grab();
+            }
+            let brightness = self.state.brightness.set_off(false);
+            self.set_brightness(brightness)
         }
         // The numlock has already been toggled on the system- no need to press
         // the Num_Lock evkey.
@@ -210,19 +1050,40 @@ impl Numpad {
     fn initialize_numlock(&mut self) -> Result<()> {
         if self.config.disable_numpad() {
             debug!("Skipping numlock init as numpad control is disabled");
-            return Ok(());
+            // the backlight might have been left on by a previous run
+            return self.touchpad_i2c.enable_numpad(false);
         }
-        let init_numlock = self
-            .keyboard_evdev
-            .event_value(&EventCode::EV_LED(EV_LED::LED_NUML));
+        // the numpad still comes up as configured, it just stays dark
+        if let Err(err) = self.set_initial_numlock() {
+            error!("Failed to set the brightness at startup: {:#}", err);
+        }
+        Ok(())
+    }
+
+    fn set_initial_numlock(&mut self) -> Result<()> {
+        restore_brightness(&mut self.touchpad_i2c, self.state.brightness.get());
+
+        let init_numlock = if self.config.numlock_sync().follows_system() {
+            self.keyboard_evdev
+                .event_value(&EventCode::EV_LED(EV_LED::LED_NUML))
+        } else {
+            // start as if numlock was off, since the numpad doesn't follow it
+            Some(0)
+        };
         match init_numlock {
             Some(init_numlock) => {
-                if init_numlock != 0 {
-                    if self.config.disable_numlock_on_start() {
-                        self.dummy_kb.keypress(EV_KEY::KEY_NUMLOCK);
-                    } else {
-                        self.handle_numlock_pressed(init_numlock)?;
-                    }
+                // the command line takes precedence over the saved state, and that over the config
+                let startup = Startup::new(
+                    init_numlock != 0,
+                    self.overrides.enabled.or(self.saved_enabled),
+                    self.config.enable_on_start(),
+                    self.config.disable_numlock_on_start(),
+                );
+                match startup {
+                    Startup::Enable => self.toggle_numlock()?,
+                    Startup::FollowNumlock => self.handle_numlock_pressed(init_numlock)?,
+                    Startup::DisableNumlock => self.dummy_kb.keypress(EV_KEY::KEY_NUMLOCK),
+                    Startup::Off => {}
                 }
             }
             None => error!(
@@ -238,14 +1099,65 @@ impl Numpad {
         Ok(())
     }
 
+    /// Keep the touchpad events from moving the pointer.
+    ///
+    /// The kernel drops the grab when we exit, even if it's not released.
     fn grab(&mut self) {
+        if self.grabbed {
+            return;
+        }
         debug!("Grabbing");
-        self.evdev
-            .grab(evdev_rs::GrabMode::Grab)
-            .unwrap_or_else(|err| warn!("Failed to grab {}", err));
+        match self.evdev.grab(evdev_rs::GrabMode::Grab) {
+            Ok(()) => self.grabbed = true,
+            Err(err) => warn!("Failed to grab {}", err),
+        }
+        if let Some(mirror) = &mut self.pointer_mirror {
+            mirror.start(self.state.finger_state != FingerState::Lifted);
+        }
+        #[cfg(feature = "session")]
+        if let Some(session_pointer) = &mut self.session_pointer {
+            session_pointer.set_enabled(false);
+        }
+    }
+
+    /// Whether the touchpad is grabbed while the numpad is on, either to
+    /// freeze the pointer or to only let some touches through to it.
+    fn grabs_pointer(&self) -> bool {
+        #[cfg(feature = "session")]
+        if self.session_pointer.is_some() {
+            return true;
+        }
+        self.config.disable_pointer() || self.pointer_mirror.is_some()
+    }
+
+    /// Pass a touchpad event on to the pointer, if the touch it belongs to
+    /// started outside of the keys and corners.
+    fn forward_to_pointer(&mut self, ev: &InputEvent) {
+        let Some(mirror) = &mut self.pointer_mirror else {
+            return;
+        };
+        if !self.grabbed {
+            return;
+        }
+        let (layout, pos) = (&self.layout, self.state.pos);
+        mirror.forward(ev, || {
+            layout.in_margins(pos) && !layout.in_numlock_bbox(pos) && !layout.in_calc_bbox(pos)
+        });
     }
 
     fn ungrab(&mut self) {
+        if !self.grabbed {
+            return;
+        }
+        debug!("Ungrabbing");
+        self.grabbed = false;
+        if let Some(mirror) = &mut self.pointer_mirror {
+            mirror.release();
+        }
+        #[cfg(feature = "session")]
+        if let Some(session_pointer) = &mut self.session_pointer {
+            session_pointer.set_enabled(true);
+        }
         self.evdev
             .grab(evdev_rs::GrabMode::Ungrab)
             .unwrap_or_else(|err| warn!("Failed to ungrab {}", err));
@@ -257,23 +1169,9 @@ impl Numpad {
             CustomCommand::Keys(keys) => self.dummy_kb.multi_keypress(keys.as_slice()),
             CustomCommand::Command { cmd, args } => {
                 debug!("Running command {} with args {:?}", cmd, args);
-                let cmd = cmd.clone();
-                let args = args.clone();
-                // spawn a thread that waits for the proc to end
-                // ensures that all procs are reaped
-                std::thread::spawn(|| {
-                    match Command::new(cmd).args(args).spawn() {
-                        Ok(mut child) => {
-                            debug!("Started child proc: {}", child.id());
-                            if let Err(err) = child.wait() {
-                                warn!("Error while starting: {}", err);
-                            } else {
-                                trace!("Process ended");
-                            }
-                        }
-                        Err(err) => warn!("Error while starting: {}", err),
-                    };
-                });
+                let mut command = Command::new(cmd);
+                command.args(args);
+                spawn_detached(command);
             }
         }
     }
@@ -305,78 +1203,386 @@ impl Numpad {
         }
     }
 
-    fn on_lift(&mut self) {
+    /// Run the calc start command, or the stop command if it was started last.
+    fn toggle_calc(&mut self) {
+        if !self.state.calc_open {
+            self.start_calc();
+        } else {
+            self.stop_calc();
+        }
+        self.state.calc_open = !self.state.calc_open;
+    }
+
+    fn on_lift(&mut self) -> Result<()> {
         // end of tap
         debug!("End tap");
-        if self.state.cur_key == CurKey::Calc
-            && self.state.pos.dist_sq(self.state.tap_start_pos) >= Self::CALC_DRAG_DIST
-        {
-            if !self.state.calc_open {
-                self.start_calc();
-            } else {
-                self.stop_calc();
+        self.state.sliding = false;
+        // the hold was the whole gesture
+        if self.state.cur_key == CurKey::Calc && !std::mem::take(&mut self.state.calc_cycled) {
+            if self.state.pos.dist_sq(self.state.tap.start()) >= Self::CALC_DRAG_DIST {
+                self.toggle_calc();
+            } else if self.state.numlock
+                && !self.config.calc_on_hold()
+                && !self.locked
+                && self.config.one_finger_brightness()
+            {
+                // with calc on hold, brushing the corner shouldn't do anything
+                self.on_calc_tap()?;
             }
-            self.state.calc_open = !self.state.calc_open;
+        }
+        // a hold that toggled already leaves the finger in TouchStart
+        if self.state.cur_key == CurKey::Numlock
+            && self.state.finger_state == FingerState::Touching
+            && !self.state.tapped_outside_numlock_bbox
+            && !self.state.finger_dragged_too_much
+            && self.config.toggle_on_tap()
+            && self.config.activation_mode() == ActivationMode::SingleCorner
+            && self.debounce_gesture("toggle")
+        {
+            debug!("Tap on numlock - toggle numlock");
+            self.toggle_numlock_by_gesture()?;
         }
 
         if self.state.finger_state == FingerState::Touching {
             if let CurKey::Numpad(key) = self.state.cur_key {
-                debug!("Keyup {:?}", key);
-
-                if self.layout.needs_multikey(key) {
-                    self.dummy_kb.multi_keyup(&self.layout.multikeys(key));
-                } else {
-                    self.dummy_kb.keyup(key);
+                if std::mem::take(&mut self.state.key_pending)
+                    && !self.state.finger_dragged_too_much
+                    && !self
+                        .state
+                        .tap
+                        .swiped(&self.layout, self.config.swipe_cancel_percent())
+                {
+                    debug!("Keydown {:?} on lift", key);
+                    self.press_numpad_key(key);
                 }
+                debug!("Keyup {:?}", key);
+                self.release_numpad_key();
                 // if we ungrab here, it causes the pointer to jump
                 // so we only ungrab when finger is dragged
             }
         }
         self.state.cur_key.reset();
         self.state.finger_state = FingerState::Lifted;
+        if self.state.pointer_passthrough {
+            self.state.pointer_passthrough = false;
+            if self.state.numlock && self.grabs_pointer() {
+                self.grab();
+            }
+        }
+        Ok(())
     }
 
-    fn on_tap(&mut self, time: TimeVal) {
-        if self.state.finger_state == FingerState::Lifted {
-            // start of tap
-            debug!("Start tap");
-            self.state.finger_state = FingerState::TouchStart;
+    /// Whether a gesture on a corner can go ahead, remembering it if so.
+    ///
+    /// One that comes within `debounce_ms` of the last one is taken for the
+    /// touchpad bouncing, and suppressed.
+    fn debounce_gesture(&mut self, gesture: &str) -> bool {
+        if let Some(window) = self.config.debounce_duration() {
+            if self.gesture_at.is_some_and(|at| at.elapsed() < window) {
+                debug!(
+                    "Suppressed {} within {:?} of the last gesture",
+                    gesture, window
+                );
+                return false;
+            }
+        }
+        self.gesture_at = Some(Instant::now());
+        true
+    }
+
+    /// Step the brightness down if this quick tap on calc bbox follows another one.
+    fn on_calc_tap(&mut self) -> Result<()> {
+        if self
+            .state
+            .tap_started_at
+            .elapsed_since(self.state.calc_tapped_at)
+            <= Self::DOUBLE_TAP_DURATION
+            && self.debounce_gesture("double tap")
+        {
+            debug!("Double tap - cycle brightness down");
+            self.brightness_chosen_at = Some(Instant::now());
+            let brightness = self.state.brightness.cycle_down();
+            self.set_brightness(brightness)?;
+            // a third tap shouldn't count as another double tap
+            self.state.calc_tapped_at = TimeVal {
+                tv_sec: 0,
+                tv_usec: 0,
+            };
+        } else {
+            self.state.calc_tapped_at = self.state.tap_started_at;
+        }
+        Ok(())
+    }
+
+    fn on_tap(&mut self, time: TimeVal, on_numlock: bool) {
+        if self.state.finger_state == FingerState::Lifted {
+            // start of tap
+            debug!("Start tap");
+            self.state.finger_state = FingerState::TouchStart;
             self.state.tap_started_at = time;
-            self.state.tap_start_pos = self.state.pos;
+            self.state.tap = Tap::new(self.state.pos);
+            self.state.key_pending = false;
             self.state.tapped_outside_numlock_bbox = false;
             self.state.finger_dragged_too_much = false;
-            if self.state.numlock {
-                self.state.cur_key = match self.layout.get_key(self.state.pos) {
+            if self.state.numlock && !on_numlock {
+                self.state.cur_key = match self.state.tap.key(&self.layout) {
+                    Some(key) if self.typing() => {
+                        debug!("Ignoring {:?} while typing", key);
+                        CurKey::None
+                    }
                     Some(key) => {
                         self.grab();
                         self.state.finger_state = FingerState::Touching;
 
-                        debug!("Keydown {:?}", key);
-                        if self.layout.needs_multikey(key) {
-                            self.dummy_kb.multi_keydown(&self.layout.multikeys(key));
+                        self.numpad_used_at = Instant::now();
+                        self.state.key_repeating = false;
+                        if self.config.swipe_cancel_percent().is_some() {
+                            debug!("Touched {:?} - waiting for a swipe", key);
+                            self.state.key_pending = true;
+                            self.state.key_pressed_at = None;
                         } else {
-                            self.dummy_kb.keydown(key);
+                            debug!("Keydown {:?}", key);
+                            self.state.key_pressed_at = Some(time);
+                            self.press_numpad_key(key);
                         }
                         CurKey::Numpad(key)
                     }
-                    None => CurKey::None,
+                    None => {
+                        if let Some(command) = self.layout.get_command(self.state.pos) {
+                            run_cell_command(command);
+                            // keep the touch from clicking
+                            self.grab();
+                            self.numpad_used_at = Instant::now();
+                        } else if self.config.disable_pointer()
+                            && self.pointer_mirror.is_none()
+                            && self.layout.in_margins(self.state.pos)
+                            && !self.layout.in_numlock_bbox(self.state.pos)
+                            && !self.layout.in_calc_bbox(self.state.pos)
+                        {
+                            // let touches in the margins move the pointer
+                            self.state.pointer_passthrough = true;
+                            self.ungrab();
+                        }
+                        CurKey::None
+                    }
                 };
             }
         }
-        if self.layout.in_numlock_bbox(self.state.pos) && !self.config.disable_numpad() {
+        if on_numlock {
             debug!("In numlock - start");
             self.state.finger_state = FingerState::Touching;
             self.state.cur_key = CurKey::Numlock;
         } else {
-            if self.layout.in_calc_bbox(self.state.pos) {
+            if self.layout.in_calc_bbox(self.state.pos) && !self.config.disable_calc_key() {
                 debug!("In calc - start");
                 self.state.finger_state = FingerState::Touching;
                 self.state.cur_key = CurKey::Calc;
+                self.state.calc_hold_started_at = Some(time);
             }
             self.state.tapped_outside_numlock_bbox = true
         }
     }
 
+    /// Whether a key was pressed on the keyboard too recently for a touch on
+    /// the numpad to be anything but a palm.
+    fn typing(&self) -> bool {
+        self.config
+            .typing_timeout()
+            .zip(self.typed_at)
+            .is_some_and(|(timeout, typed_at)| typed_at.elapsed() < timeout)
+    }
+
+    /// Press the keycodes of `key`, modifiers first.
+    ///
+    /// Modifiers that are already held on the keyboard are left out, so that
+    /// releasing them doesn't cancel the ones held by the user.
+    fn press_numpad_key(&mut self, key: EV_KEY) {
+        let codes = self.layout.key_codes(key);
+        let Some((&code, modifiers)) = codes.split_last() else {
+            return;
+        };
+        let mut keys: Vec<_> = modifiers
+            .iter()
+            .copied()
+            .filter(|modifier| {
+                self.keyboard_evdev
+                    .event_value(&EventCode::EV_KEY(*modifier))
+                    .is_none_or(|value| value == 0)
+            })
+            .collect();
+        keys.push(code);
+        if self.overrides.dry_run {
+            if let Some((row, col)) = self.layout.get_cell(self.state.pos) {
+                println!(
+                    "Touched {:?} in row {}, column {} at {}",
+                    key,
+                    row + 1,
+                    col + 1,
+                    self.state.pos
+                );
+            }
+        }
+        self.dummy_kb.multi_keydown(&keys);
+        self.state.pressed_keys = keys;
+    }
+
+    /// Release the keycodes sent by [`Numpad::press_numpad_key`], in reverse.
+    fn release_numpad_key(&mut self) {
+        let keys = std::mem::take(&mut self.state.pressed_keys);
+        if !keys.is_empty() {
+            self.dummy_kb.multi_keyup(&keys);
+        }
+    }
+
+    /// Press the touched numpad key once the finger has been held on it for
+    /// the hold duration without swiping, or forget it if it did swipe.
+    fn update_pending_key(&mut self, time: TimeVal) {
+        let (FingerState::Touching, CurKey::Numpad(key), true) = (
+            self.state.finger_state,
+            self.state.cur_key,
+            self.state.key_pending,
+        ) else {
+            return;
+        };
+        if self
+            .state
+            .tap
+            .swiped(&self.layout, self.config.swipe_cancel_percent())
+        {
+            debug!("Swiped across {:?} - cancel it", key);
+            self.state.key_pending = false;
+        } else if time.elapsed_since(self.state.tap_started_at) >= self.config.hold_duration() {
+            debug!("Keydown {:?}", key);
+            self.state.key_pending = false;
+            self.state.key_pressed_at = Some(time);
+            self.press_numpad_key(key);
+        }
+    }
+
+    /// Repeat the held numpad key like a physical keyboard does, until the
+    /// finger moves off it.
+    fn update_key_repeat(&mut self, time: TimeVal) {
+        let (FingerState::Touching, CurKey::Numpad(key)) =
+            (self.state.finger_state, self.state.cur_key)
+        else {
+            return;
+        };
+        let (Some((delay, rate)), Some(pressed_at)) =
+            (self.config.key_repeat(), self.state.key_pressed_at)
+        else {
+            return;
+        };
+        // jitter across the border of the cell doesn't stop it
+        if self.state.tap.moved_off_key(
+            &self.layout,
+            self.state.pos,
+            self.config.drag_tolerance_percent(),
+        ) {
+            debug!("Left {:?} - stop repeating", key);
+            self.state.key_pressed_at = None;
+            return;
+        }
+        let interval = if self.state.key_repeating {
+            rate
+        } else {
+            delay
+        };
+        if time.elapsed_since(pressed_at) < interval {
+            return;
+        }
+        trace!("Repeat {:?}", key);
+        self.release_numpad_key();
+        self.press_numpad_key(key);
+        self.numpad_used_at = Instant::now();
+        self.state.key_pressed_at = Some(time);
+        self.state.key_repeating = true;
+    }
+
+    /// Toggle the numpad once a finger has been held on each of the numlock
+    /// and calc bboxes for the toggle hold duration.
+    ///
+    /// It takes exactly two fingers, and lifting either of them or moving it
+    /// off its corner starts over.
+    fn update_both_corners(&mut self, time: TimeVal) -> Result<()> {
+        let positions: Vec<_> = self.contacts.positions().collect();
+        let held = match positions[..] {
+            [a, b] => {
+                let layout = &self.layout;
+                (layout.in_numlock_bbox(a) && layout.in_calc_bbox(b))
+                    || (layout.in_numlock_bbox(b) && layout.in_calc_bbox(a))
+            }
+            _ => false,
+        };
+        if !held || self.config.disable_numpad() || self.locked {
+            if self.state.both_corners_held_at.take().is_some() && !self.state.both_corners_toggled
+            {
+                debug!("Left the corners - cancel the toggle");
+            }
+            self.state.both_corners_toggled = false;
+            return Ok(());
+        }
+        let held_at = match self.state.both_corners_held_at {
+            Some(held_at) => held_at,
+            None => {
+                debug!("Holding both corners - start");
+                // the first finger is on a corner, so it has no key or calc hold of its own
+                self.state.cur_key.reset();
+                *self.state.both_corners_held_at.insert(time)
+            }
+        };
+        if !self.state.both_corners_toggled
+            && time.elapsed_since(held_at) >= self.config.toggle_hold_duration()
+        {
+            self.state.both_corners_toggled = true;
+            if self.debounce_gesture("toggle") {
+                debug!("Hold finish on both corners - toggle numlock");
+                self.toggle_numlock_by_gesture()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Follow the finger with the brightness while it's on the slider.
+    ///
+    /// Sliding only starts when the finger comes out of the numlock bbox, so
+    /// that touching the top row of keys doesn't change the brightness.
+    fn update_slider(&mut self, time: TimeVal, height: i32) -> Result<()> {
+        let pct = match self.layout.slider_percent(self.state.pos, height) {
+            Some(pct)
+                if self.state.finger_state == FingerState::Touching
+                    && self.config.one_finger_brightness() =>
+            {
+                pct
+            }
+            _ => {
+                self.state.sliding = false;
+                return Ok(());
+            }
+        };
+        if !self.state.sliding {
+            if !self.state.numlock
+                || self.state.cur_key != CurKey::Numlock
+                || self.layout.in_numlock_bbox(self.state.pos)
+            {
+                return Ok(());
+            }
+            debug!("Start sliding");
+            self.state.sliding = true;
+        } else if time.elapsed_since(self.state.slider_moved_at) < Self::SLIDER_INTERVAL {
+            return Ok(());
+        }
+        let level = Brightness::from_percent_continuous(pct);
+        if level != self.state.brightness.level() {
+            trace!("Slider at {}%", pct);
+            self.state.slider_moved_at = time;
+            self.brightness_chosen_at = Some(Instant::now());
+            let brightness = self.state.brightness.set_level(level);
+            // fading would lag behind the finger
+            self.touchpad_i2c.set_brightness(brightness)?;
+        }
+        Ok(())
+    }
+
     fn handle_touchpad_event(&mut self, ev: InputEvent) -> Result<()> {
         // TODO: Double-taps when numpad is active should not be propagated.
         //       Need to grab/ungrab the device intelligently.
@@ -385,44 +1591,75 @@ impl Numpad {
         if !matches!(ev.event_code, EventCode::EV_MSC(EV_MSC::MSC_TIMESTAMP)) {
             trace!("TP {:?} {}", ev.event_code, ev.value);
         }
-        match ev.event_code {
-            EventCode::EV_ABS(EV_ABS::ABS_MT_POSITION_X) => {
+        let palm = PalmLimits {
+            size: self
+                .config
+                .palm_size_percent()
+                .map(|percent| self.layout.key_fraction(percent)),
+            pressure: self.config.palm_pressure(),
+        };
+        let contact = self.contacts.update(&ev.event_code, ev.value, palm);
+        match (ev.event_code, contact) {
+            // other fingers don't move the one on the numpad
+            (EventCode::EV_ABS(EV_ABS::ABS_MT_POSITION_X), _) if self.contacts.in_owner_slot() => {
                 self.state.pos.x = ev.value;
             }
-            EventCode::EV_ABS(EV_ABS::ABS_MT_POSITION_Y) => {
+            (EventCode::EV_ABS(EV_ABS::ABS_MT_POSITION_Y), _) if self.contacts.in_owner_slot() => {
                 self.state.pos.y = ev.value;
             }
-            EventCode::EV_KEY(EV_KEY::BTN_TOOL_FINGER) if ev.value == 0 => {
+            (_, Some(Contact::Palm)) => {
+                if let CurKey::Numpad(key) = self.state.cur_key {
+                    debug!("Palm on {:?} - cancel it", key);
+                    self.state.finger_dragged_too_much = true;
+                    self.on_lift()?;
+                }
+            }
+            (_, Some(Contact::Up)) => {
                 if !self.state.finger_dragged_too_much {
                     // only call on_lift if we did not already call it as a result of finger drag
-                    self.on_lift();
+                    self.on_lift()?;
                 } else {
                     self.state.dragged_finger_lifted_at = ev.time;
                 }
             }
-            EventCode::EV_KEY(EV_KEY::BTN_TOOL_FINGER) if ev.value == 1 => {
+            (_, Some(Contact::Down))
                 if !self.state.finger_dragged_too_much
                     || ev.time.elapsed_since(self.state.dragged_finger_lifted_at)
-                        >= Self::HOLD_DURATION
-                {
-                    self.on_tap(ev.time);
+                        >= self.config.hold_duration() =>
+            {
+                match self.toggle_guard.touch_down(
+                    Instant::now(),
+                    self.config.toggle_grace_duration(),
+                    self.layout.in_numlock_bbox(self.state.pos),
+                    !self.config.disable_numpad() && !self.locked,
+                ) {
+                    TouchDown::Ignored => debug!("Ignoring a touch right after toggling"),
+                    TouchDown::Numlock => self.on_tap(ev.time, true),
+                    TouchDown::Numpad => self.on_tap(ev.time, false),
                 }
             }
-            EventCode::EV_MSC(EV_MSC::MSC_TIMESTAMP) => {
-                // The toggle should happen automatically after HOLD_DURATION, even if user is
+            (EventCode::EV_MSC(EV_MSC::MSC_TIMESTAMP), _) => {
+                // The toggle should happen automatically after hold duration, even if user is
                 // still touching the numpad bbox.
                 if self.state.finger_state == FingerState::TouchStart {
-                    trace!("Touch {}", self.state.pos);
+                    debug!("Touch {}", self.state.pos);
                 }
+                // both coordinates of the frame are in by now
+                self.state.tap.move_to(self.state.pos);
 
                 if self.state.finger_state == FingerState::Touching
                     && !self.state.tapped_outside_numlock_bbox
                     && !self.config.disable_numpad()
+                    && self.config.activation_mode() == ActivationMode::SingleCorner
                 {
                     if self.layout.in_numlock_bbox(self.state.pos) {
-                        if ev.time.elapsed_since(self.state.tap_started_at) >= Self::HOLD_DURATION {
-                            debug!("Hold finish - toggle numlock");
-                            self.toggle_numlock()?;
+                        if ev.time.elapsed_since(self.state.tap_started_at)
+                            >= self.config.toggle_hold_duration()
+                        {
+                            if self.debounce_gesture("toggle") {
+                                debug!("Hold finish - toggle numlock");
+                                self.toggle_numlock_by_gesture()?;
+                            }
                             // If user doesn't lift the finger quickly, we don't want to keep
                             // toggling, so assume finger was moved.
                             // Can't do finger_state = Lifted, since that would start another tap
@@ -432,38 +1669,153 @@ impl Numpad {
                         self.state.tapped_outside_numlock_bbox = true;
                     }
                 }
-                if self.state.numlock
-                    && self.state.cur_key == CurKey::Calc
-                    && self.layout.in_calc_bbox(self.state.pos)
-                    && ev.time.elapsed_since(self.state.tap_started_at) >= Self::HOLD_DURATION
-                {
-                    debug!("Hold finish - cycle brightness");
-                    self.touchpad_i2c
-                        .set_brightness(self.state.brightness.cycle())?;
-                    self.state.cur_key.reset();
+                if self.config.activation_mode() == ActivationMode::BothCorners {
+                    self.update_both_corners(ev.time)?;
+                }
+                self.update_pending_key(ev.time);
+                self.update_key_repeat(ev.time);
+                if self.state.numlock && self.state.cur_key == CurKey::Calc {
+                    if !self.layout.in_calc_bbox(self.state.pos) {
+                        // start over if the finger comes back
+                        self.state.calc_hold_started_at = None;
+                    } else if ev
+                        .time
+                        .elapsed_since(*self.state.calc_hold_started_at.get_or_insert(ev.time))
+                        >= if self.state.calc_cycled {
+                            Self::CYCLE_REPEAT_INTERVAL
+                        } else {
+                            self.config.hold_duration()
+                        }
+                    {
+                        if self.config.calc_on_hold() {
+                            debug!("Hold finish - toggle calc");
+                            self.toggle_calc();
+                            self.state.cur_key.reset();
+                        } else if !self.locked
+                            && self.config.one_finger_brightness()
+                            && (self.state.calc_cycled || self.debounce_gesture("brightness cycle"))
+                        {
+                            debug!("Hold finish - cycle brightness");
+                            self.brightness_chosen_at = Some(Instant::now());
+                            let brightness = self.state.brightness.cycle();
+                            self.set_brightness(brightness)?;
+                            // keep cycling while the finger stays
+                            self.state.calc_cycled = true;
+                            self.state.calc_hold_started_at = Some(ev.time);
+                        } else {
+                            self.state.cur_key.reset();
+                        }
+                    }
                 }
             }
             _ => (),
         }
 
+        if let Some(height) = self.config.slider_height() {
+            self.update_slider(ev.time, height)?;
+        }
+
         // if the finger drags too much, stop the tap
         // TODO: Use the same logic for numlock bbox instead of `tapped_outside_numlock_bbox`
         if self.state.numlock
             && self.state.finger_state == FingerState::Touching
             && self.state.cur_key != CurKey::Calc // we are fine if finger drags on calc box
-            && self.state.tap_start_pos.dist_sq(self.state.pos) > Self::TAP_JITTER_DIST
+            && !self.state.sliding
+            && self.state.tap.moved_off_key(
+                &self.layout,
+                self.state.pos,
+                self.config.drag_tolerance_percent(),
+            )
         {
             debug!("Moved too much");
             self.state.finger_dragged_too_much = true;
-            self.ungrab();
-            self.on_lift();
+            if !self.grabs_pointer() {
+                self.ungrab();
+            }
+            self.on_lift()?;
+        }
+        Ok(())
+    }
+
+    /// Time between two attempts of [`Numpad::reconnect`].
+    const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+    /// Open the touchpad again after it disappeared, e.g. because its driver
+    /// was rebound, waiting for it to come back.
+    ///
+    /// The numpad is then put back in the state it was in.
+    fn reconnect(&mut self) -> Result<()> {
+        // the finger is gone along with the device
+        if let (FingerState::Touching, CurKey::Numpad(_)) =
+            (self.state.finger_state, self.state.cur_key)
+        {
+            self.release_numpad_key();
+        }
+        self.contacts = Contacts::default();
+        self.state.cur_key.reset();
+        self.state.finger_state = FingerState::Lifted;
+
+        let attempts = self.config.reconnect_attempts();
+        let mut last_err = anyhow!("Reconnecting is disabled");
+        for attempt in 1..=attempts {
+            std::thread::sleep(Self::RECONNECT_DELAY);
+            info!(
+                "Reconnecting to the touchpad (attempt {}/{})",
+                attempt, attempts
+            );
+            match self.reopen_touchpad() {
+                Ok(()) => {
+                    info!("Reconnected to the touchpad");
+                    return self.restore_after_reconnect();
+                }
+                Err(err) => {
+                    debug!("Touchpad is not back yet: {:#}", err);
+                    last_err = err;
+                }
+            }
+        }
+        Err(last_err).context("The touchpad did not come back")
+    }
+
+    /// Replace the touchpad handles with new ones.
+    fn reopen_touchpad(&mut self) -> Result<()> {
+        let (_, touchpads) = read_proc_input().context("Couldn't get proc input devices")?;
+        let (evdev, i2c_id) = open_touchpad(&touchpads, self.config.touchpad_device())?;
+        let touchpad_i2c = open_touchpad_i2c(i2c_id, &self.config, &self.overrides)?;
+        self.evdev = evdev;
+        self.grabbed = false;
+        self.touchpad_i2c = touchpad_i2c;
+        Ok(())
+    }
+
+    /// Give the reopened touchpad the brightness and grab of the old one.
+    fn restore_after_reconnect(&mut self) -> Result<()> {
+        if self.config.disable_numpad() {
+            return self.touchpad_i2c.enable_numpad(false);
+        }
+        let brightness = self.state.brightness.get();
+        self.touchpad_i2c.set_brightness(brightness)?;
+        if self.state.numlock && self.grabs_pointer() {
+            self.grab();
         }
         Ok(())
     }
 
     fn process(&mut self) -> Result<()> {
         self.initialize_numlock()?;
+        loop {
+            match self.run() {
+                Err(err) if is_device_lost(&err) => {
+                    warn!("Lost the touchpad: {:#}", err);
+                    self.reconnect()?;
+                }
+                result => return result,
+            }
+        }
+    }
 
+    /// The event loop, which runs until exiting or an error.
+    fn run(&mut self) -> Result<()> {
         let tp_fd = libc::pollfd {
             fd: self.evdev.file().as_raw_fd(),
             events: libc::POLLIN,
@@ -471,23 +1823,101 @@ impl Numpad {
         };
         let kb_fd = libc::pollfd {
             fd: self.keyboard_evdev.file().as_raw_fd(),
-            events: if self.config.disable_numpad() {
-                0
-            } else {
-                libc::POLLIN
-            },
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let config_fd = libc::pollfd {
+            // poll ignores negative fds
+            fd: self.config_watcher.as_ref().map_or(-1, |w| w.as_raw_fd()),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let dbus_fd = libc::pollfd {
+            fd: self.dbus_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let signal_fd = libc::pollfd {
+            fd: self.signals.as_ref().map_or(-1, |s| s.as_raw_fd()),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let resume_fd = libc::pollfd {
+            fd: self.resume_watcher.as_ref().map_or(-1, |w| w.as_raw_fd()),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let fifo_fd = libc::pollfd {
+            fd: self.fifo.as_ref().map_or(-1, |f| f.as_raw_fd()),
+            events: libc::POLLIN,
             revents: 0,
         };
-        let mut fds = [tp_fd, kb_fd];
+        let mut fds = vec![
+            tp_fd, kb_fd, config_fd, dbus_fd, signal_fd, resume_fd, fifo_fd,
+        ];
+        const CONTROL_FDS_START: usize = 7;
 
         loop {
-            match unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as _, -1) } {
-                0 => (), // timeout, TODO: disable numpad if idle (no touches) for 1 minute
-                1 | 2 => {
-                    if fds[0].revents & libc::POLLIN != 0 {
+            // the control clients come and go
+            fds.truncate(CONTROL_FDS_START);
+            if let Some(control) = &self.control {
+                fds.extend(control.pollfds());
+            }
+            // might have been changed by a config reload
+            fds[1].events = if self.config.disable_numpad() {
+                0
+            } else {
+                libc::POLLIN
+            };
+            // wake up in time for the next step of a brightness fade, to
+            // turn off the idle numpad, or to check the keyboard backlight, ambient light or AC
+            let timeout = [
+                self.touchpad_i2c.fade_timeout(),
+                self.idle_timeout(),
+                self.kbd_backlight_timeout(),
+                self.als_timeout(),
+                self.ac_adapter_timeout(),
+            ]
+            .into_iter()
+            .flatten()
+            .min()
+            .map_or(-1, |timeout| timeout.as_micros().div_ceil(1000) as i32);
+            match unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as _, timeout) } {
+                0 => (), // timeout
+                -1 => {
+                    let err = std::io::Error::last_os_error();
+                    if err.kind() != std::io::ErrorKind::Interrupted {
+                        return Err(err).context("Failed to poll devices");
+                    }
+                }
+                _ => {
+                    if fds[4].revents & libc::POLLIN != 0 && self.handle_signals()? {
+                        info!("Exiting");
+                        self.shutdown();
+                        return Ok(());
+                    }
+                    // before anything else, so that it acts on the restored state
+                    if fds[5].revents & libc::POLLIN != 0 {
+                        if let Some(watcher) = &mut self.resume_watcher {
+                            if watcher.resumed()? {
+                                self.on_resume()?;
+                            }
+                        }
+                    }
+                    // errors too, to notice the device going away
+                    if fds[0].revents != 0 {
                         // read until no more events
-                        while let Ok((_, ev)) = self.evdev.next_event(ReadFlag::NORMAL) {
-                            self.handle_touchpad_event(ev)?;
+                        loop {
+                            match self.evdev.next_event(ReadFlag::NORMAL) {
+                                Ok((_, ev)) => {
+                                    self.handle_touchpad_event(ev.clone())?;
+                                    self.forward_to_pointer(&ev);
+                                }
+                                Err(err) if err.is_disconnected() => {
+                                    return Err(err).context("Failed to read touchpad events");
+                                }
+                                Err(_) => break,
+                            }
                         }
                     }
                     if fds[1].revents & libc::POLLIN != 0 {
@@ -501,40 +1931,737 @@ impl Numpad {
                             // and query it to get the numlock state.
                             //
                             // So, we only listen for LED changes, hoping that it reflects numlock state
-                            if let EventCode::EV_LED(EV_LED::LED_NUML) = ev.event_code {
+                            if matches!(ev.event_code, EventCode::EV_LED(EV_LED::LED_NUML))
+                                && self.config.numlock_sync().follows_system()
+                            {
                                 self.handle_numlock_pressed(ev.value)?;
                             }
+                            // shortcuts can be held along with the numpad
+                            if let EventCode::EV_KEY(key) = ev.event_code {
+                                if ev.value != 0 && !is_modifier(key) && key != EV_KEY::KEY_NUMLOCK
+                                {
+                                    self.typed_at = Some(Instant::now());
+                                }
+                            }
                             trace!("KB {}, {}", ev.event_code, ev.value);
                         }
                     }
+                    if fds[2].revents & libc::POLLIN != 0
+                        && self.config_watcher.as_ref().is_some_and(|w| w.changed())
+                    {
+                        self.reload_config()?;
+                    }
+                    #[cfg(feature = "dbus")]
+                    if fds[3].revents & libc::POLLIN != 0 {
+                        self.handle_dbus_commands();
+                    }
+                    // taken out, since the commands need the rest of self
+                    if fds[6].revents & libc::POLLIN != 0 {
+                        if let Some(mut fifo) = self.fifo.take() {
+                            fifo.process(|command| {
+                                self.handle_control_command(command)
+                                    .unwrap_or_else(|err| format!("error: {:#}", err))
+                            });
+                            self.fifo = Some(fifo);
+                        }
+                    }
+                    if let Some(mut control) = self.control.take() {
+                        control.process(&fds[CONTROL_FDS_START..], |command| {
+                            self.handle_control_command(command)
+                                .unwrap_or_else(|err| format!("error: {:#}", err))
+                        });
+                        self.control = Some(control);
+                    }
                 }
-                // we have only given 2 fds, so max return val of poll can be 2
-                _ => unsafe { unreachable_unchecked() },
             }
+            self.touchpad_i2c.advance_fade()?;
+            self.check_idle()?;
+            self.check_kbd_backlight()?;
+            self.check_als()?;
+            self.check_ac_adapter()?;
+            self.save_state();
+            let status = self.file_status();
+            if let Some(status_file) = &mut self.status_file {
+                status_file.update(status);
+            }
+            #[cfg(feature = "dbus")]
+            self.publish_dbus_state();
         }
     }
 }
 
+/// Driver for the numpad overlaid on Asus touchpads.
+///
+/// Runs the daemon unless one of the options is given, in which case the
+/// running daemon is told to apply that change.
+///
+/// Options given here take precedence over the config file, whose options
+/// take precedence over the defaults.
+#[derive(Debug, Parser)]
+#[command(version, about, args_conflicts_with_subcommands = true)]
+#[command(group(ArgGroup::new("command").args(["brightness", "toggle", "cycle", "off", "lock", "unlock"])))]
+struct Args {
+    /// Tell the running daemon to set the brightness: "off", "on" (the last
+    /// level), a level like "Half", a raw value like "20" or a percentage like
+    /// "50%". Use --start-brightness to start the daemon at a level instead
+    #[arg(long)]
+    brightness: Option<BrightnessArg>,
+
+    /// Turn the numpad on or off
+    #[arg(long)]
+    toggle: bool,
+
+    /// Step up to the next brightness level
+    #[arg(long)]
+    cycle: bool,
+
+    /// Turn the numpad off
+    #[arg(long)]
+    off: bool,
+
+    /// Stop touches from toggling the numpad or changing its brightness
+    #[arg(long)]
+    lock: bool,
+
+    /// Undo --lock
+    #[arg(long)]
+    unlock: bool,
+
+    /// Use this layout instead of the one in the config
+    #[arg(long, conflicts_with = "command", value_parser = |s: &str| SupportedLayout::try_from(s))]
+    layout: Option<SupportedLayout>,
+
+    /// How long to hold the corner to toggle the numpad, instead of the config's toggle_hold_ms
+    #[arg(long, conflicts_with = "command")]
+    toggle_hold_ms: Option<u64>,
+
+    /// Size of the corner that toggles the numpad, in percent of the layout's own
+    #[arg(long, conflicts_with = "command")]
+    toggle_corner_size_percent: Option<u32>,
+
+    /// Also read commands from a named pipe created at this path, instead of the config's fifo_path
+    #[arg(long, conflicts_with = "command")]
+    fifo: Option<PathBuf>,
+
+    /// Start at this brightness, instead of the saved one or the config's brightness
+    #[arg(long, value_name = "LEVEL", conflicts_with = "command")]
+    start_brightness: Option<Brightness>,
+
+    /// Follow the keyboard backlight, like the config's sync_kbd_backlight
+    #[arg(long, conflicts_with = "command")]
+    sync_kbd_backlight: bool,
+
+    /// Start with the numpad on, whatever the config or the saved state say
+    #[arg(long, conflicts_with_all = ["command", "disabled"])]
+    enabled: bool,
+
+    /// Start with the numpad off, whatever the config or the saved state say
+    #[arg(long, conflicts_with = "command")]
+    disabled: bool,
+
+    /// Print the available layouts and their keys
+    #[arg(long, exclusive = true)]
+    list_layouts: bool,
+
+    /// Print every I2C bus where a device responds at the touchpad address
+    #[arg(long, exclusive = true)]
+    list_buses: bool,
+
+    /// Print the model of the laptop, its detected layout and the touchpad
+    /// firmware version, e.g. for a bug report
+    #[arg(long, conflicts_with = "command")]
+    identify: bool,
+
+    /// With --identify, print JSON instead of text
+    #[arg(long, requires = "identify")]
+    json: bool,
+
+    /// Decode the touches and print the keys and brightness changes, without
+    /// sending them or opening the I2C bus
+    #[arg(long, conflicts_with = "command")]
+    dry_run: bool,
+
+    /// The N of the touchpad's /dev/i2c-N, instead of the detected one or the config's i2c_id
+    #[arg(long = "i2c", value_name = "ID", global = true)]
+    i2c_id: Option<u32>,
+
+    /// The evdev of the touchpad, instead of the detected one or the config's touchpad_device
+    #[arg(long, value_name = "PATH", global = true)]
+    touchpad_device: Option<PathBuf>,
+
+    /// Read this config file instead of looking for one in the XDG config dirs
+    #[arg(long, value_name = "PATH", global = true)]
+    config: Option<PathBuf>,
+
+    /// Log more, once for info, twice for debug and three times for every event.
+    /// RUST_LOG takes precedence
+    #[arg(short, long, action = clap::ArgAction::Count, global = true)]
+    verbose: u8,
+
+    #[command(subcommand)]
+    subcommand: Option<SubCommand>,
+}
+
+#[derive(Debug, Subcommand)]
+enum SubCommand {
+    /// Write a udev rule letting the input group set the brightness over I2C
+    GenerateUdevRules {
+        /// The rules file to write. Other rules in it are kept
+        #[arg(long, default_value = udev::DEFAULT_RULES_PATH)]
+        output: PathBuf,
+    },
+    /// Install a systemd service running this binary, and reload systemd
+    InstallService {
+        /// Install it for the user's own service manager instead of the system's
+        #[arg(long)]
+        user: bool,
+        /// Also start it after a suspend, in case it stopped (system service only)
+        #[arg(long, conflicts_with = "user")]
+        restart_on_resume: bool,
+    },
+    /// Set the brightness directly without the daemon, e.g. from a keyboard shortcut.
+    /// A running daemon doesn't notice, and overrides it the next time it changes
+    /// the brightness; use --brightness to go through the daemon instead
+    SetBrightness {
+        /// "off", "on" (the last level), "low", "half", "full", a raw value or a percentage
+        level: BrightnessArg,
+    },
+    /// Write any value to the brightness register, for experimenting.
+    /// Values other than the brightness levels are undocumented and might
+    /// confuse the firmware until it is reset
+    SetBrightnessRaw {
+        /// The value to write, e.g. "24" or "0x18"
+        #[arg(value_parser = parse_byte)]
+        byte: u8,
+    },
+    /// Turn the backlight off the way the firmware starts, e.g. after set-brightness-raw
+    Reset,
+    /// Touch the corners and keys of the printed numpad to make a custom layout for it
+    Calibrate {
+        /// The layout whose corner keys, and keys if the grid is the same, are used
+        #[arg(long, default_value = "UX433FA", value_parser = |s: &str| SupportedLayout::try_from(s))]
+        base: SupportedLayout,
+        /// Rows of keys, if different from the base layout
+        #[arg(long)]
+        rows: Option<usize>,
+        /// Columns of keys, if different from the base layout
+        #[arg(long)]
+        columns: Option<usize>,
+        /// Write the [layout.custom] table here instead of stdout
+        #[arg(long)]
+        output: Option<PathBuf>,
+    },
+}
+
+/// A brightness given on the command line, which can also turn the numpad
+/// on or off.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BrightnessArg {
+    /// The last level that the numpad was on at
+    On,
+    Off,
+    Level(Brightness),
+}
+
+impl std::str::FromStr for BrightnessArg {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if s.eq_ignore_ascii_case("on") {
+            Ok(Self::On)
+        } else if s.eq_ignore_ascii_case("off") {
+            Ok(Self::Off)
+        } else {
+            s.parse().map(Self::Level)
+        }
+    }
+}
+
+fn is_modifier(key: EV_KEY) -> bool {
+    use EV_KEY::*;
+    matches!(
+        key,
+        KEY_LEFTSHIFT
+            | KEY_RIGHTSHIFT
+            | KEY_LEFTCTRL
+            | KEY_RIGHTCTRL
+            | KEY_LEFTALT
+            | KEY_RIGHTALT
+            | KEY_LEFTMETA
+            | KEY_RIGHTMETA
+    )
+}
+
+/// Parse a byte given in decimal, or in hex with a `0x` prefix.
+fn parse_byte(s: &str) -> Result<u8> {
+    match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u8::from_str_radix(hex, 16),
+        None => s.parse(),
+    }
+    .with_context(|| format!("Invalid byte {:?}", s))
+}
+
+impl Args {
+    fn overrides(&self) -> Overrides {
+        Overrides {
+            layout: self.layout.clone(),
+            toggle_hold_ms: self.toggle_hold_ms,
+            toggle_corner_size_percent: self.toggle_corner_size_percent,
+            fifo_path: self.fifo.clone(),
+            enabled: match (self.enabled, self.disabled) {
+                (true, _) => Some(true),
+                (_, true) => Some(false),
+                _ => None,
+            },
+            dry_run: self.dry_run,
+            i2c_id: self.i2c_id,
+            touchpad_device: self.touchpad_device.clone(),
+            brightness: self.start_brightness,
+            sync_kbd_backlight: self.sync_kbd_backlight,
+            config_path: self.config.clone(),
+        }
+    }
+
+    fn command(&self) -> Option<ControlCommand> {
+        if let Some(brightness) = self.brightness {
+            Some(match brightness {
+                BrightnessArg::On => ControlCommand::On,
+                BrightnessArg::Off => ControlCommand::Off,
+                BrightnessArg::Level(level) => ControlCommand::Brightness(level),
+            })
+        } else if self.toggle {
+            Some(ControlCommand::Toggle)
+        } else if self.cycle {
+            Some(ControlCommand::Cycle)
+        } else if self.off {
+            Some(ControlCommand::Off)
+        } else if self.lock {
+            Some(ControlCommand::Lock)
+        } else if self.unlock {
+            Some(ControlCommand::Unlock)
+        } else {
+            None
+        }
+    }
+}
+
+/// Send `command` to the running daemon and print the resulting state.
+fn run_client(command: ControlCommand, overrides: &Overrides) -> Result<()> {
+    let socket_path = match Config::load(overrides) {
+        Ok(config) => config.socket_path(),
+        Err(err) => {
+            debug!("Using the default socket: {:#}", err);
+            control::default_socket_path()
+        }
+    };
+    send_command(&socket_path, command)?;
+    let status: Status = serde_json::from_str(&send_command(&socket_path, ControlCommand::Status)?)
+        .context("Invalid status from daemon")?;
+    println!("{}", status);
+    Ok(())
+}
+
+/// Find the I2C bus of the touchpad, preferring the id from proc input devices.
+///
+/// Only probes the buses as a last resort, since that needs access to all of them.
+fn find_i2c_id(proc_i2c_id: Option<u32>, config_i2c_id: Option<u32>) -> Result<u32> {
+    if let Some(i2c_id) = proc_i2c_id {
+        return Ok(i2c_id);
+    }
+    warn!("Can't find touchpad I2C ID in proc input devices, trying sysfs");
+    match (TouchpadI2C::detect_i2c_id(), config_i2c_id) {
+        (Ok(i2c_id), _) => Ok(i2c_id),
+        (Err(err), Some(i2c_id)) => {
+            warn!("{:#}. Using i2c_id {} from config", err, i2c_id);
+            Ok(i2c_id)
+        }
+        (Err(err), None) => {
+            warn!("{:#}. Probing all I2C buses", err);
+            Ok(TouchpadI2C::discover()?.i2c_id())
+        }
+    }
+}
+
+/// Find the I2C bus of the touchpad without starting the daemon.
+fn touchpad_i2c_id(overrides: &Overrides) -> Result<u32> {
+    if let Some(i2c_id) = overrides.i2c_id {
+        return Ok(i2c_id);
+    }
+    let config_i2c_id = Config::load(overrides)
+        .ok()
+        .and_then(|config| config.i2c_id());
+    find_i2c_id(proc_i2c_id(), config_i2c_id)
+}
+
+/// The bus of the first touchpad in proc input devices that has one.
+fn proc_i2c_id() -> Option<u32> {
+    read_proc_input()
+        .map_err(|err| debug!("{:#}", err))
+        .ok()
+        .and_then(|(_, touchpads)| touchpads.iter().find_map(|touchpad| touchpad.i2c_id))
+}
+
+/// Open the touchpad for a command that runs without the daemon, set up from
+/// the config the same way as the daemon's, so that e.g. a custom
+/// `brightness_protocol` is written.
+fn open_configured_touchpad_i2c(overrides: &Overrides) -> Result<TouchpadI2C<I2CBus>> {
+    let config = Config::load(overrides)?;
+    open_touchpad_i2c(proc_i2c_id(), &config, overrides)
+}
+
+fn install_service(user: bool, restart_on_resume: bool) -> Result<()> {
+    let path = service::unit_path(user)?;
+    service::install(&path, user, restart_on_resume)?;
+    println!("Wrote {}", path.display());
+    println!(
+        "Start it with `systemctl {}enable --now asus-numpad.service`",
+        if user { "--user " } else { "" }
+    );
+    Ok(())
+}
+
+fn generate_udev_rules(output: &std::path::Path, overrides: &Overrides) -> Result<()> {
+    let i2c_id = touchpad_i2c_id(overrides)?;
+    udev::write_rules(output, i2c_id)?;
+    println!(
+        "Wrote the rule for /dev/i2c-{} to {}",
+        i2c_id,
+        output.display()
+    );
+    println!("Apply it with `udevadm control --reload && udevadm trigger`");
+    Ok(())
+}
+
+/// Set the brightness once, and remember it in the state file for the next daemon start.
+fn set_brightness_once(level: BrightnessArg, overrides: &Overrides) -> Result<()> {
+    let state_path = State::path();
+    let mut state = State::load(&state_path);
+    let brightness = match level {
+        BrightnessArg::On => state
+            .brightness
+            .filter(|brightness| *brightness != Brightness::Zero)
+            .unwrap_or_default(),
+        BrightnessArg::Off => Brightness::Zero,
+        BrightnessArg::Level(brightness) => brightness,
+    };
+    let mut touchpad_i2c = open_configured_touchpad_i2c(overrides)?;
+    let i2c_id = touchpad_i2c.i2c_id();
+    touchpad_i2c
+        .set_brightness(brightness)
+        .with_context(|| format!("Could not set the brightness on /dev/i2c-{}", i2c_id))?;
+
+    state.enabled = Some(brightness != Brightness::Zero);
+    if brightness != Brightness::Zero {
+        state.brightness = Some(brightness);
+    }
+    if let Err(err) = state.save(&state_path) {
+        debug!("Failed to save state: {:#}", err);
+    }
+    Ok(())
+}
+
+fn set_brightness_raw(byte: u8, overrides: &Overrides) -> Result<()> {
+    let mut touchpad_i2c = open_configured_touchpad_i2c(overrides)?;
+    warn!(
+        "Writing {:#04x} to the brightness register. Restart the daemon or suspend to undo it",
+        byte
+    );
+    touchpad_i2c.set_brightness_raw(byte)
+}
+
+/// Run `command` without waiting for it.
+fn spawn_detached(mut command: Command) {
+    // spawn a thread that waits for the proc to end
+    // ensures that all procs are reaped
+    std::thread::spawn(move || {
+        match command.spawn() {
+            Ok(mut child) => {
+                debug!("Started child proc: {}", child.id());
+                if let Err(err) = child.wait() {
+                    warn!("Error while starting: {}", err);
+                } else {
+                    trace!("Process ended");
+                }
+            }
+            Err(err) => warn!("Error while starting: {}", err),
+        };
+    });
+}
+
+/// Run the command of a remapped cell.
+///
+/// When the daemon runs as root through `sudo`, it runs as the user who
+/// called `sudo` instead.
+fn run_cell_command(command: &CellCommand) {
+    debug!(
+        "Running command {} with args {:?}",
+        command.cmd, command.args
+    );
+    let mut process = Command::new(&command.cmd);
+    process.args(&command.args);
+    if unsafe { libc::geteuid() } == 0 {
+        let id = |var| std::env::var(var).ok()?.parse::<u32>().ok();
+        match (id("SUDO_UID"), id("SUDO_GID")) {
+            (Some(uid), Some(gid)) => {
+                process.uid(uid).gid(gid);
+            }
+            _ => warn!(
+                "Running {} as root. See the README for running commands as your user",
+                command.cmd
+            ),
+        }
+    }
+    spawn_detached(process);
+}
+
+/// Open the touchpad on the bus from `overrides`, `i2c_id` from `/proc`, or
+/// the bus from `config`, with the timeout and retries from `config`.
+///
+/// In a dry run, no bus is opened and the messages are printed instead.
+fn open_touchpad_i2c(
+    i2c_id: Option<u32>,
+    config: &Config,
+    overrides: &Overrides,
+) -> Result<TouchpadI2C<I2CBus>> {
+    let i2c_id = overrides.i2c_id.or(i2c_id);
+    if overrides.dry_run {
+        let i2c_id = i2c_id.or(config.i2c_id()).unwrap_or_default();
+        return Ok(TouchpadI2C::from_device(I2CBus::Printed, i2c_id));
+    }
+    let i2c_id = find_i2c_id(i2c_id, config.i2c_id())?;
+    let mut touchpad_i2c = match config.i2c_timeout() {
+        Some(timeout) => TouchpadI2C::with_timeout(i2c_id, timeout)?,
+        None => TouchpadI2C::new(i2c_id)?,
+    };
+    touchpad_i2c.set_retry(config.retry_attempts(), config.retry_delay());
+    touchpad_i2c.set_protocol(config.brightness_protocol());
+    Ok(touchpad_i2c.map_device(I2CBus::Linux))
+}
+
+/// Create the keyboard that presses the keys of `layout`, or only prints
+/// them in a `dry_run`.
+fn create_keyboard(layout: &NumpadLayout, config: &Config, dry_run: bool) -> Result<Keyboard> {
+    if dry_run {
+        return Ok(Keyboard::Printed);
+    }
+    DummyKeyboard::new(layout, &config.calc_keys()).map(Keyboard::Uinput)
+}
+
+/// Set up the layout from the config for a touchpad of size `bbox`.
+fn build_layout(config: &Config, bbox: BBox) -> Result<NumpadLayout> {
+    let mut layout =
+        NumpadLayout::from_supported_layout(config.layout(), bbox, config.activation_corner())?;
+    layout.scale_numlock_bbox(config.toggle_corner_size_percent());
+    layout.remap(config.remap())?;
+    layout.set_key_style(config.key_style());
+    layout.set_key_gap(config.key_gap_percent());
+    if let Some(keys) = config.percent_keys() {
+        layout.set_key_sequence(EV_KEY::KEY_5, keys.to_vec());
+    }
+    if let Some(keys) = config.equals_keys() {
+        layout.set_key_sequence(EV_KEY::KEY_KPEQUAL, keys.to_vec());
+    }
+    Ok(layout)
+}
+
+/// Print the ranges of the touchpad axes, which are needed to add a layout.
+fn print_touchpad_axes(touchpad: &Device) {
+    println!("Touchpad: {}", touchpad.name().unwrap_or_default());
+    for axis in [
+        EV_ABS::ABS_X,
+        EV_ABS::ABS_Y,
+        EV_ABS::ABS_MT_POSITION_X,
+        EV_ABS::ABS_MT_POSITION_Y,
+    ] {
+        match touchpad.abs_info(&EventCode::EV_ABS(axis)) {
+            Some(info) => println!(
+                "{:?}: {} to {}, resolution {}",
+                axis, info.minimum, info.maximum, info.resolution
+            ),
+            None => println!("{:?}: none", axis),
+        }
+    }
+}
+
+/// Print what [`TouchpadI2C::identify_device`] finds, on the bus that the daemon would use.
+fn identify(overrides: &Overrides, json: bool) -> Result<()> {
+    let i2c_id = touchpad_i2c_id(overrides)?;
+    let info = TouchpadI2C::new(i2c_id)?
+        .identify_device(read_model_names)
+        .with_context(|| format!("Could not identify the touchpad on /dev/i2c-{}", i2c_id))?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&info)?);
+    } else {
+        print!("{}", info);
+    }
+    Ok(())
+}
+
+/// Print the buses that might have the touchpad, marking the one the daemon would use.
+fn list_buses(overrides: &Overrides) -> Result<()> {
+    let buses = TouchpadI2C::probe_all_buses()?;
+    if buses.is_empty() {
+        return Err(anyhow!(
+            "No device responded on any I2C bus. Is the i2c-dev kernel module loaded?"
+        ));
+    }
+    let detected = touchpad_i2c_id(overrides)
+        .map_err(|err| debug!("{:#}", err))
+        .ok();
+    for i2c_id in buses {
+        if Some(i2c_id) == detected {
+            println!("/dev/i2c-{} (used by the daemon)", i2c_id);
+        } else {
+            println!("/dev/i2c-{}", i2c_id);
+        }
+    }
+    Ok(())
+}
+
+/// Print every layout with its grid of keys.
+fn list_layouts() {
+    // the grid doesn't depend on the size of the touchpad
+    let bbox = || BBox::new(0, 1000, 0, 1000);
+    for layout in SupportedLayout::ALL {
+        println!("{}", layout.name());
+        let numpad_layout =
+            NumpadLayout::from_supported_layout(&layout, bbox(), Default::default())
+                .expect("built-in layouts are valid");
+        for row in numpad_layout.keys() {
+            let keys: Vec<_> = row
+                .iter()
+                .map(|key| {
+                    let name = key.map_or_else(|| "-".to_owned(), |key| format!("{:?}", key));
+                    format!("{:<10}", name.trim_start_matches("KEY_"))
+                })
+                .collect();
+            println!("    {}", keys.join(" ").trim_end());
+        }
+    }
+}
+
+/// Log at the level of `verbose`, unless `RUST_LOG` says otherwise.
+///
+/// Under systemd, journald adds its own timestamps, so the lines only get the
+/// syslog priority that it reads the level from.
+fn init_logger(verbose: u8) {
+    let level = match verbose {
+        0 => "warn",
+        1 => "info",
+        2 => "debug",
+        _ => "trace",
+    };
+    let mut builder =
+        env_logger::Builder::from_env(env_logger::Env::default().default_filter_or(level));
+    if std::env::var_os("JOURNAL_STREAM").is_some() || std::env::var_os("INVOCATION_ID").is_some() {
+        builder.format(|buf, record| {
+            let priority = match record.level() {
+                log::Level::Error => 3,
+                log::Level::Warn => 4,
+                log::Level::Info => 6,
+                log::Level::Debug | log::Level::Trace => 7,
+            };
+            writeln!(buf, "<{}>{}: {}", priority, record.target(), record.args())
+        });
+    }
+    builder.init();
+}
+
 fn main() -> Result<()> {
-    env_logger::init();
+    let args = Args::parse();
+    init_logger(args.verbose);
+    if args.list_layouts {
+        list_layouts();
+        return Ok(());
+    }
+    let overrides = args.overrides();
+    if args.list_buses {
+        return list_buses(&overrides);
+    }
+    if args.identify {
+        return identify(&overrides, args.json);
+    }
+    match &args.subcommand {
+        Some(SubCommand::GenerateUdevRules { output }) => {
+            return generate_udev_rules(output, &overrides);
+        }
+        Some(SubCommand::InstallService {
+            user,
+            restart_on_resume,
+        }) => return install_service(*user, *restart_on_resume),
+        Some(SubCommand::SetBrightness { level }) => {
+            return set_brightness_once(*level, &overrides);
+        }
+        Some(SubCommand::SetBrightnessRaw { byte }) => {
+            return set_brightness_raw(*byte, &overrides);
+        }
+        Some(SubCommand::Reset) => return open_configured_touchpad_i2c(&overrides)?.reset(),
+        Some(SubCommand::Calibrate {
+            base,
+            rows,
+            columns,
+            output,
+        }) => {
+            let (_, touchpads) = read_proc_input().context("Couldn't get proc input devices")?;
+            let (touchpad_dev, _) =
+                open_touchpad(&touchpads, overrides.touchpad_device.as_deref())?;
+            return calibrate::calibrate(
+                touchpad_dev,
+                base.clone(),
+                *rows,
+                *columns,
+                output.as_deref(),
+            );
+        }
+        None => (),
+    }
+    if let Some(command) = args.command() {
+        return run_client(command, &overrides);
+    }
 
-    // Follows XDG Base Dir Spec
-    const CONFIG_PATH: &str = "/etc/xdg/asus_numpad.toml";
-    let v = std::fs::read(CONFIG_PATH)?;
-    let config: Config = toml::from_str(&String::from_utf8(v)?)?;
+    let config = Config::load(&overrides)?;
     info!("Config: {:?}", config);
     let layout_name = config.layout();
+    match read_model_names() {
+        Ok(models) => match SupportedLayout::detect(&models) {
+            Ok(detected) if &detected != layout_name => warn!(
+                "Using layout {:?}, but {:?} looks like a {:?}",
+                layout_name,
+                models.join(" / "),
+                detected
+            ),
+            _ => debug!("Model: {}", models.join(" / ")),
+        },
+        Err(err) => debug!("{:#}", err),
+    }
 
-    let (keyboard_ev_id, touchpad_ev_id, i2c_id) =
+    let (keyboard_ev_id, touchpads) =
         read_proc_input().context("Couldn't get proc input devices")?;
-    let touchpad_dev = open_input_evdev(touchpad_ev_id)?;
-    let keyboard_dev = open_input_evdev(keyboard_ev_id)?;
+    let (touchpad_dev, i2c_id) = open_touchpad(&touchpads, config.touchpad_device())?;
+    let keyboard_dev = open_keyboard(keyboard_ev_id, config.keyboard_device())?;
     let bbox = get_touchpad_bbox(&touchpad_dev)?;
     info!("BBox: {:?}", bbox);
-    let layout = NumpadLayout::from_supported_layout(layout_name, bbox)?;
-    let kb = DummyKeyboard::new(&layout)?;
-    let touchpad_i2c = TouchpadI2C::new(i2c_id)?;
-    let mut numpad = Numpad::new(touchpad_dev, keyboard_dev, touchpad_i2c, kb, layout, config);
+    if overrides.dry_run {
+        print_touchpad_axes(&touchpad_dev);
+    }
+    let layout = build_layout(&config, bbox)?;
+    let kb = create_keyboard(&layout, &config, overrides.dry_run)?;
+    let mut touchpad_i2c = open_touchpad_i2c(i2c_id, &config, &overrides)?;
+    if !overrides.dry_run {
+        match touchpad_i2c.read_firmware_version() {
+            Ok(version) => info!("Touchpad firmware version: {:02x?}", version),
+            Err(err) => warn!("Failed to read touchpad firmware version: {:#}", err),
+        }
+    }
+    let mut numpad = Numpad::new(
+        touchpad_dev,
+        keyboard_dev,
+        touchpad_i2c,
+        kb,
+        layout,
+        config,
+        overrides,
+    );
     numpad.process()?;
     Ok(())
 }
@@ -1,8 +1,9 @@
+use std::collections::{BTreeMap, HashMap};
 use std::fmt::Debug;
-use std::hint::unreachable_unchecked;
 
-use anyhow::Result;
+use anyhow::{Result, anyhow};
 use evdev_rs::enums::EV_KEY;
+use log::warn;
 use serde::{Deserialize, Serialize};
 
 use crate::Point;
@@ -15,7 +16,7 @@ struct Margins {
     right: f32,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct BBox {
     minx: i32,
     maxx: i32,
@@ -52,9 +53,42 @@ impl BBox {
         }
     }
 
+    /// Flip horizontally within `outer`.
+    fn mirror_x(&self, outer: &BBox) -> Self {
+        Self {
+            minx: outer.minx + outer.maxx - self.maxx,
+            maxx: outer.minx + outer.maxx - self.minx,
+            miny: self.miny,
+            maxy: self.maxy,
+        }
+    }
+
+    /// Resize by `factor` while staying within `outer`, keeping the sides
+    /// closest to the edges of `outer` in place.
+    fn scale_within(&self, outer: &BBox, factor: f32) -> Self {
+        let width = (self.xrange() as f32 * factor) as i32;
+        let height = (self.yrange() as f32 * factor) as i32;
+        let (minx, maxx) = if self.minx - outer.minx <= outer.maxx - self.maxx {
+            (self.minx, (self.minx + width).min(outer.maxx))
+        } else {
+            ((self.maxx - width).max(outer.minx), self.maxx)
+        };
+        let (miny, maxy) = if self.miny - outer.miny <= outer.maxy - self.maxy {
+            (self.miny, (self.miny + height).min(outer.maxy))
+        } else {
+            ((self.maxy - height).max(outer.miny), self.maxy)
+        };
+        Self {
+            minx,
+            maxx,
+            miny,
+            maxy,
+        }
+    }
+
     /// Return a new BBox that is non-intersecting with self.
     /// Used for creating dummy boxes.
-    fn disjoint_dummy(&self) -> Self {
+    pub(crate) fn disjoint_dummy(&self) -> Self {
         Self {
             minx: self.maxx + 1,
             maxx: self.maxx + 2,
@@ -66,14 +100,83 @@ impl BBox {
     fn contains(&self, pos: Point) -> bool {
         (self.minx <= pos.x && pos.x <= self.maxx) && (self.miny <= pos.y && pos.y <= self.maxy)
     }
+
+    /// The margins that leave this bbox of `outer`, in percent rounded to a tenth.
+    pub(crate) fn margins_within(&self, outer: &BBox) -> CustomMargins {
+        let percent =
+            |margin: i32, range: i32| (margin as f64 * 1000.0 / range as f64).round() / 10.0;
+        CustomMargins {
+            top: percent(self.miny - outer.miny, outer.yrange()),
+            bottom: percent(outer.maxy - self.maxy, outer.yrange()),
+            left: percent(self.minx - outer.minx, outer.xrange()),
+            right: percent(outer.maxx - self.maxx, outer.xrange()),
+        }
+    }
 }
 
-type Grid = Vec<Vec<EV_KEY>>;
+/// Where the keys of a custom layout are, in percent of the touchpad from
+/// each of its edges.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct CustomMargins {
+    top: f64,
+    bottom: f64,
+    left: f64,
+    right: f64,
+}
+
+// the margins are checked to be finite
+impl Eq for CustomMargins {}
+
+impl std::hash::Hash for CustomMargins {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        for margin in [self.top, self.bottom, self.left, self.right] {
+            margin.to_bits().hash(state);
+        }
+    }
+}
+
+impl CustomMargins {
+    fn validate(&self) -> Result<()> {
+        let margins = [self.top, self.bottom, self.left, self.right];
+        if margins.iter().any(|margin| !(0.0..100.0).contains(margin))
+            || self.top + self.bottom >= 100.0
+            || self.left + self.right >= 100.0
+        {
+            return Err(anyhow!(
+                "The margins of the custom layout must leave some of the touchpad, got {:?}",
+                self
+            ));
+        }
+        Ok(())
+    }
+
+    fn to_margins(self) -> Margins {
+        Margins {
+            top: (self.top / 100.0) as f32,
+            bottom: (self.bottom / 100.0) as f32,
+            left: (self.left / 100.0) as f32,
+            right: (self.right / 100.0) as f32,
+        }
+    }
+}
+
+/// Parse an evdev key name, with or without the `KEY_` prefix.
+pub(crate) fn parse_key(name: &str) -> Option<EV_KEY> {
+    name.parse()
+        .or_else(|_| format!("KEY_{}", name).parse())
+        .ok()
+}
+
+/// Rows of keys, where `None` is a cell that does nothing.
+type Grid = Vec<Vec<Option<EV_KEY>>>;
 
 #[derive(Debug)]
 pub(crate) struct NumpadLayout {
     /// The matrix of keys
     keys: Grid,
+    /// The whole touchpad
+    touchpad_bbox: BBox,
     numpad_bbox: BBox,
     numlock_bbox: BBox,
     calc_bbox: BBox,
@@ -81,16 +184,381 @@ pub(crate) struct NumpadLayout {
     key_width: i32,
     /// The height of one numpad button/key box
     key_height: i32,
+    key_style: KeyStyle,
+    /// Percent of a key along its inner borders where touches don't count,
+    /// halved for each of the two keys that share a border
+    key_gap_percent: u32,
+    /// Keys of the grid that are typed as these keycodes instead, modifiers first
+    sequences: HashMap<EV_KEY, Vec<EV_KEY>>,
+    /// Cells that run a command instead of typing a key, by row and column
+    commands: HashMap<(usize, usize), CellCommand>,
 }
 
-#[derive(Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize, Serialize)]
 pub(crate) enum SupportedLayout {
     UX433FA,
     M433IA,
     UX581,
+    /// ZenBook Pro Duo, whose numpad has the same geometry as `UX581`
+    UX582,
     GX701,
     GX531,
     G533,
+    /// Keys from the config, on the geometry of a built-in layout
+    #[serde(rename = "custom")]
+    Custom(CustomLayout),
+}
+
+/// A grid of keys defined in the config, like
+///
+/// ```toml
+/// [layout.custom]
+/// base = "UX433FA"
+/// rows = 2
+/// columns = 2
+/// keys = [["KEY_KP1", "KEY_KP2"], ["KEY_LEFT", ""]]
+/// ```
+///
+/// where `""` is a cell that does nothing.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(try_from = "RawCustomLayout", into = "RawCustomLayout")]
+pub(crate) struct CustomLayout {
+    /// The built-in layout whose margins and corner keys are used
+    base: Box<SupportedLayout>,
+    keys: Grid,
+    /// Used instead of the margins of `base`
+    margins: Option<CustomMargins>,
+}
+
+/// [`CustomLayout`] as written in the config, before it is validated.
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+struct RawCustomLayout {
+    #[serde(default = "default_custom_base")]
+    base: String,
+    rows: usize,
+    columns: usize,
+    keys: Vec<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    margins_percent: Option<CustomMargins>,
+}
+
+fn default_custom_base() -> String {
+    SupportedLayout::UX433FA.name()
+}
+
+impl TryFrom<RawCustomLayout> for CustomLayout {
+    type Error = anyhow::Error;
+
+    fn try_from(raw: RawCustomLayout) -> Result<Self> {
+        let base = SupportedLayout::try_from(raw.base.as_str())?;
+        if let Some(margins) = &raw.margins_percent {
+            margins.validate()?;
+        }
+        if raw.rows == 0 || raw.columns == 0 {
+            return Err(anyhow!(
+                "The custom layout needs at least one row and column"
+            ));
+        }
+        if raw.keys.len() != raw.rows {
+            return Err(anyhow!(
+                "The custom layout has {} rows of keys, but rows = {}",
+                raw.keys.len(),
+                raw.rows
+            ));
+        }
+        let keys = raw
+            .keys
+            .iter()
+            .enumerate()
+            .map(|(row_idx, row)| {
+                if row.len() != raw.columns {
+                    return Err(anyhow!(
+                        "Row {} of the custom layout has {} keys, but columns = {}",
+                        row_idx + 1,
+                        row.len(),
+                        raw.columns
+                    ));
+                }
+                row.iter()
+                    .enumerate()
+                    .map(|(col_idx, name)| match name.as_str() {
+                        "" => Ok(None),
+                        name => name.parse().map(Some).map_err(|_| {
+                            anyhow!(
+                                "Unknown key {:?} in row {}, column {} of the custom layout",
+                                name,
+                                row_idx + 1,
+                                col_idx + 1
+                            )
+                        }),
+                    })
+                    .collect()
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self {
+            base: Box::new(base),
+            keys,
+            margins: raw.margins_percent,
+        })
+    }
+}
+
+impl CustomLayout {
+    pub(crate) fn new(base: SupportedLayout, keys: Grid, margins: Option<CustomMargins>) -> Self {
+        Self {
+            base: Box::new(base),
+            keys,
+            margins,
+        }
+    }
+}
+
+impl From<CustomLayout> for RawCustomLayout {
+    fn from(layout: CustomLayout) -> Self {
+        Self {
+            base: layout.base.name(),
+            rows: layout.keys.len(),
+            columns: layout.keys.first().map_or(0, |row| row.len()),
+            keys: layout
+                .keys
+                .iter()
+                .map(|row| {
+                    row.iter()
+                        .map(|key| key.map_or_else(String::new, |key| format!("{:?}", key)))
+                        .collect()
+                })
+                .collect(),
+            margins_percent: layout.margins,
+        }
+    }
+}
+
+/// A cell of the grid, by the key it has in the layout or by its position.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Cell {
+    Key(EV_KEY),
+    /// Zero-based row and column
+    Position(usize, usize),
+}
+
+impl TryFrom<&str> for Cell {
+    type Error = anyhow::Error;
+
+    /// Parse a key name, or a one-based `"ROW,COLUMN"`.
+    fn try_from(name: &str) -> Result<Self> {
+        let position = name.split_once(',').and_then(|(row, column)| {
+            let row: usize = row.trim().parse().ok()?;
+            let column: usize = column.trim().parse().ok()?;
+            Some(Self::Position(row.checked_sub(1)?, column.checked_sub(1)?))
+        });
+        position
+            .or_else(|| parse_key(name).map(Self::Key))
+            .ok_or_else(|| {
+                anyhow!(
+                    "Unknown cell {:?} in remap. Use the key name it has in the layout, \
+                    like \"KEY_KPSLASH\", or \"ROW,COLUMN\" counting from 1, like \"1,4\"",
+                    name
+                )
+            })
+    }
+}
+
+impl std::fmt::Display for Cell {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Key(key) => write!(f, "{:?}", key),
+            Self::Position(row, column) => write!(f, "{},{}", row + 1, column + 1),
+        }
+    }
+}
+
+/// A command to run when a cell is touched.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct CellCommand {
+    pub(crate) cmd: String,
+    #[serde(default)]
+    pub(crate) args: Vec<String>,
+}
+
+/// What a remapped cell does instead.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum RemapTarget {
+    /// Type this key, or nothing
+    Key(Option<EV_KEY>),
+    Command(CellCommand),
+}
+
+/// [`RemapTarget`] as written in the config, before it is validated.
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(untagged)]
+enum RawRemapTarget {
+    Key(String),
+    Command(CellCommand),
+}
+
+/// Keys of the layout to replace, like
+///
+/// ```toml
+/// [remap]
+/// KEY_KPSLASH = "KEY_VOLUMEUP"
+/// "2,5" = "KEY_PLAYPAUSE"
+/// "1,1" = {cmd = "gnome-calculator"}
+/// ```
+///
+/// where `""` turns a cell off. Positions take priority over key names.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(
+    try_from = "BTreeMap<String, RawRemapTarget>",
+    into = "BTreeMap<String, RawRemapTarget>"
+)]
+pub(crate) struct KeyRemap {
+    entries: Vec<(Cell, RemapTarget)>,
+}
+
+impl TryFrom<BTreeMap<String, RawRemapTarget>> for KeyRemap {
+    type Error = anyhow::Error;
+
+    fn try_from(raw: BTreeMap<String, RawRemapTarget>) -> Result<Self> {
+        let entries = raw
+            .into_iter()
+            .map(|(cell, target)| {
+                let target = match target {
+                    RawRemapTarget::Key(name) if name.is_empty() => RemapTarget::Key(None),
+                    RawRemapTarget::Key(name) => {
+                        RemapTarget::Key(Some(parse_key(&name).ok_or_else(|| {
+                            anyhow!(
+                                "Unknown key {:?} for {} in remap. Use an evdev key name like \
+                                \"KEY_VOLUMEUP\", \"\" for nothing, or {{cmd = \"...\", args = [...]}}",
+                                name,
+                                cell
+                            )
+                        })?))
+                    }
+                    RawRemapTarget::Command(command) => RemapTarget::Command(command),
+                };
+                Ok((Cell::try_from(cell.as_str())?, target))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self { entries })
+    }
+}
+
+impl From<KeyRemap> for BTreeMap<String, RawRemapTarget> {
+    fn from(remap: KeyRemap) -> Self {
+        remap
+            .entries
+            .into_iter()
+            .map(|(cell, target)| {
+                let target = match target {
+                    RemapTarget::Key(key) => RawRemapTarget::Key(
+                        key.map_or_else(String::new, |key| format!("{:?}", key)),
+                    ),
+                    RemapTarget::Command(command) => RawRemapTarget::Command(command),
+                };
+                (cell.to_string(), target)
+            })
+            .collect()
+    }
+}
+
+/// The corner of the touchpad that toggles the numpad.
+///
+/// The calc key is in the opposite corner.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum ActivationCorner {
+    TopLeft,
+    #[default]
+    TopRight,
+}
+
+/// Which keycodes the numpad types.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum KeyStyle {
+    /// The keypad codes, like `KEY_KP1`
+    #[default]
+    Keypad,
+    /// The codes of the main keyboard, like `KEY_1`, for remote desktops and
+    /// VMs that don't translate the keypad ones. The symbols that need Shift
+    /// assume a US layout.
+    Toprow,
+}
+
+/// Parts of DMI product or board names, and the layout of the models having them.
+///
+/// Checked in order, so more specific patterns must come first.
+const MODEL_LAYOUTS: &[(&str, SupportedLayout)] = &[
+    ("UX433", SupportedLayout::UX433FA),
+    ("UX434", SupportedLayout::UX433FA),
+    ("M433IA", SupportedLayout::M433IA),
+    ("UM433", SupportedLayout::M433IA),
+    ("UX581", SupportedLayout::UX581),
+    ("UX582", SupportedLayout::UX582),
+    ("GX701", SupportedLayout::GX701),
+    ("GX531", SupportedLayout::GX531),
+    ("G533", SupportedLayout::G533),
+];
+
+impl SupportedLayout {
+    pub(crate) const ALL: [Self; 7] = [
+        Self::UX433FA,
+        Self::M433IA,
+        Self::UX581,
+        Self::UX582,
+        Self::GX701,
+        Self::GX531,
+        Self::G533,
+    ];
+
+    /// The name used in the config, like `"UX433FA"`.
+    pub(crate) fn name(&self) -> String {
+        match self {
+            Self::Custom(_) => "custom".to_owned(),
+            _ => format!("{:?}", self),
+        }
+    }
+
+    /// Guess the layout from a model name, like the DMI product name
+    /// `"ZenBook UX433FA_UX433FA"`.
+    pub(crate) fn for_model(model: &str) -> Option<Self> {
+        let model = model.to_ascii_uppercase();
+        MODEL_LAYOUTS
+            .iter()
+            .find(|(pattern, _)| model.contains(pattern))
+            .map(|(_, layout)| layout.clone())
+    }
+
+    /// Pick the layout for the first of `models` (e.g. the DMI product and
+    /// board names) that is known.
+    pub(crate) fn detect(models: &[String]) -> Result<Self> {
+        models
+            .iter()
+            .find_map(|model| Self::for_model(model))
+            .ok_or_else(|| {
+                let names: Vec<_> = Self::ALL.iter().map(Self::name).collect();
+                anyhow!(
+                    "Unknown model {:?}. Set `layout` in the config to one of {}, \
+                    and open an issue to have the model detected",
+                    models.join(" / "),
+                    names.join(", ")
+                )
+            })
+    }
+}
+
+impl TryFrom<&str> for SupportedLayout {
+    type Error = anyhow::Error;
+
+    /// Parse a layout name, ignoring case.
+    fn try_from(name: &str) -> Result<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|layout| layout.name().eq_ignore_ascii_case(name))
+            .ok_or_else(|| anyhow!("Unknown layout {:?}", name))
+    }
 }
 
 impl NumpadLayout {
@@ -99,36 +567,184 @@ impl NumpadLayout {
         self.keys.as_ref()
     }
 
-    pub fn needs_multikey(&self, key: EV_KEY) -> bool {
-        key == EV_KEY::KEY_5
+    /// Use `style` for the keycodes that are typed.
+    pub fn set_key_style(&mut self, style: KeyStyle) {
+        self.key_style = style;
+    }
+
+    /// Ignore touches on the borders between keys, `percent` of a key wide.
+    ///
+    /// The outer edges of the grid stay, and at most half of a key goes to
+    /// the gaps.
+    pub fn set_key_gap(&mut self, percent: u32) {
+        self.key_gap_percent = percent.min(50);
     }
 
-    pub fn multikeys(&self, key: EV_KEY) -> [EV_KEY; 2] {
+    /// Replace the keys of the grid as given by `remap`.
+    ///
+    /// Every cell is looked up in the original grid, so keys can be swapped.
+    pub fn remap(&mut self, remap: &KeyRemap) -> Result<()> {
+        let rows = self.keys.len();
+        let columns = self.keys[0].len();
+        for (cell, _) in &remap.entries {
+            match cell {
+                &Cell::Position(row, column) if row >= rows || column >= columns => {
+                    return Err(anyhow!(
+                        "Cell {} in remap is outside of the {} rows and {} columns of the layout",
+                        cell,
+                        rows,
+                        columns
+                    ));
+                }
+                Cell::Key(key) if !self.keys.iter().flatten().any(|k| k == &Some(*key)) => {
+                    warn!("{:?} in remap isn't in the layout", key);
+                }
+                _ => (),
+            }
+        }
+        let lookup = |cell: Cell| {
+            remap
+                .entries
+                .iter()
+                .find(|(c, _)| *c == cell)
+                .map(|(_, target)| target)
+        };
+        let mut commands = HashMap::new();
+        let mut keys = self.keys.clone();
+        for (row, keys) in keys.iter_mut().enumerate() {
+            for (column, key) in keys.iter_mut().enumerate() {
+                let target = lookup(Cell::Position(row, column))
+                    .or_else(|| key.and_then(|key| lookup(Cell::Key(key))));
+                match target {
+                    Some(RemapTarget::Key(new_key)) => *key = *new_key,
+                    Some(RemapTarget::Command(command)) => {
+                        *key = None;
+                        commands.insert((row, column), command.clone());
+                    }
+                    None => (),
+                }
+            }
+        }
+        self.keys = keys;
+        self.commands = commands;
+        Ok(())
+    }
+
+    /// Type `key` of the grid as `keys`, modifiers first.
+    pub fn set_key_sequence(&mut self, key: EV_KEY, keys: Vec<EV_KEY>) {
+        self.sequences.insert(key, keys);
+    }
+
+    /// The keycodes typed for `key` of the grid, modifiers first.
+    pub fn key_codes(&self, key: EV_KEY) -> Vec<EV_KEY> {
+        use EV_KEY::*;
+        if let Some(keys) = self.sequences.get(&key) {
+            return keys.clone();
+        }
+        match (key, self.key_style) {
+            // percent, assuming a US layout
+            (KEY_5, _) => vec![KEY_LEFTSHIFT, KEY_5],
+            (KEY_KPASTERISK, KeyStyle::Toprow) => vec![KEY_LEFTSHIFT, KEY_8],
+            (KEY_KPPLUS, KeyStyle::Toprow) => vec![KEY_LEFTSHIFT, KEY_EQUAL],
+            _ => vec![self.key_code(key)],
+        }
+    }
+
+    /// The single keycode for `key` in the key style.
+    fn key_code(&self, key: EV_KEY) -> EV_KEY {
+        use EV_KEY::*;
+        if self.key_style == KeyStyle::Keypad {
+            return key;
+        }
         match key {
-            EV_KEY::KEY_5 => [EV_KEY::KEY_LEFTSHIFT, EV_KEY::KEY_5],
-            // Safety: We know this method will only be called after
-            // needs_multikey returns true
-            _ => unsafe { unreachable_unchecked() },
+            KEY_KP0 => KEY_0,
+            KEY_KP1 => KEY_1,
+            KEY_KP2 => KEY_2,
+            KEY_KP3 => KEY_3,
+            KEY_KP4 => KEY_4,
+            KEY_KP5 => KEY_5,
+            KEY_KP6 => KEY_6,
+            KEY_KP7 => KEY_7,
+            KEY_KP8 => KEY_8,
+            KEY_KP9 => KEY_9,
+            KEY_KPSLASH => KEY_SLASH,
+            KEY_KPMINUS => KEY_MINUS,
+            KEY_KPDOT => KEY_DOT,
+            KEY_KPENTER => KEY_ENTER,
+            KEY_KPEQUAL => KEY_EQUAL,
+            _ => key,
         }
     }
 
     /// Get the key at (posx, posy), if it exists
+    ///
+    /// The calc bbox takes priority, so that tapping it doesn't also type a key
+    /// from a cell under it.
     pub fn get_key(&self, pos: Point) -> Option<EV_KEY> {
+        let (row, col) = self.get_cell(pos)?;
+        self.keys[row][col]
+    }
+
+    /// Get the command of the cell at `pos`, if it was remapped to one.
+    pub fn get_command(&self, pos: Point) -> Option<&CellCommand> {
+        self.commands.get(&self.get_cell(pos)?)
+    }
+
+    /// The row and column at `pos`, like [`NumpadLayout::get_key`].
+    pub(crate) fn get_cell(&self, pos: Point) -> Option<(usize, usize)> {
         let bbox = &self.numpad_bbox;
-        if !bbox.contains(pos) {
+        if !bbox.contains(pos) || self.in_calc_bbox(pos) {
             return None;
         }
+        // the keys don't always divide the bbox evenly, so the last ones take the rest
         let col = ((pos.x - bbox.minx) / self.key_width) as usize;
         let row = ((pos.y - bbox.miny) / self.key_height) as usize;
-        // Safety: We have already checked that bbox contains the point
-        let key = unsafe { self.keys().get_unchecked(row).get_unchecked(col) };
-        Some(*key)
+        let row = row.min(self.keys.len() - 1);
+        let col = col.min(self.keys[row].len() - 1);
+        let in_gap = |offset: i32, index: usize, count: usize, size: i32| {
+            let gap = size * self.key_gap_percent as i32 / 200;
+            let start = index as i32 * size;
+            // the last key takes the rest, so its far side is the grid edge
+            (index > 0 && offset < start + gap)
+                || (index + 1 < count && offset >= start + size - gap)
+        };
+        if in_gap(pos.x - bbox.minx, col, self.keys[row].len(), self.key_width)
+            || in_gap(pos.y - bbox.miny, row, self.keys.len(), self.key_height)
+        {
+            return None;
+        }
+        Some((row, col))
+    }
+
+    /// `percent` of the shorter side of a key, in touchpad units.
+    pub fn key_fraction(&self, percent: u32) -> i32 {
+        self.key_width.min(self.key_height) * percent as i32 / 100
+    }
+
+    /// Whether a touch that started at `start` has moved further than
+    /// `percent` of a key, so it no longer counts as pressing the key there.
+    ///
+    /// Crossing into the next key by less than that doesn't count, so the key
+    /// under a touch near a border is latched when the finger goes down.
+    pub fn moved_off_key(&self, start: Point, pos: Point, percent: u32) -> bool {
+        start.dist_sq(pos) > self.key_fraction(percent).pow(2)
     }
 
-    pub fn _in_margins(&self, pos: Point) -> bool {
+    pub fn in_margins(&self, pos: Point) -> bool {
         !self.numpad_bbox.contains(pos)
     }
 
+    /// How far along the brightness slider `pos` is, in percent, if it is
+    /// in the strip of `height` along the top of the touchpad.
+    pub fn slider_percent(&self, pos: Point, height: i32) -> Option<u8> {
+        let bbox = &self.touchpad_bbox;
+        if pos.y > bbox.miny + height {
+            return None;
+        }
+        let x = (pos.x - bbox.minx).clamp(0, bbox.xrange());
+        Some((x * 100 / bbox.xrange().max(1)) as u8)
+    }
+
     pub fn in_numlock_bbox(&self, pos: Point) -> bool {
         self.numlock_bbox.contains(pos)
     }
@@ -137,22 +753,57 @@ impl NumpadLayout {
         self.calc_bbox.contains(pos)
     }
 
-    fn create(keys: Grid, numpad_bbox: BBox, numlock_bbox: BBox, calc_bbox: BBox) -> Self {
-        let key_width = numpad_bbox.xrange() / keys[0].len() as i32;
-        let key_height = numpad_bbox.yrange() / keys.len() as i32;
-        Self {
-            keys,
+    /// Make the numlock bbox `percent` of its size, keeping it in its corner.
+    pub fn scale_numlock_bbox(&mut self, percent: u32) {
+        let bbox = &self.numlock_bbox;
+        let touchpad = &self.touchpad_bbox;
+        // layouts without a numlock bbox have it outside of the touchpad
+        if bbox.minx > touchpad.maxx || bbox.miny > touchpad.maxy {
+            return;
+        }
+        self.numlock_bbox = bbox.scale_within(touchpad, percent as f32 / 100.0);
+    }
+
+    pub(crate) fn create(
+        touchpad_bbox: &BBox,
+        keys: Vec<Vec<EV_KEY>>,
+        numpad_bbox: BBox,
+        numlock_bbox: BBox,
+        calc_bbox: BBox,
+    ) -> Self {
+        let keys = keys
+            .into_iter()
+            .map(|row| row.into_iter().map(Some).collect())
+            .collect();
+        let mut layout = Self {
+            keys: Vec::new(),
+            touchpad_bbox: touchpad_bbox.clone(),
             numpad_bbox,
             numlock_bbox,
             calc_bbox,
-            key_width,
-            key_height,
-        }
+            key_width: 0,
+            key_height: 0,
+            key_style: Default::default(),
+            key_gap_percent: 0,
+            sequences: HashMap::new(),
+            commands: HashMap::new(),
+        };
+        layout.set_keys(keys);
+        layout
+    }
+
+    /// Replace the grid, resizing the keys to fill the numpad bbox.
+    fn set_keys(&mut self, keys: Grid) {
+        // at least 1, for when the touchpad is smaller than the number of keys
+        self.key_width = (self.numpad_bbox.xrange() / keys[0].len() as i32).max(1);
+        self.key_height = (self.numpad_bbox.yrange() / keys.len() as i32).max(1);
+        self.keys = keys;
     }
 
     pub fn ux433fa(bbox: BBox) -> Self {
         use EV_KEY::*;
         Self::create(
+            &bbox,
             vec![
                 vec![KEY_KP7, KEY_KP8, KEY_KP9, KEY_KPSLASH, KEY_BACKSPACE],
                 vec![KEY_KP4, KEY_KP5, KEY_KP6, KEY_KPASTERISK, KEY_BACKSPACE],
@@ -183,6 +834,7 @@ impl NumpadLayout {
     pub fn m433ia(bbox: BBox) -> Self {
         use EV_KEY::*;
         Self::create(
+            &bbox,
             vec![
                 vec![KEY_KP7, KEY_KP8, KEY_KP9, KEY_KPSLASH, KEY_BACKSPACE],
                 vec![KEY_KP4, KEY_KP5, KEY_KP6, KEY_KPASTERISK, KEY_BACKSPACE],
@@ -213,6 +865,7 @@ impl NumpadLayout {
     pub fn ux581(bbox: BBox) -> Self {
         use EV_KEY::*;
         Self::create(
+            &bbox,
             vec![
                 vec![KEY_KPEQUAL, KEY_5, KEY_BACKSPACE, KEY_BACKSPACE],
                 vec![KEY_KP7, KEY_KP8, KEY_KP9, KEY_KPSLASH],
@@ -244,6 +897,7 @@ impl NumpadLayout {
     pub fn gx701(bbox: BBox) -> Self {
         use EV_KEY::*;
         Self::create(
+            &bbox,
             vec![
                 vec![KEY_CALC, KEY_KPSLASH, KEY_KPASTERISK, KEY_KPMINUS],
                 vec![KEY_KP7, KEY_KP8, KEY_KP9, KEY_KPPLUS],
@@ -268,6 +922,7 @@ impl NumpadLayout {
     pub fn gx531(bbox: BBox) -> Self {
         use EV_KEY::*;
         Self::create(
+            &bbox,
             vec![
                 vec![KEY_BACKSLASH, KEY_KPSLASH, KEY_KPASTERISK, KEY_KPMINUS],
                 vec![KEY_KP7, KEY_KP8, KEY_KP9, KEY_KPPLUS],
@@ -292,6 +947,7 @@ impl NumpadLayout {
     pub fn g533(bbox: BBox) -> Self {
         use EV_KEY::*;
         Self::create(
+            &bbox,
             vec![
                 vec![KEY_KP7, KEY_KP8, KEY_KP9, KEY_KPSLASH, KEY_NUMLOCK],
                 vec![KEY_KP4, KEY_KP5, KEY_KP6, KEY_KPASTERISK, KEY_BACKSPACE],
@@ -317,16 +973,371 @@ impl NumpadLayout {
         )
     }
 
-    pub(crate) fn from_supported_layout(layout: &SupportedLayout, bbox: BBox) -> Result<Self> {
+    /// Swap the sides of the numlock and calc keys.
+    fn mirror_corners(&mut self, bbox: &BBox) {
+        self.numlock_bbox = self.numlock_bbox.mirror_x(bbox);
+        self.calc_bbox = self.calc_bbox.mirror_x(bbox);
+    }
+
+    pub(crate) fn from_supported_layout(
+        layout: &SupportedLayout,
+        bbox: BBox,
+        corner: ActivationCorner,
+    ) -> Result<Self> {
         use SupportedLayout::*;
-        let layout = match layout {
-            UX433FA => Self::ux433fa(bbox),
-            M433IA => Self::m433ia(bbox),
-            UX581 => Self::ux581(bbox),
-            GX701 => Self::gx701(bbox),
-            GX531 => Self::gx531(bbox),
-            G533 => Self::g533(bbox),
+        let touchpad_bbox = bbox.clone();
+        let (mut numpad_layout, has_corners) = match layout {
+            UX433FA => (Self::ux433fa(bbox), true),
+            M433IA => (Self::m433ia(bbox), true),
+            UX581 | UX582 => (Self::ux581(bbox), true),
+            GX701 => (Self::gx701(bbox), false),
+            GX531 => (Self::gx531(bbox), false),
+            G533 => (Self::g533(bbox), false),
+            Custom(custom) => {
+                let mut numpad_layout = Self::from_supported_layout(&custom.base, bbox, corner)?;
+                if let Some(margins) = custom.margins {
+                    numpad_layout.numpad_bbox = touchpad_bbox.apply_margins(margins.to_margins());
+                }
+                numpad_layout.set_keys(custom.keys.clone());
+                return Ok(numpad_layout);
+            }
         };
-        Ok(layout)
+        // all the layouts with corner keys have numlock at the top right
+        if corner == ActivationCorner::TopLeft {
+            if has_corners {
+                numpad_layout.mirror_corners(&touchpad_bbox);
+            } else {
+                warn!("Layout {:?} has no activation corner to move", layout);
+            }
+        }
+        Ok(numpad_layout)
     }
 }
+
+#[test]
+fn test_layout_for_model() {
+    use SupportedLayout::*;
+    assert_eq!(
+        SupportedLayout::for_model("ZenBook UX433FA_UX433FA"),
+        Some(UX433FA)
+    );
+    assert_eq!(
+        SupportedLayout::for_model("ROG Zephyrus S GX701GX_GX701GX"),
+        Some(GX701)
+    );
+    assert_eq!(SupportedLayout::for_model("zenbook ux581gv"), Some(UX581));
+    assert_eq!(
+        SupportedLayout::for_model("ZenBook Pro Duo 15 OLED UX582LR_UX582LR"),
+        Some(UX582)
+    );
+    assert_eq!(SupportedLayout::for_model("ROG Strix G533QS"), Some(G533));
+    assert_eq!(SupportedLayout::for_model("ZenBook UX425EA"), None);
+    assert_eq!(
+        SupportedLayout::for_model("VivoBook S14 UM433DA"),
+        Some(M433IA)
+    );
+}
+
+#[test]
+fn test_layout_detect() {
+    let models = |names: &[&str]| {
+        names
+            .iter()
+            .map(|name| name.to_string())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(
+        SupportedLayout::detect(&models(&["ASUS Laptop", "GX531GW"])).unwrap(),
+        SupportedLayout::GX531
+    );
+    let err = SupportedLayout::detect(&models(&["ZenBook UX425EA", "UX425EA"]))
+        .unwrap_err()
+        .to_string();
+    assert!(err.contains("\"ZenBook UX425EA / UX425EA\""), "{}", err);
+    assert!(err.contains("UX433FA, M433IA, UX581"), "{}", err);
+    assert!(SupportedLayout::detect(&[]).is_err());
+}
+
+#[test]
+fn test_layout_try_from_str() {
+    assert_eq!(
+        SupportedLayout::try_from("ux433fa").unwrap(),
+        SupportedLayout::UX433FA
+    );
+    assert_eq!(
+        SupportedLayout::try_from("GX531").unwrap(),
+        SupportedLayout::GX531
+    );
+    assert!(SupportedLayout::try_from("UX425").is_err());
+}
+
+#[test]
+fn test_custom_layout_keys() {
+    use EV_KEY::*;
+    let custom = CustomLayout {
+        base: Box::new(SupportedLayout::GX701),
+        keys: vec![
+            vec![Some(KEY_LEFT), None],
+            vec![Some(KEY_KP1), Some(KEY_KP2)],
+        ],
+        margins: None,
+    };
+    let layout = NumpadLayout::from_supported_layout(
+        &SupportedLayout::Custom(custom),
+        BBox::new(0, 1000, 0, 1000),
+        Default::default(),
+    )
+    .unwrap();
+    assert_eq!(layout.get_key(Point { x: 100, y: 100 }), Some(KEY_LEFT));
+    assert_eq!(layout.get_key(Point { x: 900, y: 100 }), None);
+    assert_eq!(layout.get_key(Point { x: 900, y: 900 }), Some(KEY_KP2));
+    // the far edge belongs to the last key
+    assert_eq!(layout.get_key(Point { x: 975, y: 975 }), Some(KEY_KP2));
+}
+
+#[test]
+fn test_slider_percent() {
+    let layout = NumpadLayout::from_supported_layout(
+        &SupportedLayout::UX433FA,
+        BBox::new(0, 1000, 0, 500),
+        Default::default(),
+    )
+    .unwrap();
+    assert_eq!(layout.slider_percent(Point { x: 0, y: 10 }, 50), Some(0));
+    assert_eq!(layout.slider_percent(Point { x: 250, y: 50 }, 50), Some(25));
+    assert_eq!(
+        layout.slider_percent(Point { x: 1000, y: 0 }, 50),
+        Some(100)
+    );
+    assert_eq!(layout.slider_percent(Point { x: 500, y: 51 }, 50), None);
+}
+
+#[test]
+fn test_activation_corner() {
+    let bbox = || BBox::new(0, 1000, 0, 500);
+    let top_left = Point { x: 10, y: 10 };
+    let top_right = Point { x: 990, y: 10 };
+    let layout =
+        NumpadLayout::from_supported_layout(&SupportedLayout::UX433FA, bbox(), Default::default())
+            .unwrap();
+    assert!(layout.in_numlock_bbox(top_right));
+    assert!(layout.in_calc_bbox(top_left));
+
+    let layout = NumpadLayout::from_supported_layout(
+        &SupportedLayout::UX433FA,
+        bbox(),
+        ActivationCorner::TopLeft,
+    )
+    .unwrap();
+    assert!(layout.in_numlock_bbox(top_left));
+    assert!(!layout.in_numlock_bbox(top_right));
+    assert!(layout.in_calc_bbox(top_right));
+}
+
+#[test]
+fn test_moved_off_key() {
+    use EV_KEY::*;
+    let bbox = BBox::new(0, 1000, 0, 500);
+    let layout = NumpadLayout::create(
+        &bbox,
+        vec![vec![KEY_KP7, KEY_KP8], vec![KEY_KP4, KEY_KP5]],
+        bbox.clone(),
+        bbox.disjoint_dummy(),
+        bbox.disjoint_dummy(),
+    );
+    // keys are 500x250, so 20% is 50 units
+    assert_eq!(layout.key_fraction(20), 50);
+    let start = Point { x: 490, y: 100 };
+    let across = Point { x: 530, y: 100 };
+    assert_eq!(layout.get_key(start), Some(KEY_KP7));
+    assert_eq!(layout.get_key(across), Some(KEY_KP8));
+    assert!(!layout.moved_off_key(start, across, 20));
+    assert!(layout.moved_off_key(start, Point { x: 541, y: 100 }, 20));
+    // the same holds across a row border, and diagonally
+    let start = Point { x: 100, y: 240 };
+    assert!(!layout.moved_off_key(start, Point { x: 100, y: 290 }, 20));
+    assert!(!layout.moved_off_key(start, Point { x: 130, y: 280 }, 20));
+    assert!(layout.moved_off_key(start, Point { x: 140, y: 280 }, 20));
+    assert!(layout.moved_off_key(start, Point { x: 101, y: 240 }, 0));
+}
+
+#[test]
+fn test_key_gap() {
+    use EV_KEY::*;
+    let bbox = BBox::new(0, 1000, 0, 500);
+    let mut layout = NumpadLayout::create(
+        &bbox,
+        vec![vec![KEY_KP7, KEY_KP8], vec![KEY_KP4, KEY_KP5]],
+        bbox.clone(),
+        bbox.disjoint_dummy(),
+        bbox.disjoint_dummy(),
+    );
+    let key = |x, y| layout.get_key(Point { x, y });
+    assert_eq!(key(490, 100), Some(KEY_KP7));
+
+    // keys are 500x250, so 20% leaves 100 units between columns and 50 between rows
+    layout.set_key_gap(20);
+    let key = |x, y| layout.get_key(Point { x, y });
+    assert_eq!(key(449, 100), Some(KEY_KP7));
+    assert_eq!(key(450, 100), None);
+    assert_eq!(key(549, 100), None);
+    assert_eq!(key(550, 100), Some(KEY_KP8));
+    assert_eq!(key(100, 240), None);
+    assert_eq!(key(100, 275), Some(KEY_KP4));
+    // the outer edges are still keys
+    assert_eq!(key(0, 0), Some(KEY_KP7));
+    assert_eq!(key(1000, 500), Some(KEY_KP5));
+
+    // half of a key at most, which leaves the middle of the keys
+    layout.set_key_gap(100);
+    let key = |x, y| layout.get_key(Point { x, y });
+    assert_eq!(key(250, 100), Some(KEY_KP7));
+    assert_eq!(key(374, 100), Some(KEY_KP7));
+    assert_eq!(key(375, 100), None);
+    assert_eq!(key(250, 200), None);
+}
+
+#[test]
+fn test_calc_bbox_priority() {
+    use EV_KEY::*;
+    let bbox = BBox::new(0, 1000, 0, 500);
+    let layout = NumpadLayout::create(
+        &bbox,
+        vec![vec![KEY_KP7, KEY_KP8], vec![KEY_KP4, KEY_KP5]],
+        bbox.clone(),
+        bbox.disjoint_dummy(),
+        BBox::new(0, 100, 0, 50),
+    );
+    assert_eq!(layout.get_key(Point { x: 50, y: 25 }), None);
+    assert_eq!(layout.get_key(Point { x: 150, y: 25 }), Some(KEY_KP7));
+    assert_eq!(layout.get_key(Point { x: 50, y: 400 }), Some(KEY_KP4));
+}
+
+#[test]
+fn test_key_style() {
+    use EV_KEY::*;
+    let mut layout = NumpadLayout::from_supported_layout(
+        &SupportedLayout::M433IA,
+        BBox::new(0, 1000, 0, 500),
+        Default::default(),
+    )
+    .unwrap();
+    assert_eq!(layout.key_codes(KEY_KP5), [KEY_KP5]);
+    assert_eq!(layout.key_codes(KEY_KPPLUS), [KEY_KPPLUS]);
+    assert_eq!(layout.key_codes(KEY_5), [KEY_LEFTSHIFT, KEY_5]);
+
+    layout.set_key_style(KeyStyle::Toprow);
+    assert_eq!(layout.key_codes(KEY_KP5), [KEY_5]);
+    assert_eq!(layout.key_codes(KEY_KPENTER), [KEY_ENTER]);
+    assert_eq!(layout.key_codes(KEY_BACKSPACE), [KEY_BACKSPACE]);
+    // percent is still Shift+5, even though 5 is now typed as KEY_5
+    assert_eq!(layout.key_codes(KEY_5), [KEY_LEFTSHIFT, KEY_5]);
+    assert_eq!(layout.key_codes(KEY_KPPLUS), [KEY_LEFTSHIFT, KEY_EQUAL]);
+    assert_eq!(layout.key_codes(KEY_KPASTERISK), [KEY_LEFTSHIFT, KEY_8]);
+}
+
+#[test]
+fn test_key_sequence() {
+    use EV_KEY::*;
+    let mut layout = NumpadLayout::from_supported_layout(
+        &SupportedLayout::M433IA,
+        BBox::new(0, 1000, 0, 500),
+        Default::default(),
+    )
+    .unwrap();
+    // AZERTY
+    layout.set_key_sequence(KEY_5, vec![KEY_LEFTSHIFT, KEY_APOSTROPHE]);
+    layout.set_key_sequence(KEY_KPEQUAL, vec![KEY_EQUAL]);
+    layout.set_key_style(KeyStyle::Toprow);
+    assert_eq!(layout.key_codes(KEY_5), [KEY_LEFTSHIFT, KEY_APOSTROPHE]);
+    assert_eq!(layout.key_codes(KEY_KPEQUAL), [KEY_EQUAL]);
+    assert_eq!(layout.key_codes(KEY_KP5), [KEY_5]);
+    assert_eq!(parse_key("LEFTSHIFT"), Some(KEY_LEFTSHIFT));
+    assert_eq!(parse_key("KEY_APOSTROPHE"), Some(KEY_APOSTROPHE));
+    assert_eq!(parse_key("PERCENT"), None);
+}
+
+#[test]
+fn test_remap() {
+    use EV_KEY::*;
+    let bbox = BBox::new(0, 1000, 0, 500);
+    let mut layout = NumpadLayout::create(
+        &bbox,
+        vec![vec![KEY_KP7, KEY_KP8], vec![KEY_KP4, KEY_KPSLASH]],
+        bbox.clone(),
+        bbox.disjoint_dummy(),
+        bbox.disjoint_dummy(),
+    );
+    let remap = |entries: &[(&str, &str)]| {
+        let raw: BTreeMap<_, _> = entries
+            .iter()
+            .map(|(cell, key)| (cell.to_string(), RawRemapTarget::Key(key.to_string())))
+            .collect();
+        KeyRemap::try_from(raw)
+    };
+    // swap two keys, and use a position over the key name
+    let swap = remap(&[
+        ("KEY_KP7", "KP8"),
+        ("KP8", "KEY_KP7"),
+        ("KEY_KPSLASH", "KEY_MUTE"),
+        ("2,2", "KEY_VOLUMEUP"),
+        ("2,1", ""),
+    ])
+    .unwrap();
+    layout.remap(&swap).unwrap();
+    assert_eq!(
+        layout.keys(),
+        &vec![
+            vec![Some(KEY_KP8), Some(KEY_KP7)],
+            vec![None, Some(KEY_VOLUMEUP)]
+        ]
+    );
+
+    assert!(
+        layout
+            .remap(&remap(&[("3,1", "KEY_MUTE")]).unwrap())
+            .is_err()
+    );
+    let err = remap(&[("KEY_KP1", "KEY_LOUDER")]).unwrap_err();
+    assert!(format!("{}", err).contains("KEY_VOLUMEUP"), "{}", err);
+    assert!(remap(&[("0,1", "KEY_MUTE")]).is_err());
+    assert_eq!(layout.get_command(Point { x: 100, y: 100 }), None);
+
+    let command: KeyRemap = toml::from_str("KEY_KP8 = {cmd = \"gnome-calculator\"}").unwrap();
+    layout.remap(&command).unwrap();
+    // the first cell has KEY_KP8 after the swap
+    assert_eq!(layout.get_key(Point { x: 100, y: 100 }), None);
+    assert_eq!(
+        layout.get_command(Point { x: 100, y: 100 }),
+        Some(&CellCommand {
+            cmd: "gnome-calculator".to_owned(),
+            args: Vec::new()
+        })
+    );
+    assert_eq!(layout.get_command(Point { x: 900, y: 100 }), None);
+    assert!(remap(&[("top left", "KEY_MUTE")]).is_err());
+}
+
+#[test]
+fn test_scale_numlock_bbox() {
+    let bbox = || BBox::new(0, 1000, 0, 500);
+    let mut layout =
+        NumpadLayout::from_supported_layout(&SupportedLayout::UX433FA, bbox(), Default::default())
+            .unwrap();
+    let corner = Point { x: 1000, y: 0 };
+    let outside = Point { x: 920, y: 60 };
+    assert!(layout.in_numlock_bbox(corner));
+    assert!(!layout.in_numlock_bbox(outside));
+    layout.scale_numlock_bbox(200);
+    assert!(layout.in_numlock_bbox(corner));
+    assert!(layout.in_numlock_bbox(outside));
+    layout.scale_numlock_bbox(50);
+    assert!(layout.in_numlock_bbox(corner));
+    assert!(!layout.in_numlock_bbox(Point { x: 940, y: 10 }));
+
+    let mut layout =
+        NumpadLayout::from_supported_layout(&SupportedLayout::GX701, bbox(), Default::default())
+            .unwrap();
+    layout.scale_numlock_bbox(1000);
+    assert!(!layout.in_numlock_bbox(corner));
+}
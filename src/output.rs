@@ -0,0 +1,174 @@
+use std::io::ErrorKind;
+use std::time::Duration;
+
+use anyhow::Result;
+use evdev_rs::enums::EV_KEY;
+use i2cdev::{
+    core::I2CDevice,
+    linux::{LinuxI2CDevice, LinuxI2CError},
+};
+
+use crate::dummy_keyboard::{DummyKeyboard, KeyEvents};
+use crate::touchpad_i2c::{Brightness, Reopen, parse_brightness_msg};
+
+/// Where the keys of the numpad go: the dummy keyboard, or stdout in a dry run.
+#[derive(Debug)]
+pub(crate) enum Keyboard {
+    Uinput(DummyKeyboard),
+    Printed,
+}
+
+impl KeyEvents for Keyboard {
+    fn keydown(&self, key: EV_KEY) {
+        match self {
+            Self::Uinput(kb) => kb.keydown(key),
+            Self::Printed => println!("Key down {:?}", key),
+        }
+    }
+
+    fn keyup(&self, key: EV_KEY) {
+        match self {
+            Self::Uinput(kb) => kb.keyup(key),
+            Self::Printed => println!("Key up {:?}", key),
+        }
+    }
+
+    fn multi_keydown(&self, keys: &[EV_KEY]) {
+        match self {
+            Self::Uinput(kb) => kb.multi_keydown(keys),
+            Self::Printed => println!("Keys down {:?}", keys),
+        }
+    }
+
+    fn multi_keyup(&self, keys: &[EV_KEY]) {
+        match self {
+            Self::Uinput(kb) => kb.multi_keyup(keys),
+            Self::Printed => println!("Keys up {:?}", keys),
+        }
+    }
+}
+
+/// Where the messages to the touchpad go: its I2C bus, or stdout in a dry run.
+///
+/// Reads fail in a dry run, since there is nothing to answer them.
+pub(crate) enum I2CBus {
+    Linux(LinuxI2CDevice),
+    Printed,
+}
+
+fn dry_run_error() -> LinuxI2CError {
+    LinuxI2CError::Io(std::io::Error::new(
+        ErrorKind::Unsupported,
+        "The touchpad isn't read in a dry run",
+    ))
+}
+
+/// How a message to the touchpad is printed in a dry run.
+fn describe_msg(data: &[u8]) -> String {
+    match parse_brightness_msg(data).map(|raw| (raw, Brightness::try_from(raw))) {
+        Some((raw, Ok(brightness))) => format!("Brightness {} ({:#04x})", brightness, raw),
+        Some((raw, Err(_))) => format!("Brightness {:#04x}", raw),
+        None => format!("I2C write {:02x?}", data),
+    }
+}
+
+impl I2CDevice for I2CBus {
+    type Error = LinuxI2CError;
+
+    fn read(&mut self, data: &mut [u8]) -> Result<(), LinuxI2CError> {
+        match self {
+            Self::Linux(dev) => dev.read(data),
+            Self::Printed => Err(dry_run_error()),
+        }
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<(), LinuxI2CError> {
+        match self {
+            Self::Linux(dev) => dev.write(data),
+            Self::Printed => {
+                println!("{}", describe_msg(data));
+                Ok(())
+            }
+        }
+    }
+
+    fn smbus_write_quick(&mut self, bit: bool) -> Result<(), LinuxI2CError> {
+        match self {
+            Self::Linux(dev) => dev.smbus_write_quick(bit),
+            Self::Printed => Err(dry_run_error()),
+        }
+    }
+
+    fn smbus_read_block_data(&mut self, register: u8) -> Result<Vec<u8>, LinuxI2CError> {
+        match self {
+            Self::Linux(dev) => dev.smbus_read_block_data(register),
+            Self::Printed => Err(dry_run_error()),
+        }
+    }
+
+    fn smbus_read_i2c_block_data(
+        &mut self,
+        register: u8,
+        len: u8,
+    ) -> Result<Vec<u8>, LinuxI2CError> {
+        match self {
+            Self::Linux(dev) => dev.smbus_read_i2c_block_data(register, len),
+            Self::Printed => Err(dry_run_error()),
+        }
+    }
+
+    fn smbus_write_block_data(&mut self, register: u8, values: &[u8]) -> Result<(), LinuxI2CError> {
+        match self {
+            Self::Linux(dev) => dev.smbus_write_block_data(register, values),
+            Self::Printed => Err(dry_run_error()),
+        }
+    }
+
+    fn smbus_write_i2c_block_data(
+        &mut self,
+        register: u8,
+        values: &[u8],
+    ) -> Result<(), LinuxI2CError> {
+        match self {
+            Self::Linux(dev) => dev.smbus_write_i2c_block_data(register, values),
+            Self::Printed => Err(dry_run_error()),
+        }
+    }
+
+    fn smbus_process_block(
+        &mut self,
+        register: u8,
+        values: &[u8],
+    ) -> Result<Vec<u8>, LinuxI2CError> {
+        match self {
+            Self::Linux(dev) => dev.smbus_process_block(register, values),
+            Self::Printed => Err(dry_run_error()),
+        }
+    }
+}
+
+impl Reopen for I2CBus {
+    fn reopen(&mut self, i2c_id: u32, timeout: Option<Duration>) -> Result<()> {
+        match self {
+            Self::Linux(dev) => dev.reopen(i2c_id, timeout),
+            Self::Printed => Ok(()),
+        }
+    }
+}
+
+#[test]
+fn test_describe_msg() {
+    use crate::touchpad_i2c::{TouchpadI2C, build_brightness_msg};
+
+    assert_eq!(
+        describe_msg(&build_brightness_msg(Brightness::Full.as_raw())),
+        "Brightness Full (0x01)"
+    );
+    assert_eq!(describe_msg(&build_brightness_msg(0xff)), "Brightness 0xff");
+    assert_eq!(describe_msg(&[0x05, 0x00]), "I2C write [05, 00]");
+
+    // nothing is there to answer
+    let mut touchpad_i2c = TouchpadI2C::from_device(I2CBus::Printed, 0);
+    touchpad_i2c.set_brightness(Brightness::Half).unwrap();
+    assert!(touchpad_i2c.read_firmware_version().is_err());
+}
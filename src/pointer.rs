@@ -0,0 +1,250 @@
+use std::collections::BTreeSet;
+
+use anyhow::{Context, Result};
+use evdev_rs::{
+    Device, DeviceWrapper, InputEvent, TimeVal, UInputDevice,
+    enums::{EV_ABS, EV_KEY, EV_SYN, EventCode},
+};
+use log::error;
+
+/// Decides which frames of the grabbed touchpad go on to the pointer.
+///
+/// A touch is followed from its first frame until all fingers are lifted,
+/// and is either forwarded whole or not at all, so that the pointer never
+/// sees half of one.
+#[derive(Debug, Default)]
+struct Frames {
+    /// Events of the current frame, sent or dropped together at `SYN_REPORT`
+    frame: Vec<InputEvent>,
+    /// Whether the touch going on is forwarded, once its first frame is complete
+    forwarding: Option<bool>,
+    /// A finger is down, as told by `BTN_TOUCH`
+    touching: bool,
+    /// The slot that the forwarded events are about
+    slot: i32,
+    /// Slots with a contact that the pointer has seen, to lift them when cut off
+    slots: BTreeSet<i32>,
+}
+
+impl Frames {
+    /// Queue `ev`, returning the events to forward once its frame is complete.
+    ///
+    /// `outside` tells whether a touch that started in this frame is outside
+    /// of the keys, and is only called then.
+    fn push(&mut self, ev: &InputEvent, outside: impl FnOnce() -> bool) -> Vec<InputEvent> {
+        if ev.event_code == EventCode::EV_KEY(EV_KEY::BTN_TOUCH) {
+            self.touching = ev.value != 0;
+        }
+        self.frame.push(ev.clone());
+        if ev.event_code != EventCode::EV_SYN(EV_SYN::SYN_REPORT) {
+            return Vec::new();
+        }
+        if self.forwarding.is_none() && self.touching {
+            self.forwarding = Some(outside());
+        }
+        let frame = std::mem::take(&mut self.frame);
+        // frames without a touch, e.g. of a physical button, always go through
+        let forward = self.forwarding.unwrap_or(true);
+        if !self.touching {
+            self.forwarding = None;
+        }
+        if !forward {
+            return Vec::new();
+        }
+        for ev in &frame {
+            match ev.event_code {
+                EventCode::EV_ABS(EV_ABS::ABS_MT_SLOT) => self.slot = ev.value,
+                EventCode::EV_ABS(EV_ABS::ABS_MT_TRACKING_ID) if ev.value >= 0 => {
+                    self.slots.insert(self.slot);
+                }
+                EventCode::EV_ABS(EV_ABS::ABS_MT_TRACKING_ID) => {
+                    self.slots.remove(&self.slot);
+                }
+                _ => (),
+            }
+        }
+        frame
+    }
+
+    /// Start over from a newly grabbed touchpad. A touch that is already
+    /// going on isn't forwarded, since the pointer got its start directly.
+    fn start(&mut self, touching: bool) {
+        self.frame.clear();
+        self.touching = touching;
+        self.forwarding = touching.then_some(false);
+    }
+
+    /// The events that lift the fingers which the pointer still thinks are down.
+    fn release(&mut self) -> Vec<InputEvent> {
+        let time = TimeVal {
+            tv_sec: 0,
+            tv_usec: 0,
+        };
+        let event = |event_code, value| InputEvent {
+            time,
+            event_code,
+            value,
+        };
+        self.frame.clear();
+        let forwarding = self.forwarding.take() == Some(true);
+        self.touching = false;
+        if !forwarding && self.slots.is_empty() {
+            return Vec::new();
+        }
+        let mut events = Vec::new();
+        for slot in std::mem::take(&mut self.slots) {
+            events.push(event(EventCode::EV_ABS(EV_ABS::ABS_MT_SLOT), slot));
+            events.push(event(EventCode::EV_ABS(EV_ABS::ABS_MT_TRACKING_ID), -1));
+        }
+        for key in [
+            EV_KEY::BTN_TOUCH,
+            EV_KEY::BTN_TOOL_FINGER,
+            EV_KEY::BTN_TOOL_DOUBLETAP,
+            EV_KEY::BTN_TOOL_TRIPLETAP,
+            EV_KEY::BTN_TOOL_QUADTAP,
+            EV_KEY::BTN_TOOL_QUINTTAP,
+            EV_KEY::BTN_LEFT,
+        ] {
+            events.push(event(EventCode::EV_KEY(key), 0));
+        }
+        events.push(event(EventCode::EV_SYN(EV_SYN::SYN_REPORT), 0));
+        events
+    }
+}
+
+/// A virtual copy of the touchpad, which moves the pointer with the touches
+/// that start outside of the keys while the real one is grabbed.
+pub(crate) struct PointerMirror {
+    udev: UInputDevice,
+    frames: Frames,
+}
+
+impl std::fmt::Debug for PointerMirror {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PointerMirror")
+            .field("udev", &self.udev.devnode())
+            .field("frames", &self.frames)
+            .finish()
+    }
+}
+
+impl PointerMirror {
+    /// Create a device with the axes, buttons and properties of `touchpad`.
+    pub(crate) fn new(touchpad: &Device) -> Result<Self> {
+        // a handle of our own, to not rename the touchpad itself
+        let file = touchpad
+            .file()
+            .try_clone()
+            .context("Could not duplicate the touchpad fd")?;
+        let copy = Device::new_from_file(file).context("Could not read the touchpad")?;
+        // not a name that is taken for the touchpad when looking for it
+        copy.set_name("asus_numpad pointer");
+        let udev = UInputDevice::create_from_device(&copy)
+            .context("Unable to create the pointer UInput device")?;
+        Ok(Self {
+            udev,
+            frames: Frames::default(),
+        })
+    }
+
+    /// Pass `ev` on if it belongs to a touch that starts outside of the keys,
+    /// as told by `outside`.
+    pub(crate) fn forward(&mut self, ev: &InputEvent, outside: impl FnOnce() -> bool) {
+        let events = self.frames.push(ev, outside);
+        self.write(&events);
+    }
+
+    /// Follow the touchpad again after it was grabbed, with a finger
+    /// `touching` it or not.
+    pub(crate) fn start(&mut self, touching: bool) {
+        self.frames.start(touching);
+    }
+
+    /// Lift whatever is down on the pointer, once the touchpad isn't grabbed
+    /// anymore.
+    pub(crate) fn release(&mut self) {
+        let events = self.frames.release();
+        self.write(&events);
+    }
+
+    fn write(&self, events: &[InputEvent]) {
+        for ev in events {
+            if let Err(err) = self.udev.write_event(ev) {
+                error!("Failed to move the pointer: {}", err);
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+fn frame(events: &[(EventCode, i32)]) -> Vec<InputEvent> {
+    let time = TimeVal {
+        tv_sec: 0,
+        tv_usec: 0,
+    };
+    events
+        .iter()
+        .chain([&(EventCode::EV_SYN(EV_SYN::SYN_REPORT), 0)])
+        .map(|&(event_code, value)| InputEvent {
+            time,
+            event_code,
+            value,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+fn push_frame(frames: &mut Frames, events: &[(EventCode, i32)], outside: bool) -> usize {
+    frame(events)
+        .iter()
+        .map(|ev| frames.push(ev, || outside).len())
+        .sum()
+}
+
+#[test]
+fn test_frames() {
+    use EventCode::{EV_ABS as Abs, EV_KEY as Key};
+    let down = [
+        (Abs(EV_ABS::ABS_MT_TRACKING_ID), 1),
+        (Key(EV_KEY::BTN_TOUCH), 1),
+        (Abs(EV_ABS::ABS_MT_POSITION_X), 100),
+    ];
+    let up = [
+        (Abs(EV_ABS::ABS_MT_TRACKING_ID), -1),
+        (Key(EV_KEY::BTN_TOUCH), 0),
+    ];
+    let moved = [(Abs(EV_ABS::ABS_MT_POSITION_X), 2000)];
+    let mut frames = Frames::default();
+
+    // a touch outside of the keys goes through whole, even onto them
+    assert_eq!(push_frame(&mut frames, &down, true), 4);
+    assert_eq!(push_frame(&mut frames, &moved, false), 2);
+    assert_eq!(push_frame(&mut frames, &up, false), 3);
+    assert!(frames.slots.is_empty());
+
+    // and one on the keys doesn't at all
+    assert_eq!(push_frame(&mut frames, &down, false), 0);
+    assert_eq!(push_frame(&mut frames, &moved, true), 0);
+    assert_eq!(push_frame(&mut frames, &up, true), 0);
+    assert!(frames.release().is_empty());
+
+    // other frames do
+    assert_eq!(
+        push_frame(&mut frames, &[(Key(EV_KEY::BTN_LEFT), 1)], false),
+        2
+    );
+
+    // cut off in the middle, the pointer gets a lift
+    push_frame(&mut frames, &down, true);
+    let release = frames.release();
+    assert_eq!(release[1].event_code, Abs(EV_ABS::ABS_MT_TRACKING_ID));
+    assert_eq!(release[1].value, -1);
+    assert!(frames.release().is_empty());
+
+    // a touch from before the grab stays with the touchpad
+    frames.start(true);
+    assert_eq!(push_frame(&mut frames, &moved, true), 0);
+    assert_eq!(push_frame(&mut frames, &up, true), 0);
+    assert_eq!(push_frame(&mut frames, &down, true), 4);
+}
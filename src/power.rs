@@ -0,0 +1,248 @@
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::time::Duration;
+
+#[cfg(feature = "dbus")]
+use anyhow::anyhow;
+use anyhow::{Context, Result};
+#[cfg(feature = "dbus")]
+use log::{debug, warn};
+
+/// Tells when the system has resumed from suspend, for restoring what the
+/// touchpad forgot.
+#[derive(Debug)]
+pub(crate) enum ResumeWatcher {
+    #[cfg(feature = "dbus")]
+    Logind(LogindWatcher),
+    Clock(ClockWatcher),
+}
+
+impl ResumeWatcher {
+    /// Listen to logind if possible, falling back to watching the clocks.
+    pub(crate) fn new() -> Result<Self> {
+        #[cfg(feature = "dbus")]
+        match LogindWatcher::start() {
+            Ok(watcher) => return Ok(Self::Logind(watcher)),
+            Err(err) => debug!("Not using logind to detect resume: {:#}", err),
+        }
+        ClockWatcher::new().map(Self::Clock)
+    }
+
+    /// Check whether the system resumed since the last call.
+    ///
+    /// Must be called when the fd is readable, to be woken up again next time.
+    /// The fd changes if logind stops sending signals, since the clocks are
+    /// watched instead.
+    pub(crate) fn resumed(&mut self) -> Result<bool> {
+        match self {
+            #[cfg(feature = "dbus")]
+            Self::Logind(watcher) => match watcher.resumed() {
+                Ok(resumed) => Ok(resumed),
+                Err(err) => {
+                    warn!("Watching the clocks to detect resume instead: {:#}", err);
+                    *self = ClockWatcher::new().map(Self::Clock)?;
+                    Ok(false)
+                }
+            },
+            Self::Clock(watcher) => watcher.resumed(),
+        }
+    }
+}
+
+impl AsRawFd for ResumeWatcher {
+    fn as_raw_fd(&self) -> RawFd {
+        match self {
+            #[cfg(feature = "dbus")]
+            Self::Logind(watcher) => watcher.reader.as_raw_fd(),
+            Self::Clock(watcher) => watcher.fd.as_raw_fd(),
+        }
+    }
+}
+
+/// Receives logind's `PrepareForSleep` signal, which is sent before
+/// suspending and after resuming.
+///
+/// The signals arrive on a thread of their own, which passes them on through
+/// a socket that the event loop can poll.
+#[cfg(feature = "dbus")]
+#[derive(Debug)]
+pub(crate) struct LogindWatcher {
+    reader: std::os::unix::net::UnixStream,
+}
+
+#[cfg(feature = "dbus")]
+impl LogindWatcher {
+    fn start() -> Result<Self> {
+        use std::io::Write;
+
+        let conn = zbus::blocking::Connection::system()?;
+        let proxy = zbus::blocking::Proxy::new(
+            &conn,
+            "org.freedesktop.login1",
+            "/org/freedesktop/login1",
+            "org.freedesktop.login1.Manager",
+        )?;
+        let signals = proxy
+            .receive_signal("PrepareForSleep")
+            .context("Could not subscribe to PrepareForSleep")?;
+        let (reader, mut writer) = std::os::unix::net::UnixStream::pair()?;
+        reader.set_nonblocking(true)?;
+        std::thread::spawn(move || {
+            // keep the subscription alive
+            let _proxy = proxy;
+            for msg in signals {
+                let start = match msg.body().deserialize::<bool>() {
+                    Ok(start) => start,
+                    Err(err) => {
+                        debug!("Invalid PrepareForSleep signal: {}", err);
+                        continue;
+                    }
+                };
+                if writer.write_all(&[start as u8]).is_err() {
+                    // the watcher is gone
+                    break;
+                }
+            }
+        });
+        Ok(Self { reader })
+    }
+
+    /// Check for a resume in the signals passed on since the last call.
+    ///
+    /// Fails once the thread receiving them has ended, e.g. because the
+    /// connection to the system bus dropped.
+    fn resumed(&mut self) -> Result<bool> {
+        use std::io::{ErrorKind, Read};
+
+        let mut resumed = false;
+        let mut buf = [0; 16];
+        loop {
+            match self.reader.read(&mut buf) {
+                Ok(0) => return Err(anyhow!("The PrepareForSleep signals stopped")),
+                Ok(len) => {
+                    for &start in &buf[..len] {
+                        if start == 1 {
+                            debug!("Preparing for sleep");
+                        } else {
+                            resumed = true;
+                        }
+                    }
+                }
+                Err(err) if err.kind() == ErrorKind::WouldBlock => return Ok(resumed),
+                Err(err) if err.kind() == ErrorKind::Interrupted => (),
+                Err(err) => return Err(err).context("Could not read the PrepareForSleep signals"),
+            }
+        }
+    }
+}
+
+/// Time spent suspended since boot.
+///
+/// `CLOCK_BOOTTIME` keeps counting while suspended, but `CLOCK_MONOTONIC` doesn't.
+fn suspended_time() -> Duration {
+    let now = |clock| {
+        let mut ts: libc::timespec = unsafe { std::mem::zeroed() };
+        unsafe { libc::clock_gettime(clock, &mut ts) };
+        Duration::new(ts.tv_sec as u64, ts.tv_nsec as u32)
+    };
+    let monotonic = now(libc::CLOCK_MONOTONIC);
+    now(libc::CLOCK_BOOTTIME).saturating_sub(monotonic)
+}
+
+/// Becomes readable when the system might have resumed from suspend, for
+/// when logind isn't available.
+///
+/// The kernel cancels realtime timers armed with `TFD_TIMER_CANCEL_ON_SET`
+/// when resuming, as well as when the clock is set, so this doesn't need any
+/// periodic wakeups. The two cases are told apart with [`suspended_time`].
+#[derive(Debug)]
+pub(crate) struct ClockWatcher {
+    fd: OwnedFd,
+    suspended: Duration,
+}
+
+impl ClockWatcher {
+    /// Suspends shorter than this are not worth reacting to. Also makes up
+    /// for the clocks being read at slightly different times.
+    const MIN_SUSPEND: Duration = Duration::from_millis(100);
+
+    fn new() -> Result<Self> {
+        let fd = unsafe {
+            libc::timerfd_create(libc::CLOCK_REALTIME, libc::TFD_NONBLOCK | libc::TFD_CLOEXEC)
+        };
+        if fd < 0 {
+            return Err(std::io::Error::last_os_error()).context("Could not create timerfd");
+        }
+        let watcher = Self {
+            fd: unsafe { OwnedFd::from_raw_fd(fd) },
+            suspended: suspended_time(),
+        };
+        watcher.arm()?;
+        Ok(watcher)
+    }
+
+    /// Set a timer that never expires, just to be told about clock changes.
+    fn arm(&self) -> Result<()> {
+        let mut spec: libc::itimerspec = unsafe { std::mem::zeroed() };
+        spec.it_value.tv_sec = libc::time_t::MAX;
+        let res = unsafe {
+            libc::timerfd_settime(
+                self.fd.as_raw_fd(),
+                libc::TFD_TIMER_ABSTIME | libc::TFD_TIMER_CANCEL_ON_SET,
+                &spec,
+                std::ptr::null_mut(),
+            )
+        };
+        if res != 0 {
+            return Err(std::io::Error::last_os_error()).context("Could not arm timerfd");
+        }
+        Ok(())
+    }
+
+    /// Check whether the system was suspended since the last call.
+    fn resumed(&mut self) -> Result<bool> {
+        let mut expirations = 0u64;
+        // fails with ECANCELED, which is what we're waiting for
+        unsafe {
+            libc::read(
+                self.fd.as_raw_fd(),
+                (&mut expirations as *mut u64).cast(),
+                std::mem::size_of::<u64>(),
+            )
+        };
+        self.arm()?;
+        let suspended = suspended_time();
+        let resumed = suspended >= self.suspended + Self::MIN_SUSPEND;
+        self.suspended = suspended;
+        Ok(resumed)
+    }
+}
+
+#[test]
+fn test_clock_watcher() {
+    let mut watcher = ClockWatcher::new().unwrap();
+    let mut fd = libc::pollfd {
+        fd: watcher.fd.as_raw_fd(),
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    assert_eq!(unsafe { libc::poll(&mut fd, 1, 0) }, 0);
+    assert!(!watcher.resumed().unwrap());
+}
+
+#[cfg(feature = "dbus")]
+#[test]
+fn test_logind_watcher_stopped() {
+    use std::io::Write;
+
+    let (reader, mut writer) = std::os::unix::net::UnixStream::pair().unwrap();
+    reader.set_nonblocking(true).unwrap();
+    let mut watcher = ResumeWatcher::Logind(LogindWatcher { reader });
+    writer.write_all(&[1, 0]).unwrap();
+    assert!(watcher.resumed().unwrap());
+    assert!(!watcher.resumed().unwrap());
+
+    // the thread passing the signals on is gone, so the clocks take over
+    drop(writer);
+    assert!(!watcher.resumed().unwrap());
+    assert!(matches!(watcher, ResumeWatcher::Clock(_)));
+}
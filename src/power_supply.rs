@@ -0,0 +1,98 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result, anyhow};
+
+#[cfg(test)]
+use crate::testing::TempDir;
+
+const SYSFS_POWER_SUPPLY: &str = "/sys/class/power_supply";
+
+/// The AC adapter, read through the power supply sysfs interface.
+#[derive(Debug)]
+pub(crate) struct AcAdapter {
+    online_path: PathBuf,
+    /// Whether it was plugged in last time
+    last: bool,
+}
+
+impl AcAdapter {
+    /// Find the first power supply that is mains.
+    pub(crate) fn find() -> Result<Self> {
+        Self::find_in(Path::new(SYSFS_POWER_SUPPLY))
+    }
+
+    fn find_in(supplies_dir: &Path) -> Result<Self> {
+        let entries = std::fs::read_dir(supplies_dir)
+            .with_context(|| format!("Could not list {}", supplies_dir.display()))?;
+        let mut dirs: Vec<_> = entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| {
+                std::fs::read_to_string(path.join("type")).is_ok_and(|kind| kind.trim() == "Mains")
+                    && path.join("online").exists()
+            })
+            .collect();
+        // the order of read_dir isn't stable
+        dirs.sort();
+        let dir = dirs
+            .into_iter()
+            .next()
+            .ok_or_else(|| anyhow!("No AC adapter in {}", supplies_dir.display()))?;
+        let mut adapter = Self {
+            online_path: dir.join("online"),
+            last: false,
+        };
+        adapter.last = adapter.online()?;
+        Ok(adapter)
+    }
+
+    /// Whether the AC adapter is plugged in.
+    pub(crate) fn online(&self) -> Result<bool> {
+        let value = std::fs::read_to_string(&self.online_path)
+            .with_context(|| format!("Could not read {}", self.online_path.display()))?;
+        match value.trim() {
+            "0" => Ok(false),
+            "1" => Ok(true),
+            other => Err(anyhow!(
+                "Invalid value {:?} in {}",
+                other,
+                self.online_path.display()
+            )),
+        }
+    }
+
+    /// Read the adapter, telling whether it is plugged in if that is
+    /// different from the last time. It being plugged in or not when it was
+    /// found doesn't count as a change.
+    pub(crate) fn changed(&mut self) -> Result<Option<bool>> {
+        let online = self.online()?;
+        if self.last == online {
+            return Ok(None);
+        }
+        self.last = online;
+        Ok(Some(online))
+    }
+}
+
+#[test]
+fn test_ac_adapter() {
+    let dir = TempDir::new("power");
+    let supplies = dir.path();
+    assert!(AcAdapter::find_in(supplies).is_err());
+    let battery = supplies.join("BAT0");
+    std::fs::create_dir_all(&battery).unwrap();
+    std::fs::write(battery.join("type"), "Battery\n").unwrap();
+    std::fs::write(battery.join("online"), "1\n").unwrap();
+    assert!(AcAdapter::find_in(supplies).is_err());
+    let adapter = supplies.join("AC0");
+    std::fs::create_dir_all(&adapter).unwrap();
+    std::fs::write(adapter.join("type"), "Mains\n").unwrap();
+    std::fs::write(adapter.join("online"), "1\n").unwrap();
+
+    let mut ac = AcAdapter::find_in(supplies).unwrap();
+    assert_eq!(ac.changed().unwrap(), None);
+    std::fs::write(adapter.join("online"), "0\n").unwrap();
+    assert_eq!(ac.changed().unwrap(), Some(false));
+    std::fs::write(adapter.join("online"), "?\n").unwrap();
+    assert!(ac.changed().is_err());
+}
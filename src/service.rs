@@ -0,0 +1,85 @@
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use anyhow::{Context, Result, anyhow};
+
+/// The unit in `tools/`, which runs `asus-numpad` from `$PATH`.
+const TEMPLATE: &str = include_str!("../tools/asus-numpad.service");
+
+const UNIT_NAME: &str = "asus-numpad.service";
+
+/// The unit file, running `exe`.
+///
+/// With `restart_on_resume`, it is also started again after a suspend, in
+/// case it didn't survive it.
+fn service_unit(exe: &Path, restart_on_resume: bool) -> String {
+    let mut unit = String::new();
+    for line in TEMPLATE.lines() {
+        match line {
+            _ if line.starts_with("ExecStart=") => {
+                unit.push_str(&format!("ExecStart={}", exe.display()))
+            }
+            _ if line.starts_with("Description=") && restart_on_resume => {
+                unit.push_str(line);
+                unit.push_str("\nAfter=suspend.target");
+            }
+            "WantedBy=default.target" if restart_on_resume => {
+                unit.push_str("WantedBy=default.target suspend.target")
+            }
+            _ => unit.push_str(line),
+        }
+        unit.push('\n');
+    }
+    unit
+}
+
+/// Where the unit goes: `/etc/systemd/system`, or
+/// `$XDG_CONFIG_HOME/systemd/user` for the user's own service manager.
+pub(crate) fn unit_path(user: bool) -> Result<PathBuf> {
+    if !user {
+        return Ok(Path::new("/etc/systemd/system").join(UNIT_NAME));
+    }
+    let non_empty = |var| std::env::var_os(var).filter(|val| !val.is_empty());
+    let config_home = non_empty("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| non_empty("HOME").map(|home| PathBuf::from(home).join(".config")))
+        .ok_or_else(|| anyhow!("Neither XDG_CONFIG_HOME nor HOME is set"))?;
+    Ok(config_home.join("systemd/user").join(UNIT_NAME))
+}
+
+/// Write the unit for the running binary to `path`, and have systemd pick
+/// it up if it is around.
+pub(crate) fn install(path: &Path, user: bool, restart_on_resume: bool) -> Result<()> {
+    let exe = std::env::current_exe().context("Could not find the running binary")?;
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("Could not create {}", dir.display()))?;
+    }
+    std::fs::write(path, service_unit(&exe, restart_on_resume))
+        .with_context(|| format!("Could not write {}", path.display()))?;
+
+    let mut systemctl = Command::new("systemctl");
+    if user {
+        systemctl.arg("--user");
+    }
+    match systemctl.arg("daemon-reload").status() {
+        Ok(status) if !status.success() => Err(anyhow!("systemctl daemon-reload {}", status)),
+        Err(err) if err.kind() != ErrorKind::NotFound => {
+            Err(err).context("Could not run systemctl daemon-reload")
+        }
+        _ => Ok(()),
+    }
+}
+
+#[test]
+fn test_service_unit() {
+    let unit = service_unit(Path::new("/usr/local/bin/asus-numpad"), false);
+    assert!(unit.contains("\nExecStart=/usr/local/bin/asus-numpad\n"));
+    assert!(!unit.contains("suspend.target"));
+    assert_eq!(unit.lines().count(), TEMPLATE.lines().count());
+
+    let unit = service_unit(Path::new("/usr/bin/asus-numpad"), true);
+    assert!(unit.contains("Description=Asus Numpad Driver\nAfter=suspend.target\n\n[Service]\n"));
+    assert!(unit.contains("\nWantedBy=default.target suspend.target\n"));
+}
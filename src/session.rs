@@ -0,0 +1,130 @@
+use std::process::Command;
+
+use anyhow::{Context, Result, anyhow};
+use log::{debug, warn};
+
+/// The kind of graphical session that the daemon runs in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Session {
+    X11,
+    Wayland,
+}
+
+impl Session {
+    /// Tell the session from the environment of the daemon, if it has one.
+    pub(crate) fn detect() -> Option<Self> {
+        Self::from_env(|var| std::env::var(var).ok().filter(|value| !value.is_empty()))
+    }
+
+    fn from_env(var: impl Fn(&str) -> Option<String>) -> Option<Self> {
+        match var("XDG_SESSION_TYPE").as_deref() {
+            Some("x11") => return Some(Self::X11),
+            Some("wayland") => return Some(Self::Wayland),
+            _ => (),
+        }
+        // XWayland sets DISPLAY too
+        if var("WAYLAND_DISPLAY").is_some() {
+            Some(Self::Wayland)
+        } else if var("DISPLAY").is_some() {
+            Some(Self::X11)
+        } else {
+            None
+        }
+    }
+}
+
+/// Turns the touchpad off in the display server while the numpad is on, so
+/// that e.g. tap-to-click can't fire from events that came before the grab.
+///
+/// On X11, this sets the `Device Enabled` property with `xinput`. Wayland
+/// compositors have no such thing, so there it is only grabbed. Flushing the
+/// events that came before the grab wouldn't help there: every reader of the
+/// evdev node has a queue of its own, so the compositor keeps the ones it
+/// read, and only the numpad would miss the start of the touch.
+#[derive(Debug)]
+pub(crate) struct SessionPointer {
+    session: Session,
+    /// The name of the touchpad, as the display server knows it
+    device: String,
+    enabled: bool,
+}
+
+impl SessionPointer {
+    /// Follow the session of the daemon, if it is in one.
+    ///
+    /// On X11, the touchpad is enabled right away, in case a previous run
+    /// didn't get to it.
+    pub(crate) fn new(device: &str) -> Option<Self> {
+        let Some(session) = Session::detect() else {
+            warn!("Not disabling the touchpad in the session, since there is none");
+            return None;
+        };
+        debug!("Running in a {:?} session", session);
+        let mut pointer = Self {
+            session,
+            device: device.to_owned(),
+            enabled: false,
+        };
+        pointer.set_enabled(true);
+        Some(pointer)
+    }
+
+    /// Enable or disable the touchpad in the session. Failures are only logged.
+    pub(crate) fn set_enabled(&mut self, enabled: bool) {
+        if self.session != Session::X11 || self.enabled == enabled {
+            return;
+        }
+        match set_device_enabled(&self.device, enabled) {
+            Ok(()) => self.enabled = enabled,
+            Err(err) => warn!("Failed to change the touchpad in X11: {:#}", err),
+        }
+    }
+}
+
+fn set_device_enabled(device: &str, enabled: bool) -> Result<()> {
+    // several devices can have the name of the touchpad, so pick its pointer
+    let status = Command::new("xinput")
+        .arg("set-prop")
+        .arg(format!("pointer:{}", device))
+        .arg("Device Enabled")
+        .arg(if enabled { "1" } else { "0" })
+        .status()
+        .context("Could not run xinput")?;
+    if !status.success() {
+        return Err(anyhow!("xinput set-prop {}", status));
+    }
+    debug!(
+        "{} {} in X11",
+        if enabled { "Enabled" } else { "Disabled" },
+        device
+    );
+    Ok(())
+}
+
+#[test]
+fn test_session_from_env() {
+    let env = |vars: &'static [(&str, &str)]| {
+        move |var: &str| {
+            vars.iter()
+                .find(|(name, _)| *name == var)
+                .map(|(_, value)| value.to_string())
+        }
+    };
+    assert_eq!(Session::from_env(env(&[])), None);
+    assert_eq!(
+        Session::from_env(env(&[("DISPLAY", ":0")])),
+        Some(Session::X11)
+    );
+    assert_eq!(
+        Session::from_env(env(&[("DISPLAY", ":0"), ("WAYLAND_DISPLAY", "wayland-0")])),
+        Some(Session::Wayland)
+    );
+    assert_eq!(
+        Session::from_env(env(&[
+            ("XDG_SESSION_TYPE", "x11"),
+            ("WAYLAND_DISPLAY", "wayland-0")
+        ])),
+        Some(Session::X11)
+    );
+    assert_eq!(Session::from_env(env(&[("XDG_SESSION_TYPE", "tty")])), None);
+}
@@ -0,0 +1,158 @@
+use std::io::{ErrorKind, Read};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::sync::atomic::{AtomicI32, Ordering};
+
+use anyhow::{Context, Result, anyhow};
+
+/// What the daemon does when it gets a signal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum SignalAction {
+    /// Save the state and exit, on `SIGTERM` and `SIGINT`
+    Exit,
+    /// Step up to the next brightness level, on `SIGUSR1`
+    CycleBrightness,
+    /// Turn the numpad off, on `SIGUSR2`
+    TurnOff,
+    /// Read the config again, on `SIGHUP`
+    ReloadConfig,
+}
+
+impl SignalAction {
+    /// The signals that have an action, to install handlers for.
+    pub(crate) const SIGNALS: [libc::c_int; 5] = [
+        libc::SIGTERM,
+        libc::SIGINT,
+        libc::SIGUSR1,
+        libc::SIGUSR2,
+        libc::SIGHUP,
+    ];
+
+    /// The action for `signum`, if it has one.
+    ///
+    /// `SIGUSR1` used to toggle the numpad and `SIGUSR2` to cycle the
+    /// brightness, which keybindings may still expect.
+    pub(crate) fn for_signal(signum: libc::c_int) -> Option<Self> {
+        match signum {
+            libc::SIGTERM | libc::SIGINT => Some(Self::Exit),
+            libc::SIGUSR1 => Some(Self::CycleBrightness),
+            libc::SIGUSR2 => Some(Self::TurnOff),
+            libc::SIGHUP => Some(Self::ReloadConfig),
+            _ => None,
+        }
+    }
+}
+
+/// Write end of the pipe that the signal handler reports to.
+static SIGNAL_FD: AtomicI32 = AtomicI32::new(-1);
+
+extern "C" fn on_signal(signum: libc::c_int) {
+    let fd = SIGNAL_FD.load(Ordering::Relaxed);
+    if fd >= 0 {
+        // only async-signal-safe calls are allowed here, and a failed write
+        // must not clobber the errno of whatever the signal interrupted
+        let byte = signum as u8;
+        unsafe {
+            let errno = *libc::__errno_location();
+            libc::write(fd, (&byte as *const u8).cast(), 1);
+            *libc::__errno_location() = errno;
+        }
+    }
+}
+
+/// Turns signals into readable data on an fd, so they can be handled in the event loop.
+#[derive(Debug)]
+pub(crate) struct SignalPipe {
+    reader: UnixStream,
+    /// Kept open for the handler
+    _writer: UnixStream,
+    signals: Vec<libc::c_int>,
+}
+
+impl SignalPipe {
+    /// Install handlers for `signals`. Only one pipe can exist at a time.
+    pub(crate) fn install(signals: &[libc::c_int]) -> Result<Self> {
+        let (reader, writer) = UnixStream::pair().context("Could not create signal pipe")?;
+        reader.set_nonblocking(true)?;
+        writer.set_nonblocking(true)?;
+        if SIGNAL_FD
+            .compare_exchange(-1, writer.as_raw_fd(), Ordering::SeqCst, Ordering::SeqCst)
+            .is_err()
+        {
+            return Err(anyhow!("Signal handlers are already installed"));
+        }
+        for &signum in signals {
+            let mut action: libc::sigaction = unsafe { std::mem::zeroed() };
+            action.sa_sigaction = on_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+            action.sa_flags = libc::SA_RESTART;
+            unsafe { libc::sigemptyset(&mut action.sa_mask) };
+            if unsafe { libc::sigaction(signum, &action, std::ptr::null_mut()) } != 0 {
+                return Err(std::io::Error::last_os_error())
+                    .with_context(|| format!("Could not handle signal {}", signum));
+            }
+        }
+        Ok(Self {
+            reader,
+            _writer: writer,
+            signals: signals.to_vec(),
+        })
+    }
+
+    /// Take the signals received since the last call, in order.
+    pub(crate) fn pending(&mut self) -> Vec<libc::c_int> {
+        let mut signals = Vec::new();
+        let mut buf = [0; 16];
+        loop {
+            match self.reader.read(&mut buf) {
+                Ok(0) => break,
+                Ok(len) => signals.extend(buf[..len].iter().map(|&b| b as libc::c_int)),
+                Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                Err(_) => break,
+            }
+        }
+        signals
+    }
+}
+
+impl Drop for SignalPipe {
+    fn drop(&mut self) {
+        for &signum in &self.signals {
+            unsafe { libc::signal(signum, libc::SIG_DFL) };
+        }
+        SIGNAL_FD.store(-1, Ordering::SeqCst);
+    }
+}
+
+impl AsRawFd for SignalPipe {
+    fn as_raw_fd(&self) -> RawFd {
+        self.reader.as_raw_fd()
+    }
+}
+
+#[test]
+fn test_signal_actions() {
+    use SignalAction::*;
+    assert_eq!(SignalAction::for_signal(libc::SIGTERM), Some(Exit));
+    assert_eq!(SignalAction::for_signal(libc::SIGINT), Some(Exit));
+    assert_eq!(
+        SignalAction::for_signal(libc::SIGUSR1),
+        Some(CycleBrightness)
+    );
+    assert_eq!(SignalAction::for_signal(libc::SIGUSR2), Some(TurnOff));
+    assert_eq!(SignalAction::for_signal(libc::SIGHUP), Some(ReloadConfig));
+    assert_eq!(SignalAction::for_signal(libc::SIGPIPE), None);
+    for signum in SignalAction::SIGNALS {
+        assert!(SignalAction::for_signal(signum).is_some());
+    }
+}
+
+#[test]
+fn test_signal_pipe() {
+    let mut pipe = SignalPipe::install(&[libc::SIGUSR1]).unwrap();
+    assert!(SignalPipe::install(&[libc::SIGUSR2]).is_err());
+    assert!(pipe.pending().is_empty());
+    unsafe { libc::raise(libc::SIGUSR1) };
+    unsafe { libc::raise(libc::SIGUSR1) };
+    assert_eq!(pipe.pending(), [libc::SIGUSR1, libc::SIGUSR1]);
+    assert!(pipe.pending().is_empty());
+}
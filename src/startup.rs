@@ -0,0 +1,94 @@
+use i2cdev::core::I2CDevice;
+use log::warn;
+
+#[cfg(test)]
+use crate::testing::MockI2CDevice;
+use crate::touchpad_i2c::{Brightness, DeviceError, Reopen, TouchpadI2C};
+
+/// How the numpad comes up when the daemon starts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Startup {
+    /// Turn the numpad on, and the system numlock with it
+    Enable,
+    /// The system numlock is already on, follow it
+    FollowNumlock,
+    /// Turn the system numlock off, the numpad follows it once it's off
+    DisableNumlock,
+    /// Leave the numpad off
+    Off,
+}
+
+impl Startup {
+    /// Pick the startup from the numlock led and the configuration.
+    ///
+    /// `start_enabled` comes from the command line or the saved state, and
+    /// takes precedence over `enable_on_start` and `disable_numlock_on_start`.
+    pub(crate) fn new(
+        numlock: bool,
+        start_enabled: Option<bool>,
+        enable_on_start: bool,
+        disable_numlock_on_start: bool,
+    ) -> Self {
+        let enable_on_start = start_enabled.unwrap_or(enable_on_start);
+        let disable_numlock_on_start = start_enabled.map_or(disable_numlock_on_start, |e| !e);
+        match (numlock, enable_on_start) {
+            (false, true) => Self::Enable,
+            (true, true) => Self::FollowNumlock,
+            (true, false) if disable_numlock_on_start => Self::DisableNumlock,
+            (true, false) => Self::FollowNumlock,
+            (false, false) => Self::Off,
+        }
+    }
+}
+
+/// Set the backlight before the numpad is brought up, since it might have
+/// been left on by a previous run.
+///
+/// A failure is only logged: the numpad should still come up as configured,
+/// even if it stays dark.
+pub(crate) fn restore_brightness<D>(touchpad_i2c: &mut TouchpadI2C<D>, brightness: Brightness)
+where
+    D: I2CDevice + Reopen,
+    D::Error: DeviceError + Send + Sync + 'static,
+{
+    if let Err(err) = touchpad_i2c.set_brightness(brightness) {
+        warn!("Failed to restore the brightness at startup: {:#}", err);
+    }
+}
+
+#[test]
+fn test_startup() {
+    use Startup::*;
+    // (numlock, start_enabled, enable_on_start, disable_numlock_on_start)
+    for (numlock, start_enabled, enable, disable, expected) in [
+        (false, None, false, false, Off),
+        (false, None, true, false, Enable),
+        (true, None, true, false, FollowNumlock),
+        (true, None, false, false, FollowNumlock),
+        (true, None, false, true, DisableNumlock),
+        // the command line or saved state wins over the config
+        (false, Some(true), false, true, Enable),
+        (true, Some(false), true, false, DisableNumlock),
+        (false, Some(false), true, false, Off),
+    ] {
+        let startup = Startup::new(numlock, start_enabled, enable, disable);
+        assert_eq!(
+            startup, expected,
+            "numlock {numlock}, start_enabled {start_enabled:?}, enable {enable}, disable {disable}"
+        );
+    }
+}
+
+#[test]
+fn test_startup_brightness_fails() {
+    let dev = MockI2CDevice {
+        write_errors: vec![std::io::ErrorKind::PermissionDenied],
+        ..Default::default()
+    };
+    let mut touchpad = TouchpadI2C::from_device(dev, 1);
+    restore_brightness(&mut touchpad, Brightness::Zero);
+
+    // the numpad is still enabled, and lit by the next write
+    assert_eq!(Startup::new(false, None, true, false), Startup::Enable);
+    touchpad.set_brightness(Brightness::Full).unwrap();
+}
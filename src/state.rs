@@ -0,0 +1,82 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+
+#[cfg(test)]
+use crate::testing::TempDir;
+use crate::touchpad_i2c::Brightness;
+
+/// State that is kept across restarts of the daemon.
+#[derive(Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub(crate) struct State {
+    /// Last brightness level chosen by the user
+    pub(crate) brightness: Option<Brightness>,
+    /// Whether the numpad was on
+    pub(crate) enabled: Option<bool>,
+}
+
+impl State {
+    /// Get the path of the state file.
+    ///
+    /// Uses the `StateDirectory` set up by systemd if available, then
+    /// `$XDG_STATE_HOME`, and finally `/var/lib`.
+    pub(crate) fn path() -> PathBuf {
+        if let Some(dir) = std::env::var_os("STATE_DIRECTORY") {
+            // systemd already includes our name in the dir
+            return PathBuf::from(dir).join("state.toml");
+        }
+        let base = std::env::var_os("XDG_STATE_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map_or_else(|| PathBuf::from("/var/lib"), PathBuf::from);
+        base.join("asus-numpad").join("state.toml")
+    }
+
+    /// Read the state from `path`, falling back to defaults if it is missing or invalid.
+    pub(crate) fn load(path: &Path) -> Self {
+        let data = match std::fs::read_to_string(path) {
+            Ok(data) => data,
+            Err(err) => {
+                debug!("Can't read state from {}: {}", path.display(), err);
+                return Self::default();
+            }
+        };
+        toml::from_str(&data).unwrap_or_else(|err| {
+            warn!("Ignoring invalid state in {}: {}", path.display(), err);
+            Self::default()
+        })
+    }
+
+    /// Atomically write the state to `path`.
+    pub(crate) fn save(&self, path: &Path) -> Result<()> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("Could not create state dir {}", dir.display()))?;
+        }
+        let tmp_path = path.with_extension("toml.tmp");
+        std::fs::write(&tmp_path, toml::to_string(self)?)
+            .with_context(|| format!("Could not write state to {}", tmp_path.display()))?;
+        std::fs::rename(&tmp_path, path)
+            .with_context(|| format!("Could not move state to {}", path.display()))
+    }
+}
+
+#[test]
+fn test_state_roundtrip() {
+    let dir = TempDir::new("state");
+    let path = dir.join("state.toml");
+    assert_eq!(State::load(&path), State::default());
+
+    for brightness in [Brightness::Half, Brightness::Custom(20)] {
+        let state = State {
+            brightness: Some(brightness),
+            enabled: Some(true),
+        };
+        state.save(&path).unwrap();
+        assert_eq!(State::load(&path), state);
+    }
+
+    std::fs::write(&path, "brightness = \"blinding\"").unwrap();
+    assert_eq!(State::load(&path), State::default());
+}
@@ -0,0 +1,104 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use log::{debug, warn};
+use serde::Serialize;
+
+use crate::control::{Status, default_socket_path};
+#[cfg(test)]
+use crate::testing::TempDir;
+
+const FILE_NAME: &str = "asus-numpad-status.json";
+
+/// What the status file holds: the reply to `status`, and the layout in use.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub(crate) struct FileStatus {
+    #[serde(flatten)]
+    pub(crate) status: Status,
+    pub(crate) layout: String,
+}
+
+/// Get the default path of the status file.
+///
+/// Uses `$XDG_RUNTIME_DIR` if it is set, and otherwise the directory of the
+/// control socket.
+pub(crate) fn default_status_path() -> PathBuf {
+    match std::env::var_os("XDG_RUNTIME_DIR").filter(|dir| !dir.is_empty()) {
+        Some(dir) => PathBuf::from(dir).join(FILE_NAME),
+        None => default_socket_path().with_file_name(FILE_NAME),
+    }
+}
+
+/// Keeps a JSON file up to date with the state of the numpad, for status
+/// bars to read.
+#[derive(Debug)]
+pub(crate) struct StatusFile {
+    path: PathBuf,
+    /// What was last written, to only write it when something changes
+    written: Option<FileStatus>,
+}
+
+impl StatusFile {
+    pub(crate) fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            written: None,
+        }
+    }
+
+    /// Write `status` to the file if it changed.
+    ///
+    /// Failures are only logged, and not retried until the status changes again.
+    pub(crate) fn update(&mut self, status: FileStatus) {
+        if self.written.as_ref() == Some(&status) {
+            return;
+        }
+        match write_atomically(&self.path, &status) {
+            Ok(()) => debug!("Wrote status to {}", self.path.display()),
+            Err(err) => warn!("Failed to write the status file: {:#}", err),
+        }
+        self.written = Some(status);
+    }
+}
+
+/// Write to a temporary file next to `path` first, so that readers never see
+/// half of it.
+fn write_atomically(path: &Path, status: &FileStatus) -> Result<()> {
+    let tmp_path = path.with_extension("json.tmp");
+    std::fs::write(&tmp_path, serde_json::to_string(status)? + "\n")
+        .with_context(|| format!("Could not write {}", tmp_path.display()))?;
+    std::fs::rename(&tmp_path, path)
+        .with_context(|| format!("Could not move the status to {}", path.display()))
+}
+
+#[test]
+fn test_status_file() {
+    use crate::touchpad_i2c::Brightness;
+
+    let dir = TempDir::new("status");
+    let path = dir.join("status.json");
+    let mut status = FileStatus {
+        status: Status {
+            enabled: true,
+            brightness: Brightness::Half,
+            locked: false,
+        },
+        layout: "UX433FA".to_owned(),
+    };
+    let mut file = StatusFile::new(path.clone());
+    file.update(status.clone());
+    assert_eq!(
+        std::fs::read_to_string(&path).unwrap(),
+        "{\"enabled\":true,\"brightness\":\"Half\",\"locked\":false,\"layout\":\"UX433FA\"}\n"
+    );
+
+    // unchanged, so not written again
+    std::fs::remove_file(&path).unwrap();
+    file.update(status.clone());
+    assert!(!path.exists());
+
+    status.status.enabled = false;
+    file.update(status);
+    let written: Status = serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+    assert!(!written.enabled);
+}
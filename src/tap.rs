@@ -0,0 +1,223 @@
+use std::time::{Duration, Instant};
+
+use evdev_rs::enums::EV_KEY;
+
+use crate::Point;
+#[cfg(test)]
+use crate::numpad_layout::BBox;
+use crate::numpad_layout::NumpadLayout;
+
+/// Where a finger went between touching the numpad and lifting.
+#[derive(Debug, Default, Clone, Copy)]
+pub(crate) struct Tap {
+    /// Where the finger went down
+    start: Point,
+    /// Where the finger was at the end of the last frame
+    last: Point,
+    /// How far the finger has traveled, back and forth included
+    travel: i32,
+}
+
+impl Tap {
+    pub(crate) fn new(start: Point) -> Self {
+        Self {
+            start,
+            last: start,
+            travel: 0,
+        }
+    }
+
+    pub(crate) fn start(&self) -> Point {
+        self.start
+    }
+
+    /// The key that the touch presses, which is the one under the finger as
+    /// it went down, wherever it moves after that.
+    pub(crate) fn key(&self, layout: &NumpadLayout) -> Option<EV_KEY> {
+        layout.get_key(self.start)
+    }
+
+    /// Follow the finger to `pos`, once both of its coordinates are in.
+    pub(crate) fn move_to(&mut self, pos: Point) {
+        let step = self.last.dist_sq(pos);
+        self.travel += f64::from(step).sqrt() as i32;
+        self.last = pos;
+    }
+
+    /// Whether the finger at `pos` has moved further than `percent` of a key
+    /// from where it went down, so it no longer presses [`Tap::key`].
+    pub(crate) fn moved_off_key(&self, layout: &NumpadLayout, pos: Point, percent: u32) -> bool {
+        layout.moved_off_key(self.start, pos, percent)
+    }
+
+    /// Whether the finger traveled further than `percent` of a key, for the
+    /// touch to be a swipe rather than a key press.
+    ///
+    /// Unlike [`Tap::moved_off_key`], this counts the whole way the finger
+    /// went, so a swipe that comes back still cancels the key. Never true
+    /// without a `percent`.
+    pub(crate) fn swiped(&self, layout: &NumpadLayout, percent: Option<u32>) -> bool {
+        percent.is_some_and(|percent| self.travel > layout.key_fraction(percent))
+    }
+}
+
+/// What a finger coming down on the touchpad does.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum TouchDown {
+    /// Nothing, as it's likely the finger that toggled the numpad coming down
+    /// again as it lifts
+    Ignored,
+    /// It only ever toggles the numpad, even if a key is under it
+    Numlock,
+    /// It touches whatever is under it
+    Numpad,
+}
+
+/// Keeps the finger that toggled the numpad from touching it right after.
+#[derive(Debug, Default)]
+pub(crate) struct ToggleGuard {
+    /// When a gesture last toggled the numpad
+    toggled_at: Option<Instant>,
+}
+
+impl ToggleGuard {
+    /// A gesture toggled the numpad at `now`.
+    ///
+    /// Toggles from anywhere else have no finger on the touchpad to guard
+    /// against.
+    pub(crate) fn toggled(&mut self, now: Instant) {
+        self.toggled_at = Some(now);
+    }
+
+    /// What a finger coming down at `now` does, `grace` being how long
+    /// touches are ignored after a toggle.
+    ///
+    /// `on_numlock` is whether it is on the numlock bbox, and `can_toggle`
+    /// whether touches can toggle the numpad at all.
+    pub(crate) fn touch_down(
+        &self,
+        now: Instant,
+        grace: Duration,
+        on_numlock: bool,
+        can_toggle: bool,
+    ) -> TouchDown {
+        if self
+            .toggled_at
+            .is_some_and(|at| now.saturating_duration_since(at) < grace)
+        {
+            TouchDown::Ignored
+        } else if on_numlock && can_toggle {
+            TouchDown::Numlock
+        } else {
+            TouchDown::Numpad
+        }
+    }
+}
+
+#[cfg(test)]
+fn layout() -> NumpadLayout {
+    use EV_KEY::*;
+    let bbox = BBox::new(0, 1000, 0, 500);
+    // keys are 500x250, so 20% is 50 units
+    NumpadLayout::create(
+        &bbox,
+        vec![vec![KEY_KP7, KEY_KP8], vec![KEY_KP4, KEY_KP5]],
+        bbox.clone(),
+        bbox.disjoint_dummy(),
+        bbox.disjoint_dummy(),
+    )
+}
+
+/// Follow a touch through `path`, telling whether it moved off its key at
+/// each point.
+#[cfg(test)]
+fn replay(tap: &mut Tap, layout: &NumpadLayout, path: &[(i32, i32)], percent: u32) -> Vec<bool> {
+    path.iter()
+        .map(|&(x, y)| {
+            let pos = Point { x, y };
+            tap.move_to(pos);
+            tap.moved_off_key(layout, pos, percent)
+        })
+        .collect()
+}
+
+#[test]
+fn test_tap_drift_under_tolerance() {
+    use EV_KEY::*;
+    let layout = layout();
+    // down on 7, right next to 8
+    let mut tap = Tap::new(Point { x: 490, y: 100 });
+    assert_eq!(tap.key(&layout), Some(KEY_KP7));
+    let moved_off = replay(&mut tap, &layout, &[(500, 100), (520, 102), (539, 98)], 20);
+    assert_eq!(moved_off, [false; 3]);
+    // the finger is over 8 now, but the touch still presses 7
+    assert_eq!(layout.get_key(Point { x: 539, y: 98 }), Some(KEY_KP8));
+    assert_eq!(tap.key(&layout), Some(KEY_KP7));
+    assert!(!tap.swiped(&layout, None));
+}
+
+#[test]
+fn test_tap_drift_over_tolerance() {
+    use EV_KEY::*;
+    let layout = layout();
+    let mut tap = Tap::new(Point { x: 490, y: 100 });
+    let moved_off = replay(&mut tap, &layout, &[(510, 100), (530, 100), (545, 100)], 20);
+    assert_eq!(moved_off, [false, false, true]);
+    // and when the tolerance is off, any movement counts
+    let mut tap = Tap::new(Point { x: 490, y: 100 });
+    assert_eq!(
+        replay(&mut tap, &layout, &[(490, 100), (491, 100)], 0),
+        [false, true]
+    );
+    assert_eq!(tap.key(&layout), Some(KEY_KP7));
+}
+
+#[test]
+fn test_tap_swipe_cancel() {
+    let layout = layout();
+    // back and forth across the border, never far enough to move off the key
+    let mut tap = Tap::new(Point { x: 490, y: 100 });
+    let moved_off = replay(
+        &mut tap,
+        &layout,
+        &[(530, 100), (490, 100), (530, 100), (490, 100)],
+        20,
+    );
+    assert_eq!(moved_off, [false; 4]);
+    // 160 units traveled, more than the 150 of 60% of a key
+    assert!(!tap.swiped(&layout, Some(80)));
+    assert!(tap.swiped(&layout, Some(60)));
+    assert!(!tap.swiped(&layout, None));
+}
+
+#[test]
+fn test_toggle_guard() {
+    use TouchDown::*;
+    let grace = Duration::from_millis(300);
+    let mut guard = ToggleGuard::default();
+    let start = Instant::now();
+    assert_eq!(guard.touch_down(start, grace, false, true), Numpad);
+    assert_eq!(guard.touch_down(start, grace, true, true), Numlock);
+    // a locked numpad treats the numlock bbox like the rest
+    assert_eq!(guard.touch_down(start, grace, true, false), Numpad);
+
+    // held on the numlock bbox until it toggled, then lifted and touched again
+    let toggled = start + Duration::from_secs(1);
+    guard.toggled(toggled);
+    let retouch = toggled + Duration::from_millis(120);
+    assert_eq!(guard.touch_down(retouch, grace, true, true), Ignored);
+    assert_eq!(guard.touch_down(retouch, grace, false, true), Ignored);
+    assert_eq!(
+        guard.touch_down(toggled + grace, grace, false, true),
+        Numpad
+    );
+    assert_eq!(
+        guard.touch_down(toggled + grace, grace, true, true),
+        Numlock
+    );
+    // without a grace, the next touch counts right away
+    assert_eq!(
+        guard.touch_down(retouch, Duration::ZERO, false, true),
+        Numpad
+    );
+}
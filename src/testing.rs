@@ -0,0 +1,113 @@
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::Duration;
+
+use anyhow::{Result, anyhow};
+use i2cdev::core::I2CDevice;
+
+use crate::touchpad_i2c::Reopen;
+
+/// Records everything written to it instead of talking to hardware.
+#[derive(Debug, Default)]
+pub(crate) struct MockI2CDevice {
+    pub(crate) writes: Vec<Vec<u8>>,
+    /// Data to be returned by the upcoming reads
+    pub(crate) reads: Vec<Vec<u8>>,
+    /// How many times the device was reopened
+    pub(crate) reopened: u32,
+    /// Whether reopening the device should fail
+    pub(crate) reopen_fails: bool,
+    /// Errors to be returned by the upcoming writes
+    pub(crate) write_errors: Vec<std::io::ErrorKind>,
+}
+
+impl I2CDevice for MockI2CDevice {
+    type Error = std::io::Error;
+
+    fn read(&mut self, data: &mut [u8]) -> std::io::Result<()> {
+        if self.reads.is_empty() {
+            return Err(std::io::ErrorKind::TimedOut.into());
+        }
+        let read = self.reads.remove(0);
+        data.copy_from_slice(&read[..data.len()]);
+        Ok(())
+    }
+
+    fn write(&mut self, data: &[u8]) -> std::io::Result<()> {
+        if !self.write_errors.is_empty() {
+            return Err(self.write_errors.remove(0).into());
+        }
+        self.writes.push(data.to_vec());
+        Ok(())
+    }
+
+    fn smbus_write_quick(&mut self, _bit: bool) -> std::io::Result<()> {
+        Err(std::io::ErrorKind::Unsupported.into())
+    }
+
+    fn smbus_read_block_data(&mut self, _register: u8) -> std::io::Result<Vec<u8>> {
+        Err(std::io::ErrorKind::Unsupported.into())
+    }
+
+    fn smbus_read_i2c_block_data(&mut self, _register: u8, _len: u8) -> std::io::Result<Vec<u8>> {
+        Err(std::io::ErrorKind::Unsupported.into())
+    }
+
+    fn smbus_write_block_data(&mut self, _register: u8, _values: &[u8]) -> std::io::Result<()> {
+        Err(std::io::ErrorKind::Unsupported.into())
+    }
+
+    fn smbus_write_i2c_block_data(&mut self, _register: u8, _values: &[u8]) -> std::io::Result<()> {
+        Err(std::io::ErrorKind::Unsupported.into())
+    }
+
+    fn smbus_process_block(&mut self, _register: u8, _values: &[u8]) -> std::io::Result<Vec<u8>> {
+        Err(std::io::ErrorKind::Unsupported.into())
+    }
+}
+
+impl Reopen for MockI2CDevice {
+    fn reopen(&mut self, _i2c_id: u32, _timeout: Option<Duration>) -> Result<()> {
+        if self.reopen_fails {
+            return Err(anyhow!("No such device"));
+        }
+        self.reopened += 1;
+        Ok(())
+    }
+}
+
+/// A fresh directory for a test, removed along with everything in it once
+/// the test is done, even if it panics.
+#[derive(Debug)]
+pub(crate) struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    /// Create a directory whose name starts with `name`, unique to this call.
+    pub(crate) fn new(name: &str) -> Self {
+        static COUNT: AtomicU32 = AtomicU32::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "asus-numpad-{}-{}-{}",
+            name,
+            std::process::id(),
+            COUNT.fetch_add(1, Ordering::Relaxed)
+        ));
+        std::fs::create_dir_all(&path).unwrap();
+        Self { path }
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub(crate) fn join(&self, path: impl AsRef<Path>) -> PathBuf {
+        self.path.join(path)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.path);
+    }
+}
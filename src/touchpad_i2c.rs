@@ -1,17 +1,36 @@
 use std::fmt::Debug;
 use std::io::ErrorKind::{NotFound, PermissionDenied};
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
 
-use anyhow::{Context, Error, Result};
+use anyhow::{Context, Error, Result, anyhow};
 use i2cdev::core::I2CDevice;
 use i2cdev::linux::{LinuxI2CDevice, LinuxI2CError};
+use log::{debug, info, warn};
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
-#[derive(Debug, Default, Clone, Copy)]
+use crate::numpad_layout::SupportedLayout;
+#[cfg(test)]
+use crate::testing::MockI2CDevice;
+
+/// Backlight level of the numpad.
+///
+/// The firmware takes a raw byte where `0` turns the backlight off, and
+/// smaller non-zero values are brighter (`1` is the brightest).
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Brightness {
-    Zero = 0,
-    Low = 31,
-    Half = 24,
+    Zero,
+    Low,
+    Half,
     #[default]
-    Full = 1,
+    Full,
+    /// Any other raw value accepted by the firmware, up to [`Brightness::MAX_RAW`].
+    ///
+    /// Skipped over when cycling.
+    Custom(u8),
 }
 
 impl std::fmt::Display for Brightness {
@@ -22,83 +41,1902 @@ impl std::fmt::Display for Brightness {
             Low => "Low",
             Half => "Half",
             Full => "Full",
+            Custom(raw) => return write!(f, "Custom({})", raw),
         };
         f.write_str(level)
     }
 }
 
+impl TryFrom<u8> for Brightness {
+    type Error = Error;
+
+    /// Map a raw brightness byte (as sent to the touchpad) back to its level.
+    ///
+    /// Values that don't belong to a named level become [`Brightness::Custom`].
+    fn try_from(value: u8) -> Result<Self> {
+        use Brightness::*;
+        match value {
+            0 => Ok(Zero),
+            31 => Ok(Low),
+            24 => Ok(Half),
+            1 => Ok(Full),
+            2..=Self::MAX_RAW => Ok(Custom(value)),
+            _ => Err(anyhow!(
+                "Brightness value {} is out of range (0-{})",
+                value,
+                Self::MAX_RAW
+            )),
+        }
+    }
+}
+
+impl TryFrom<&str> for Brightness {
+    type Error = Error;
+
+    /// Parse the level name as printed by `Display` ignoring case, a raw value,
+    /// or a percentage like `"50%"`.
+    fn try_from(value: &str) -> Result<Self> {
+        if let Ok(raw) = value.parse::<u8>() {
+            return Self::try_from(raw);
+        }
+        if let Some(pct) = value.strip_suffix('%') {
+            return match pct.trim().parse::<u8>() {
+                Ok(pct @ 0..=100) => Ok(Self::from_percent(pct)),
+                _ => Err(anyhow!("Invalid brightness percentage {:?}", value)),
+            };
+        }
+        Self::all()
+            .find(|b| b.to_string().eq_ignore_ascii_case(value))
+            .ok_or_else(|| anyhow!("Unknown brightness level {:?}", value))
+    }
+}
+
+impl std::str::FromStr for Brightness {
+    type Err = Error;
+
+    /// Same as `TryFrom<&str>`, for `str::parse` and the command line.
+    fn from_str(s: &str) -> Result<Self> {
+        Self::try_from(s)
+    }
+}
+
+impl<'de> Deserialize<'de> for Brightness {
+    /// Accepts either a level name (`"half"`) or a raw value (`20`).
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Repr {
+            Raw(u8),
+            Name(String),
+        }
+        match Repr::deserialize(deserializer)? {
+            Repr::Raw(raw) => Self::try_from(raw),
+            Repr::Name(name) => Self::try_from(name.as_str()),
+        }
+        .map_err(D::Error::custom)
+    }
+}
+
+impl Serialize for Brightness {
+    /// The inverse of `Deserialize`: level name for named levels, else the raw value.
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Brightness::Custom(raw) => serializer.serialize_u8(*raw),
+            _ => serializer.collect_str(self),
+        }
+    }
+}
+
 impl Brightness {
+    /// The largest raw value known to be handled by the firmware.
+    ///
+    /// Higher values are rejected since their behavior is undefined.
+    pub const MAX_RAW: u8 = 31;
+
+    /// The byte that is sent to the touchpad for this level.
+    pub fn as_raw(&self) -> u8 {
+        use Brightness::*;
+        match self {
+            Zero => 0,
+            Low => 31,
+            Half => 24,
+            Full => 1,
+            Custom(raw) => *raw,
+        }
+    }
+
+    /// Snap a percentage to the nearest named level.
+    ///
+    /// `0` is `Zero`, `1..=33` is `Low`, `34..=66` is `Half` and anything above is `Full`.
+    pub fn from_percent(pct: u8) -> Self {
+        use Brightness::*;
+        match pct {
+            0 => Zero,
+            1..=33 => Low,
+            34..=66 => Half,
+            _ => Full,
+        }
+    }
+
+    /// Map a percentage onto every raw value from the dimmest (`0`) to the
+    /// brightest (`100`), without turning the backlight off.
+    pub fn from_percent_continuous(pct: u8) -> Self {
+        let pct = pct.min(100) as u32;
+        let raw = Self::MAX_RAW as u32 - pct * (Self::MAX_RAW as u32 - 1) / 100;
+        Self::try_from(raw as u8).expect("raw value is in range")
+    }
+
+    /// The approximate percentage of this level, such that
+    /// `Brightness::from_percent(b.to_percent())` is stable.
+    pub fn to_percent(self) -> u8 {
+        use Brightness::*;
+        match self {
+            Zero => 0,
+            Low => 33,
+            Half => 66,
+            Full => 100,
+            // linear between Full (1) and MAX_RAW, which is as dim as Low
+            Custom(raw) => {
+                let raw = raw.clamp(1, Self::MAX_RAW) as u32;
+                (100 - (raw - 1) * 67 / (Self::MAX_RAW as u32 - 1)) as u8
+            }
+        }
+    }
+
     fn next(&self) -> Self {
         use Brightness::*;
         match self {
-            Zero => Default::default(), // Jump to default
+            Zero | Custom(_) => Default::default(), // Jump to default
             Low => Half,
             Half => Full,
             Full => Low,
         }
     }
 
+    /// Go to the next level. This never turns the backlight off.
     pub fn cycle(&mut self) -> Self {
         *self = self.next();
         *self
     }
+
+    /// The reverse of [`Brightness::next`], except that it stops at `Low`
+    /// instead of wrapping around to the brightest level.
+    fn prev(&self) -> Self {
+        use Brightness::*;
+        match self {
+            Zero => Zero, // Stay off
+            Custom(_) => Default::default(),
+            Low => Low,
+            Half => Low,
+            Full => Half,
+        }
+    }
+
+    pub fn cycle_down(&mut self) -> Self {
+        *self = self.prev();
+        *self
+    }
+
+    /// Every named level, starting from `Zero` and then in cycle order.
+    pub fn all() -> BrightnessIter {
+        BrightnessIter { index: 0 }
+    }
+}
+
+/// Iterator over the named levels, created by [`Brightness::all`].
+#[derive(Debug, Clone)]
+pub struct BrightnessIter {
+    index: usize,
+}
+
+impl BrightnessIter {
+    const LEVELS: [Brightness; 4] = [
+        Brightness::Zero,
+        Brightness::Low,
+        Brightness::Half,
+        Brightness::Full,
+    ];
+}
+
+impl Iterator for BrightnessIter {
+    type Item = Brightness;
+
+    fn next(&mut self) -> Option<Brightness> {
+        let level = Self::LEVELS.get(self.index)?;
+        self.index += 1;
+        Some(*level)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = Self::LEVELS.len() - self.index;
+        (remaining, Some(remaining))
+    }
+}
+
+impl ExactSizeIterator for BrightnessIter {}
+
+/// How bright a raw value is, with `0` being off and larger values being brighter.
+fn intensity(raw: u8) -> u8 {
+    if raw == 0 {
+        0
+    } else {
+        Brightness::MAX_RAW + 1 - raw
+    }
+}
+
+/// The levels to go through to smoothly get from `from` to `to`, ending with `to`.
+///
+/// Returns at most `max_steps` levels, or nothing if they are the same.
+fn fade_steps(from: Brightness, to: Brightness, max_steps: u32) -> Vec<Brightness> {
+    let start = intensity(from.as_raw()) as i32;
+    let end = intensity(to.as_raw()) as i32;
+    let n = (end - start).unsigned_abs().min(max_steps) as i32;
+    let mut steps: Vec<_> = (1..n)
+        .map(|i| {
+            let step = (start + (end - start) * i / n) as u8;
+            // inverse of `intensity`
+            let raw = if step == 0 {
+                0
+            } else {
+                Brightness::MAX_RAW + 1 - step
+            };
+            Brightness::try_from(raw).unwrap()
+        })
+        .collect();
+    if n > 0 {
+        steps.push(to);
+    }
+    steps
+}
+
+/// An ongoing transition between two brightness levels.
+#[derive(Debug)]
+struct Fade {
+    /// The remaining levels to be set
+    steps: std::vec::IntoIter<Brightness>,
+    target: Brightness,
+    next_step_at: Instant,
+}
+
+/// Tracks the brightness level, remembering it while the backlight is off.
+#[derive(Debug, Clone, Copy)]
+pub struct BrightnessCycler {
+    /// Level to be used whenever the backlight is on
+    level: Brightness,
+    off: bool,
+}
+
+impl BrightnessCycler {
+    /// Create a cycler at `level`, with the backlight initially off.
+    pub fn new(level: Brightness) -> Self {
+        Self { level, off: true }
+    }
+
+    /// The brightness that the backlight should currently have.
+    pub fn get(&self) -> Brightness {
+        if self.off {
+            Brightness::Zero
+        } else {
+            self.level
+        }
+    }
+
+    /// The level that is used while the backlight is on.
+    pub fn level(&self) -> Brightness {
+        self.level
+    }
+
+    /// Turn the backlight off, or back on to the saved level if already off.
+    pub fn toggle_off(&mut self) -> Brightness {
+        self.set_off(!self.off)
+    }
+
+    pub fn set_off(&mut self, off: bool) -> Brightness {
+        self.off = off;
+        self.get()
+    }
+
+    /// Change the level, staying off if the backlight is off.
+    pub fn set_level(&mut self, level: Brightness) -> Brightness {
+        self.level = level;
+        self.get()
+    }
+
+    pub fn cycle(&mut self) -> Brightness {
+        self.level.cycle();
+        self.get()
+    }
+
+    pub fn cycle_down(&mut self) -> Brightness {
+        self.level.cycle_down();
+        self.get()
+    }
+}
+
+impl Default for BrightnessCycler {
+    fn default() -> Self {
+        Self::new(Default::default())
+    }
+}
+
+const SYSFS_I2C_DEVICES: &str = "/sys/bus/i2c/devices";
+
+/// Prefixes of the ACPI ids that the supported touchpads show up with,
+/// most likely first.
+const KNOWN_TOUCHPAD_IDS: [&str; 4] = ["ASUE", "ASUP", "ASCP", "ELAN"];
+
+/// Position of the matching prefix in [`KNOWN_TOUCHPAD_IDS`].
+pub(crate) fn touchpad_rank(name: &str) -> Option<usize> {
+    // modalias looks like "acpi:ELAN1200:PNP0C50:"
+    let name = name.trim().trim_start_matches("acpi:");
+    KNOWN_TOUCHPAD_IDS
+        .iter()
+        .position(|id| name.starts_with(id))
+}
+
+fn is_known_touchpad(name: &str) -> bool {
+    touchpad_rank(name).is_some()
+}
+
+/// Choose the candidate whose name matches the most likely touchpad id.
+///
+/// Keeps the earliest one on a tie, and logs the ones that aren't chosen.
+fn pick_touchpad(candidates: &[(String, u32)]) -> Option<&(String, u32)> {
+    let chosen = candidates
+        .iter()
+        .min_by_key(|(name, _)| touchpad_rank(name).unwrap_or(KNOWN_TOUCHPAD_IDS.len()))?;
+    for candidate @ (name, i2c_id) in candidates {
+        if !std::ptr::eq(chosen, candidate) {
+            info!(
+                "Ignoring touchpad candidate {} on /dev/i2c-{}",
+                name, i2c_id
+            );
+        }
+    }
+    Some(chosen)
+}
+
+/// Get the adapter number from the resolved sysfs path of an I2C client,
+/// e.g. `.../i2c_designware.0/i2c-1/i2c-ELAN1200:00` gives `1`.
+fn adapter_id_from_path(path: &Path) -> Option<u32> {
+    path.parent()?
+        .file_name()?
+        .to_str()?
+        .strip_prefix("i2c-")?
+        .parse()
+        .ok()
+}
+
+/// List the (name, adapter id) of all I2C clients that look like a touchpad.
+fn find_touchpad_adapters(devices_dir: &Path) -> Result<Vec<(String, u32)>> {
+    let entries = std::fs::read_dir(devices_dir)
+        .with_context(|| format!("Could not read {}", devices_dir.display()))?;
+    let mut candidates = Vec::new();
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let read = |file| std::fs::read_to_string(path.join(file)).unwrap_or_default();
+        let name = read("name");
+        if !is_known_touchpad(&name) && !is_known_touchpad(&read("modalias")) {
+            continue;
+        }
+        let Ok(real_path) = std::fs::canonicalize(&path) else {
+            continue;
+        };
+        match adapter_id_from_path(&real_path) {
+            Some(i2c_id) => candidates.push((name.trim().to_owned(), i2c_id)),
+            None => debug!("Can't get adapter of {}", real_path.display()),
+        }
+    }
+    Ok(candidates)
+}
+
+/// Add a hint about the likely cause of failing to open the I2C device.
+fn open_error(err: LinuxI2CError, i2c_id: u32) -> Error {
+    let mut context = format!("Unable to open Touchpad I2C at /dev/i2c-{}", i2c_id);
+    let extra_context = match &err {
+        LinuxI2CError::Io(e) => match e.kind() {
+            NotFound => "Is i2c-dev kernel module loaded?",
+            PermissionDenied => "Do you have the permission to read /dev/i2c-*?",
+            _ => "",
+        },
+        LinuxI2CError::Errno(_) => "",
+    };
+    if !extra_context.is_empty() {
+        context.push_str(". ");
+        context.push_str(extra_context);
+    };
+    Error::new(err).context(context)
+}
+
+/// Tells apart the errors that an I2C operation can recover from.
+pub trait DeviceError {
+    /// Retrying the operation might succeed.
+    fn is_transient(&self) -> bool;
+    /// The device went away, and needs to be opened again.
+    fn is_disconnected(&self) -> bool;
+}
+
+/// The bus or firmware being busy, as opposed to e.g. `ENODEV` or `EACCES`.
+fn is_transient_errno(errno: i32) -> bool {
+    matches!(
+        errno,
+        libc::EAGAIN | libc::EBUSY | libc::EINTR | libc::EIO | libc::EREMOTEIO | libc::ETIMEDOUT
+    )
+}
+
+fn is_disconnected_errno(errno: i32) -> bool {
+    matches!(errno, libc::ENODEV | libc::ENXIO)
+}
+
+impl DeviceError for std::io::Error {
+    fn is_transient(&self) -> bool {
+        use std::io::ErrorKind::*;
+        match self.raw_os_error() {
+            Some(errno) => is_transient_errno(errno),
+            None => matches!(self.kind(), WouldBlock | Interrupted | TimedOut),
+        }
+    }
+
+    fn is_disconnected(&self) -> bool {
+        match self.raw_os_error() {
+            Some(errno) => is_disconnected_errno(errno),
+            None => self.kind() == std::io::ErrorKind::NotConnected,
+        }
+    }
+}
+
+impl DeviceError for LinuxI2CError {
+    fn is_transient(&self) -> bool {
+        match self {
+            LinuxI2CError::Errno(errno) => is_transient_errno(*errno),
+            LinuxI2CError::Io(err) => err.is_transient(),
+        }
+    }
+
+    fn is_disconnected(&self) -> bool {
+        match self {
+            LinuxI2CError::Errno(errno) => is_disconnected_errno(*errno),
+            LinuxI2CError::Io(err) => err.is_disconnected(),
+        }
+    }
+}
+
+/// Whether `err` means that the touchpad is gone, rather than just failing.
+///
+/// Besides the device disappearing, `EREMOTEIO` that persists after the
+/// retries means that nothing answers at the touchpad address anymore.
+pub fn is_device_lost(err: &Error) -> bool {
+    err.chain().any(|cause| {
+        if let Some(err) = cause.downcast_ref::<std::io::Error>() {
+            err.is_disconnected() || err.raw_os_error() == Some(libc::EREMOTEIO)
+        } else if let Some(err) = cause.downcast_ref::<LinuxI2CError>() {
+            err.is_disconnected() || matches!(err, LinuxI2CError::Errno(libc::EREMOTEIO))
+        } else {
+            false
+        }
+    })
+}
+
+/// Devices that can be opened again after they disappear, e.g. across suspend.
+pub trait Reopen {
+    /// Open `i2c_id` again, with the timeout of [`TouchpadI2C::with_timeout`] if any.
+    fn reopen(&mut self, i2c_id: u32, timeout: Option<Duration>) -> Result<()>;
+}
+
+impl Reopen for LinuxI2CDevice {
+    fn reopen(&mut self, i2c_id: u32, timeout: Option<Duration>) -> Result<()> {
+        *self = match timeout {
+            Some(timeout) => TouchpadI2C::with_timeout(i2c_id, timeout)?.dev,
+            None => TouchpadI2C::new(i2c_id)?.dev,
+        };
+        Ok(())
+    }
+}
+
+/// The `ioctl` that sets how long an adapter waits for a transfer.
+const I2C_TIMEOUT: u32 = 0x0702;
+
+/// `timeout` in the units of [`I2C_TIMEOUT`], which are 10 ms.
+///
+/// Rounded up, since 0 would mean the kernel default.
+fn timeout_units(timeout: Duration) -> u64 {
+    (timeout.as_millis().div_ceil(10) as u64).max(1)
+}
+
+/// Register of the I2C-HID descriptor on the supported touchpads.
+///
+/// In general this is given by ACPI, but ELAN and ASUE touchpads use `0x0001`.
+const HID_DESC_REGISTER: u16 = 0x0001;
+const HID_DESC_LENGTH: usize = 30;
+/// The command and data registers that the brightness message is written to
+const HID_COMMAND_REGISTER: u16 = 0x0005;
+const HID_DATA_REGISTER: u16 = 0x0006;
+/// The feature report that holds the brightness, as sent by
+/// [`TouchpadI2C::set_brightness_with_retry`]: its length, id and the bytes
+/// that come before the brightness.
+const BRIGHTNESS_REPORT_HEADER: [u8; 5] = [0x07, 0x00, 0x0d, 0x14, 0x03];
+/// The byte after the brightness, which ends the feature report.
+///
+/// Neither the default report nor the HID descriptor carry a checksum, so
+/// this and the header are all that a read can be checked against.
+const BRIGHTNESS_REPORT_TRAILER: u8 = 0xad;
+const BRIGHTNESS_REPORT_LENGTH: usize = 7;
+
+/// The message that sets the brightness to the raw byte `raw`.
+///
+/// It is a SET_REPORT (`3d 03`) through [`HID_COMMAND_REGISTER`], then
+/// [`HID_DATA_REGISTER`] and the feature report: [`BRIGHTNESS_REPORT_HEADER`],
+/// `raw` and [`BRIGHTNESS_REPORT_TRAILER`].
+pub(crate) const fn build_brightness_msg(raw: u8) -> [u8; 13] {
+    let [cmd_lo, cmd_hi] = HID_COMMAND_REGISTER.to_le_bytes();
+    let [data_lo, data_hi] = HID_DATA_REGISTER.to_le_bytes();
+    let [len_lo, len_hi, report_id, prefix_lo, prefix_hi] = BRIGHTNESS_REPORT_HEADER;
+    [
+        cmd_lo,
+        cmd_hi,
+        0x3d,
+        0x03,
+        data_lo,
+        data_hi,
+        len_lo,
+        len_hi,
+        report_id,
+        prefix_lo,
+        prefix_hi,
+        raw,
+        BRIGHTNESS_REPORT_TRAILER,
+    ]
+}
+
+/// The raw byte that `msg` sets the brightness to, if it is a message from
+/// [`build_brightness_msg`].
+pub(crate) fn parse_brightness_msg(msg: &[u8]) -> Option<u8> {
+    let [head @ .., _, tail] = build_brightness_msg(0);
+    match msg {
+        [msg_head @ .., raw, msg_tail] if msg_head == head && *msg_tail == tail => Some(*raw),
+        _ => None,
+    }
+}
+
+/// The layout of the message that sets the brightness, for models that take
+/// other bytes than [`build_brightness_msg`].
+///
+/// The message is 13 bytes: the brightness at `brightness_idx`, a last byte
+/// at `checksum_idx` and the preamble in between, in order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+pub(crate) struct BrightnessProtocol {
+    preamble: [u8; 11],
+    brightness_idx: usize,
+    /// Where the byte that makes the message add up to zero goes, instead of
+    /// the `ad` at the end
+    checksum_idx: Option<usize>,
+}
+
+impl Default for BrightnessProtocol {
+    fn default() -> Self {
+        let [preamble @ .., _, _] = build_brightness_msg(0);
+        Self {
+            preamble,
+            brightness_idx: 11,
+            checksum_idx: None,
+        }
+    }
 }
 
-pub struct TouchpadI2C {
-    dev: LinuxI2CDevice,
+impl BrightnessProtocol {
+    const MSG_LEN: usize = 13;
+    /// Where the feature report starts in the message, after the SET_REPORT
+    /// command and the data register
+    const REPORT_START: usize = Self::MSG_LEN - BRIGHTNESS_REPORT_LENGTH;
+
+    pub(crate) fn new(
+        preamble: [u8; 11],
+        brightness_idx: usize,
+        checksum_idx: Option<usize>,
+    ) -> Result<Self> {
+        let last_idx = checksum_idx.unwrap_or(Self::MSG_LEN - 1);
+        if brightness_idx >= Self::MSG_LEN || last_idx >= Self::MSG_LEN {
+            return Err(anyhow!(
+                "The brightness and checksum must be in the message (0-{})",
+                Self::MSG_LEN - 1
+            ));
+        }
+        if brightness_idx == last_idx {
+            return Err(anyhow!(
+                "The brightness can't be at {}, where the {} is",
+                last_idx,
+                if checksum_idx.is_some() {
+                    "checksum"
+                } else {
+                    "last byte"
+                }
+            ));
+        }
+        Ok(Self {
+            preamble,
+            brightness_idx,
+            checksum_idx,
+        })
+    }
+
+    /// The message that sets the brightness to the raw byte `raw`.
+    fn build(&self, raw: u8) -> [u8; 13] {
+        let last_idx = self.checksum_idx.unwrap_or(Self::MSG_LEN - 1);
+        let mut preamble = self.preamble.into_iter();
+        let mut msg = [0; 13];
+        for (idx, byte) in msg.iter_mut().enumerate() {
+            if idx == self.brightness_idx {
+                *byte = raw;
+            } else if idx != last_idx {
+                *byte = preamble.next().unwrap_or_default();
+            }
+        }
+        msg[last_idx] = match self.checksum_idx {
+            Some(_) => 0u8.wrapping_sub(msg.iter().fold(0, |sum, byte| sum.wrapping_add(*byte))),
+            None => BRIGHTNESS_REPORT_TRAILER,
+        };
+        msg
+    }
+
+    /// The brightness in a feature report read back from the touchpad, if it
+    /// is the report part of the message that this protocol writes.
+    fn parse_report(&self, report: &[u8; BRIGHTNESS_REPORT_LENGTH]) -> Result<Brightness> {
+        let idx = self
+            .brightness_idx
+            .checked_sub(Self::REPORT_START)
+            .ok_or_else(|| {
+                anyhow!("The brightness_protocol has no brightness in the report to read back")
+            })?;
+        let raw = report[idx];
+        let msg = self.build(raw);
+        let expected = &msg[Self::REPORT_START..];
+        if report[..idx] != expected[..idx] {
+            return Err(anyhow!("Unrecognized brightness report {:02x?}", report));
+        }
+        if report[idx + 1..] != expected[idx + 1..] {
+            return Err(anyhow!(
+                "Corrupted brightness report {:02x?}, expected it to end in {:02x?}",
+                report,
+                &expected[idx + 1..]
+            ));
+        }
+        Brightness::try_from(raw).context("Invalid brightness report")
+    }
+}
+
+impl<'de> Deserialize<'de> for BrightnessProtocol {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(deny_unknown_fields)]
+        struct Fields {
+            preamble: [u8; 11],
+            brightness_idx: usize,
+            checksum_idx: Option<usize>,
+        }
+        let fields = Fields::deserialize(deserializer)?;
+        Self::new(fields.preamble, fields.brightness_idx, fields.checksum_idx)
+            .map_err(D::Error::custom)
+    }
+}
+
+/// Default number of times a write is tried by [`TouchpadI2C::set_brightness`].
+pub const DEFAULT_RETRY_ATTEMPTS: u32 = 3;
+/// Default delay before the first retry.
+pub const DEFAULT_RETRY_DELAY: Duration = Duration::from_millis(10);
+
+/// The touchpad and the laptop that it is in, as told by
+/// [`TouchpadI2C::identify_device`], for bug reports.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub(crate) struct DeviceInfo {
+    /// The DMI product and board names, if they could be read
+    pub(crate) models: Vec<String>,
+    /// The layout that the models are detected as, if any
+    pub(crate) layout: Option<SupportedLayout>,
+    pub(crate) i2c_id: u32,
+    /// As returned by [`TouchpadI2C::read_firmware_version`]
+    pub(crate) firmware_version: [u8; 4],
+}
+
+impl std::fmt::Display for DeviceInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.models.is_empty() {
+            writeln!(f, "Model: unknown")?;
+        } else {
+            writeln!(f, "Model: {}", self.models.join(" / "))?;
+        }
+        match &self.layout {
+            Some(layout) => writeln!(f, "Layout: {}", layout.name())?,
+            None => writeln!(f, "Layout: not detected")?,
+        }
+        writeln!(f, "Touchpad I2C: /dev/i2c-{}", self.i2c_id)?;
+        writeln!(f, "Firmware version: {:02x?}", self.firmware_version)
+    }
+}
+
+/// Controls the numpad backlight over I2C.
+///
+/// Generic over the device so that it can be tested without the hardware.
+pub struct TouchpadI2C<D: I2CDevice = LinuxI2CDevice> {
+    dev: D,
     i2c_id: u32,
+    /// The last brightness that was successfully set
+    current: Option<Brightness>,
+    fade: Option<Fade>,
+    retry_attempts: u32,
+    retry_delay: Duration,
+    /// Set on the bus instead of the kernel default, also when it is reopened
+    timeout: Option<Duration>,
+    protocol: BrightnessProtocol,
+    enabled: bool,
+    /// Brightness to restore once the numpad is enabled again
+    requested: Option<Brightness>,
+}
+
+/// Get the bus id from a device node name like `i2c-3`.
+fn parse_i2c_dev_name(name: &str) -> Option<u32> {
+    name.strip_prefix("i2c-")?.parse().ok()
 }
 
+/// List the ids of all `/dev/i2c-*` nodes, in ascending order.
+fn list_i2c_buses() -> Result<Vec<u32>> {
+    let mut ids: Vec<u32> = std::fs::read_dir("/dev")
+        .context("Could not read /dev")?
+        .flatten()
+        .filter_map(|entry| parse_i2c_dev_name(entry.file_name().to_str()?))
+        .collect();
+    ids.sort_unstable();
+    Ok(ids)
+}
+
+/// The bus found by [`TouchpadI2C::discover`]
+static DISCOVERED_I2C_ID: OnceLock<u32> = OnceLock::new();
+
 impl TouchpadI2C {
+    const TOUCHPAD_ADDR: u16 = 0x15;
+
     pub fn new(i2c_id: u32) -> Result<Self> {
-        const TOUCHPAD_ADDR: u16 = 0x15;
         let dev = unsafe {
-            LinuxI2CDevice::force_new(format!("/dev/i2c-{}", i2c_id), TOUCHPAD_ADDR).map_err(
-                |err| {
-                    let mut context = format!("Unable to open Touchpad I2C at /dev/i2c-{}", i2c_id);
-                    let extra_context = match &err {
-                        LinuxI2CError::Io(e) => match e.kind() {
-                            NotFound => "Is i2c-dev kernel module loaded?",
-                            PermissionDenied => "Do you have the permission to read /dev/i2c-*?",
-                            _ => "",
-                        },
-                        LinuxI2CError::Errno(_) => "",
-                    };
-                    if !extra_context.is_empty() {
-                        context.push_str(". ");
-                        context.push_str(extra_context);
-                    };
-                    Error::new(err).context(context)
-                },
-            )?
+            LinuxI2CDevice::force_new(format!("/dev/i2c-{}", i2c_id), Self::TOUCHPAD_ADDR)
+                .map_err(|err| open_error(err, i2c_id))?
         };
-        Ok(Self { dev, i2c_id })
+        Ok(Self::from_device(dev, i2c_id))
     }
 
-    pub fn set_brightness(&mut self, brightness: Brightness) -> Result<()> {
-        let msg = [
-            0x05,
-            0x00,
-            0x3d,
-            0x03,
-            0x06,
-            0x00,
-            0x07,
-            0x00,
-            0x0d,
-            0x14,
-            0x03,
-            brightness as u8,
-            0xad,
-        ];
+    /// Like [`TouchpadI2C::new`], but transfers on the bus give up after
+    /// `timeout`, so that a hung touchpad fails and can be retried instead
+    /// of blocking.
+    pub fn with_timeout(i2c_id: u32, timeout: Duration) -> Result<Self> {
+        let mut touchpad = Self::new(i2c_id)?;
+        let units = timeout_units(timeout);
+        if unsafe { libc::ioctl(touchpad.dev.as_raw_fd(), I2C_TIMEOUT as _, units) } < 0 {
+            return Err(std::io::Error::last_os_error()).with_context(|| {
+                format!(
+                    "Could not set a timeout of {:?} on /dev/i2c-{}",
+                    timeout, i2c_id
+                )
+            });
+        }
+        debug!("Set a timeout of {}0 ms on /dev/i2c-{}", units, i2c_id);
+        touchpad.timeout = Some(timeout);
+        Ok(touchpad)
+    }
+
+    /// Open the first I2C bus where a device responds at the touchpad address.
+    ///
+    /// This is a last resort when the touchpad can't be found by its name.
+    /// The result is remembered for later calls.
+    pub fn discover() -> Result<Self> {
+        if let Some(i2c_id) = DISCOVERED_I2C_ID.get() {
+            return Self::new(*i2c_id);
+        }
+        let buses = list_i2c_buses()?;
+        for &i2c_id in &buses {
+            if let Some(touchpad) = Self::probe(i2c_id) {
+                info!("Found a device at /dev/i2c-{}", i2c_id);
+                DISCOVERED_I2C_ID.get_or_init(|| i2c_id);
+                return Ok(touchpad);
+            }
+        }
+        Err(anyhow!(
+            "No device responded at address {:#x} on any of the I2C buses {:?}. \
+            Is i2c-dev kernel module loaded?",
+            Self::TOUCHPAD_ADDR,
+            buses
+        ))
+    }
+
+    /// Every I2C bus where a device responds at the touchpad address.
+    ///
+    /// Unlike [`TouchpadI2C::discover`], this doesn't stop at the first one,
+    /// since other controllers (e.g. in docks) can respond too.
+    pub fn probe_all_buses() -> Result<Vec<u32>> {
+        Ok(list_i2c_buses()?
+            .into_iter()
+            .filter(|&i2c_id| Self::probe(i2c_id).is_some())
+            .collect())
+    }
+
+    /// Open `i2c_id` if a device responds on it at the touchpad address.
+    fn probe(i2c_id: u32) -> Option<Self> {
+        let mut touchpad = match Self::new(i2c_id) {
+            Ok(touchpad) => touchpad,
+            Err(err) => {
+                debug!("Skipping bus: {:#}", err);
+                return None;
+            }
+        };
+        match touchpad.dev.read(&mut [0]) {
+            Ok(()) => Some(touchpad),
+            Err(err) => {
+                debug!("No response on /dev/i2c-{}: {}", i2c_id, err);
+                None
+            }
+        }
+    }
+
+    /// Find the adapter of the touchpad in `/sys/bus/i2c/devices`.
+    ///
+    /// If there are several candidates, the one with the most likely name is
+    /// chosen. Fails if there are none, in which case the I2C id needs to be
+    /// passed explicitly to [`TouchpadI2C::new`].
+    pub fn detect_i2c_id() -> Result<u32> {
+        let candidates = find_touchpad_adapters(Path::new(SYSFS_I2C_DEVICES))?;
+        match pick_touchpad(&candidates) {
+            Some((name, i2c_id)) => {
+                info!("Detected touchpad {} on /dev/i2c-{}", name, i2c_id);
+                Ok(*i2c_id)
+            }
+            None => Err(anyhow!(
+                "Could not find the touchpad under {} on any of the I2C buses {:?}. \
+                Are the i2c_hid_acpi (or i2c_hid) and i2c-dev kernel modules loaded?",
+                SYSFS_I2C_DEVICES,
+                list_i2c_buses().unwrap_or_default()
+            )),
+        }
+    }
+}
+
+impl<D> TouchpadI2C<D>
+where
+    D: I2CDevice + Reopen,
+    D::Error: DeviceError + Send + Sync + 'static,
+{
+    /// Wrap an already opened device. `i2c_id` is only used for messages.
+    pub fn from_device(dev: D, i2c_id: u32) -> Self {
+        Self {
+            dev,
+            i2c_id,
+            current: None,
+            fade: None,
+            retry_attempts: DEFAULT_RETRY_ATTEMPTS,
+            retry_delay: DEFAULT_RETRY_DELAY,
+            timeout: None,
+            protocol: BrightnessProtocol::default(),
+            enabled: true,
+            requested: None,
+        }
+    }
+
+    pub fn i2c_id(&self) -> u32 {
+        self.i2c_id
+    }
+
+    /// Keep the settings and state, with the device replaced by `f(dev)`.
+    pub fn map_device<E: I2CDevice>(self, f: impl FnOnce(D) -> E) -> TouchpadI2C<E> {
+        TouchpadI2C {
+            dev: f(self.dev),
+            i2c_id: self.i2c_id,
+            current: self.current,
+            fade: self.fade,
+            retry_attempts: self.retry_attempts,
+            retry_delay: self.retry_delay,
+            timeout: self.timeout,
+            protocol: self.protocol,
+            enabled: self.enabled,
+            requested: self.requested,
+        }
+    }
+
+    /// Set the brightness with the message of `protocol` from now on.
+    pub fn set_protocol(&mut self, protocol: BrightnessProtocol) {
+        self.protocol = protocol;
+    }
+
+    /// Configure how often [`TouchpadI2C::set_brightness`] tries to write,
+    /// and how long it waits before the first retry.
+    pub fn set_retry(&mut self, attempts: u32, delay: Duration) {
+        self.retry_attempts = attempts;
+        self.retry_delay = delay;
+    }
+
+    /// Time between two steps of a fade.
+    const FADE_INTERVAL: Duration = Duration::from_millis(15);
+
+    /// Time between two attempts of [`TouchpadI2C::restore`].
+    const RESTORE_INTERVAL: Duration = Duration::from_millis(100);
+
+    /// Read the firmware version, and the models of the laptop along with
+    /// the layout that they are detected as.
+    ///
+    /// The models are read with `read_models`, usually from DMI. Only fails
+    /// if the firmware can't be read, since the models don't come from the
+    /// touchpad.
+    pub fn identify_device(
+        &mut self,
+        read_models: impl FnOnce() -> Result<Vec<String>>,
+    ) -> Result<DeviceInfo> {
+        let firmware_version = self.read_firmware_version()?;
+        let models = read_models()
+            .map_err(|err| debug!("{:#}", err))
+            .unwrap_or_default();
+        Ok(DeviceInfo {
+            layout: SupportedLayout::detect(&models).ok(),
+            models,
+            i2c_id: self.i2c_id,
+            firmware_version,
+        })
+    }
+
+    /// Read the firmware version from the I2C-HID descriptor.
+    ///
+    /// Returns the little-endian `wProductID` followed by `wVersionID`.
+    /// Warns if the descriptor doesn't match the layout that the brightness
+    /// message assumes, since it is then unlikely to work.
+    pub fn read_firmware_version(&mut self) -> Result<[u8; 4]> {
+        let mut desc = [0; HID_DESC_LENGTH];
+        self.dev
+            .write(&HID_DESC_REGISTER.to_le_bytes())
+            .context("Could not request the HID descriptor")?;
+        self.dev
+            .read(&mut desc)
+            .context("Could not read the HID descriptor")?;
+        debug!(
+            "Read HID descriptor from /dev/i2c-{}: {:02x?}",
+            self.i2c_id, desc
+        );
+        let word = |idx: usize| u16::from_le_bytes([desc[idx], desc[idx + 1]]);
+        let (length, bcd_version) = (word(0), word(2));
+        let (command_reg, data_reg) = (word(16), word(18));
+        if length as usize != HID_DESC_LENGTH
+            || bcd_version != 0x0100
+            || command_reg != HID_COMMAND_REGISTER
+            || data_reg != HID_DATA_REGISTER
+        {
+            warn!(
+                "Unrecognized touchpad firmware (descriptor length {}, HID version {:#06x}, \
+                command register {:#06x}, data register {:#06x}). \
+                Setting brightness might not work",
+                length, bcd_version, command_reg, data_reg
+            );
+        }
+        Ok([desc[22], desc[23], desc[24], desc[25]])
+    }
+
+    /// Get the brightness that the touchpad is at.
+    ///
+    /// With `use_cache`, this is the last level that was set, if any, since
+    /// it's what the touchpad has unless it was reset. Otherwise the brightness
+    /// report is read back from the firmware, which fails if it doesn't
+    /// support that. What is read isn't cached, so that the last level that
+    /// was set can still be restored.
+    pub fn get_brightness(&mut self, use_cache: bool) -> Result<Brightness> {
+        if let Some(current) = self.current.filter(|_| use_cache) {
+            return Ok(current);
+        }
+        let [cmd_lo, cmd_hi] = HID_COMMAND_REGISTER.to_le_bytes();
+        let [data_lo, data_hi] = HID_DATA_REGISTER.to_le_bytes();
+        // GET_REPORT for the feature report 0x0d
         self.dev
-            .write(&msg)
-            .with_context(|| format!("Could not set touchpad brightness to {}", brightness))
+            .write(&[cmd_lo, cmd_hi, 0x3d, 0x02, data_lo, data_hi])
+            .context("Could not request the brightness report")?;
+        let mut report = [0; BRIGHTNESS_REPORT_LENGTH];
+        self.dev
+            .read(&mut report)
+            .context("Could not read the brightness report")?;
+        debug!(
+            "Read brightness report from /dev/i2c-{}: {:02x?}",
+            self.i2c_id, report
+        );
+        self.protocol.parse_report(&report)
+    }
+
+    /// Turn the numpad backlight on or off as a whole.
+    ///
+    /// The firmware has no separate switch for the numpad, so disabling sets
+    /// the brightness to zero. While disabled, brightness changes are only
+    /// remembered, and the last one is applied when enabled again.
+    pub fn enable_numpad(&mut self, enabled: bool) -> Result<()> {
+        if enabled == self.enabled {
+            return Ok(());
+        }
+        debug!(
+            "{} the numpad on /dev/i2c-{}",
+            if enabled { "Enabling" } else { "Disabling" },
+            self.i2c_id
+        );
+        if enabled {
+            self.enabled = true;
+            match self.requested.take() {
+                Some(brightness) => self.set_brightness(brightness),
+                None => Ok(()),
+            }
+        } else {
+            let restore = self.fade.as_ref().map(|fade| fade.target).or(self.current);
+            self.set_brightness(Brightness::Zero)?;
+            self.requested = restore;
+            self.enabled = false;
+            Ok(())
+        }
+    }
+
+    /// Turn the backlight off right away, even if the numpad is disabled.
+    ///
+    /// Meant for shutting down, so nothing is remembered for later.
+    pub fn turn_off(&mut self) -> Result<()> {
+        self.fade = None;
+        self.set_brightness_with_retry(Brightness::Zero, self.retry_attempts, self.retry_delay)
+    }
+
+    /// Write the current brightness again, for when the firmware has lost it,
+    /// like after a suspend. Any ongoing fade is finished at once.
+    ///
+    /// The bus can take a while to come back, so every error is retried
+    /// until `timeout` has passed.
+    pub fn restore(&mut self, timeout: Duration) -> Result<()> {
+        let brightness = if self.enabled {
+            self.fade.take().map(|fade| fade.target).or(self.current)
+        } else {
+            Some(Brightness::Zero)
+        };
+        let Some(brightness) = brightness else {
+            return Ok(());
+        };
+        let deadline = Instant::now() + timeout;
+        loop {
+            match self.set_brightness_with_retry(brightness, self.retry_attempts, self.retry_delay)
+            {
+                Err(err) if Instant::now() < deadline => {
+                    debug!(
+                        "Restoring brightness failed, retrying in {:?}: {:#}",
+                        Self::RESTORE_INTERVAL,
+                        err
+                    );
+                    std::thread::sleep(Self::RESTORE_INTERVAL);
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Remember `brightness` for later if the numpad is disabled.
+    ///
+    /// Returns whether it should be written now.
+    fn check_enabled(&mut self, brightness: Brightness) -> bool {
+        if !self.enabled {
+            debug!(
+                "Numpad is disabled, not setting brightness to {}",
+                brightness
+            );
+            self.requested = Some(brightness);
+        }
+        self.enabled
+    }
+
+    /// Set the brightness immediately, cancelling any ongoing fade.
+    pub fn set_brightness(&mut self, brightness: Brightness) -> Result<()> {
+        self.fade = None;
+        if !self.check_enabled(brightness) {
+            return Ok(());
+        }
+        self.set_brightness_with_retry(brightness, self.retry_attempts, self.retry_delay)
+    }
+
+    /// Start a smooth transition to `target` over `duration`.
+    ///
+    /// Only the first step is done right away. The rest must be driven by
+    /// calling [`TouchpadI2C::advance_fade`] after [`TouchpadI2C::fade_timeout`].
+    pub fn set_brightness_faded(&mut self, target: Brightness, duration: Duration) -> Result<()> {
+        if !self.check_enabled(target) {
+            self.fade = None;
+            return Ok(());
+        }
+        let Some(current) = self.current else {
+            // we don't know where to fade from
+            return self.set_brightness(target);
+        };
+        let max_steps = (duration.as_millis() / Self::FADE_INTERVAL.as_millis()) as u32;
+        let steps = fade_steps(current, target, max_steps);
+        if steps.is_empty() {
+            return self.set_brightness(target);
+        }
+        self.fade = Some(Fade {
+            steps: steps.into_iter(),
+            target,
+            next_step_at: Instant::now(),
+        });
+        self.advance_fade()
+    }
+
+    /// Time left until the next step of the ongoing fade, if any.
+    pub fn fade_timeout(&self) -> Option<Duration> {
+        self.fade
+            .as_ref()
+            .map(|fade| fade.next_step_at.saturating_duration_since(Instant::now()))
+    }
+
+    /// Do the next step of the ongoing fade if it is due.
+    ///
+    /// Falls back to setting the target directly if the device rejects a step.
+    pub fn advance_fade(&mut self) -> Result<()> {
+        let Some(fade) = &mut self.fade else {
+            return Ok(());
+        };
+        let now = Instant::now();
+        if fade.next_step_at > now {
+            return Ok(());
+        }
+        let target = fade.target;
+        let Some(step) = fade.steps.next() else {
+            self.fade = None;
+            return Ok(());
+        };
+        fade.next_step_at = now + Self::FADE_INTERVAL;
+        if fade.steps.len() == 0 {
+            self.fade = None;
+        }
+        if let Err(err) = self.set_brightness_with_retry(step, 1, Duration::ZERO) {
+            debug!(
+                "Fade step to {} failed, jumping to {}: {:#}",
+                step, target, err
+            );
+            return self.set_brightness(target);
+        }
+        Ok(())
+    }
+
+    /// Set the brightness, trying up to `attempts` times, waiting for `delay`
+    /// before the first retry and doubling it every time after.
+    ///
+    /// The touchpad firmware sometimes fails writes while it is busy. Only
+    /// such transient errors are retried, others are returned immediately.
+    /// If the device has disappeared, it is opened again and the write is
+    /// retried once.
+    pub fn set_brightness_with_retry(
+        &mut self,
+        brightness: Brightness,
+        attempts: u32,
+        delay: Duration,
+    ) -> Result<()> {
+        self.write_brightness_msg(brightness.as_raw(), &brightness, attempts, delay)?;
+        self.current = Some(brightness);
+        Ok(())
+    }
+
+    /// Write `byte` as the brightness, even if it isn't a known level.
+    ///
+    /// Meant for experimenting with the firmware. Any ongoing fade is
+    /// cancelled, and the value is written even if the numpad is disabled.
+    ///
+    /// # Safety
+    ///
+    /// This can't cause memory unsafety, but the firmware only documents the
+    /// values of [`Brightness`]. Anything else might be ignored, misbehave,
+    /// or leave the touchpad in an odd state until it is reset, e.g. by a
+    /// suspend or a reboot.
+    pub fn set_brightness_raw(&mut self, byte: u8) -> Result<()> {
+        self.fade = None;
+        let result = self.write_brightness_msg(
+            byte,
+            &format_args!("{:#04x}", byte),
+            self.retry_attempts,
+            self.retry_delay,
+        );
+        // we can't tell what the touchpad is at after an unknown value
+        self.current = Brightness::try_from(byte).ok();
+        result
+    }
+
+    /// Put the backlight back in the state that the firmware starts in, for
+    /// when it was confused, e.g. by [`TouchpadI2C::set_brightness_raw`].
+    ///
+    /// The firmware has no reset of its own for the backlight (and resetting
+    /// the whole I2C-HID device would upset the kernel driver), so this turns
+    /// it off with the brightness message `05 00 3d 03 06 00 07 00 0d 14 03 00 ad`.
+    /// That is a SET_REPORT (`3d 03`) through the command register `0x0005`
+    /// and the data register `0x0006`, of the 7 byte (`07 00`) feature report
+    /// `0x0d`, with the brightness `00` after its `14 03` prefix.
+    ///
+    /// Any fade is cancelled. Whether the numpad is enabled doesn't change,
+    /// so the next brightness is written like after a fresh start.
+    pub fn reset(&mut self) -> Result<()> {
+        debug!("Resetting the backlight on /dev/i2c-{}", self.i2c_id);
+        self.fade = None;
+        self.current = None;
+        self.set_brightness_with_retry(Brightness::Zero, self.retry_attempts, self.retry_delay)
+    }
+
+    /// Write the brightness message with `raw` in it, retrying as
+    /// described by [`TouchpadI2C::set_brightness_with_retry`].
+    ///
+    /// `what` is how `raw` is shown in the error.
+    ///
+    /// Every supported model takes the same message, so it doesn't depend on
+    /// the layout or the DMI model name, only on [`TouchpadI2C::set_protocol`].
+    fn write_brightness_msg(
+        &mut self,
+        raw: u8,
+        what: &dyn std::fmt::Display,
+        attempts: u32,
+        delay: Duration,
+    ) -> Result<()> {
+        let msg = self.protocol.build(raw);
+        let mut attempt = 1;
+        let mut delay = delay;
+        let mut reopened = false;
+        let context = |attempt| {
+            format!(
+                "Could not set touchpad brightness to {} after {} attempt(s)",
+                what, attempt
+            )
+        };
+        loop {
+            match self.dev.write(&msg) {
+                Ok(()) => {
+                    debug!(
+                        "Set brightness to {} on /dev/i2c-{}: {:02x?}",
+                        what, self.i2c_id, msg
+                    );
+                    return Ok(());
+                }
+                Err(err) if attempt < attempts && err.is_transient() => {
+                    debug!(
+                        "Setting brightness failed (attempt {}), retrying in {:?}: {}",
+                        attempt, delay, err
+                    );
+                    std::thread::sleep(delay);
+                    delay *= 2;
+                    attempt += 1;
+                }
+                Err(err) if err.is_disconnected() && !reopened => {
+                    reopened = true;
+                    match self.dev.reopen(self.i2c_id, self.timeout) {
+                        Ok(()) => {
+                            info!("Reopened touchpad I2C at /dev/i2c-{}", self.i2c_id);
+                            attempt += 1;
+                        }
+                        Err(reopen_err) => {
+                            debug!("Failed to reopen touchpad I2C: {:#}", reopen_err);
+                            return Err(Error::new(err).context(context(attempt)));
+                        }
+                    }
+                }
+                Err(err) => return Err(Error::new(err).context(context(attempt))),
+            }
+        }
     }
 }
 
-impl Debug for TouchpadI2C {
+impl<D: I2CDevice> Debug for TouchpadI2C<D> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "TouchpadI2C: /dev/i2c-{}", self.i2c_id)
     }
 }
+
+#[test]
+fn test_brightness_try_from_u8() {
+    use Brightness::*;
+    for b in [Zero, Low, Half, Full, Custom(20)] {
+        assert_eq!(Brightness::try_from(b.as_raw()).unwrap(), b);
+    }
+    assert_eq!(Brightness::try_from(2).unwrap(), Custom(2));
+    assert!(Brightness::try_from(32).is_err());
+    assert!(Brightness::try_from(255).is_err());
+}
+
+#[test]
+fn test_brightness_try_from_str() {
+    use Brightness::*;
+    for b in [Zero, Low, Half, Full] {
+        assert_eq!(Brightness::try_from(b.to_string().as_str()).unwrap(), b);
+    }
+    assert_eq!(Brightness::try_from("half").unwrap(), Half);
+    assert_eq!(Brightness::try_from("FULL").unwrap(), Full);
+    assert_eq!(Brightness::try_from("20").unwrap(), Custom(20));
+    assert!(Brightness::try_from("200").is_err());
+    assert!(Brightness::try_from("medium").is_err());
+    assert!(Brightness::try_from("").is_err());
+    assert_eq!("low".parse::<Brightness>().unwrap(), Low);
+    assert_eq!("0".parse::<Brightness>().unwrap(), Zero);
+    assert_eq!("50%".parse::<Brightness>().unwrap(), Half);
+    assert!("on".parse::<Brightness>().is_err());
+}
+
+#[test]
+fn test_brightness_all() {
+    use Brightness::*;
+    let mut levels = Brightness::all();
+    assert_eq!(levels.len(), 4);
+    assert_eq!(levels.next(), Some(Zero));
+    assert_eq!(levels.len(), 3);
+    assert_eq!(levels.collect::<Vec<_>>(), [Low, Half, Full]);
+    // after Zero, each level is the next one in the cycle
+    let levels: Vec<_> = Brightness::all().skip(1).collect();
+    for pair in levels.windows(2) {
+        assert_eq!(pair[0].next(), pair[1]);
+    }
+    assert_eq!(levels[levels.len() - 1].next(), levels[0]);
+}
+
+#[test]
+fn test_parse_i2c_dev_name() {
+    assert_eq!(parse_i2c_dev_name("i2c-0"), Some(0));
+    assert_eq!(parse_i2c_dev_name("i2c-12"), Some(12));
+    assert_eq!(parse_i2c_dev_name("i2c-"), None);
+    assert_eq!(parse_i2c_dev_name("tty1"), None);
+}
+
+#[test]
+fn test_timeout_units() {
+    assert_eq!(timeout_units(Duration::from_millis(100)), 10);
+    assert_eq!(timeout_units(Duration::from_millis(15)), 2);
+    assert_eq!(timeout_units(Duration::from_micros(1)), 1);
+    assert_eq!(timeout_units(Duration::ZERO), 1);
+}
+
+#[test]
+fn test_adapter_id_from_path() {
+    let path =
+        Path::new("/sys/devices/pci0000:00/0000:00:15.0/i2c_designware.0/i2c-1/i2c-ELAN1200:00");
+    assert_eq!(adapter_id_from_path(path), Some(1));
+    assert_eq!(
+        adapter_id_from_path(Path::new("/sys/bus/i2c/devices/i2c-3")),
+        None
+    );
+    assert!(is_known_touchpad("acpi:ASUE140D:PNP0C50:\n"));
+    assert!(!is_known_touchpad("acpi:INT33D5:"));
+}
+
+#[test]
+fn test_pick_touchpad() {
+    let candidate = |name: &str, i2c_id| (name.to_owned(), i2c_id);
+    assert_eq!(pick_touchpad(&[]), None);
+    let candidates = [
+        candidate("ELAN1200:00", 0),
+        candidate("ASUE140D:00", 1),
+        candidate("ASUE140A:00", 2),
+    ];
+    assert_eq!(pick_touchpad(&candidates), Some(&candidates[1]));
+    assert_eq!(pick_touchpad(&candidates[..1]), Some(&candidates[0]));
+}
+
+#[test]
+fn test_brightness_cycle_down() {
+    use Brightness::*;
+    for b in [Low, Half] {
+        assert_eq!(b.next().prev(), b);
+    }
+    for b in [Half, Full] {
+        assert_eq!(b.prev().next(), b);
+    }
+    let mut b = Full;
+    assert_eq!(b.cycle_down(), Half);
+    assert_eq!(b.cycle_down(), Low);
+    // stepping down past the dimmest level doesn't jump to the brightest
+    assert_eq!(b.cycle_down(), Low);
+    assert_eq!(Zero.prev(), Zero);
+}
+
+#[test]
+fn test_brightness_percent_continuous() {
+    assert_eq!(Brightness::from_percent_continuous(0), Brightness::Low);
+    assert_eq!(
+        Brightness::from_percent_continuous(50),
+        Brightness::Custom(16)
+    );
+    assert_eq!(Brightness::from_percent_continuous(100), Brightness::Full);
+    assert_eq!(Brightness::from_percent_continuous(200), Brightness::Full);
+    let raws: Vec<_> = (0..=100)
+        .map(|pct| Brightness::from_percent_continuous(pct).as_raw())
+        .collect();
+    assert!(raws.windows(2).all(|pair| pair[0] >= pair[1]));
+}
+
+#[test]
+fn test_brightness_percent() {
+    use Brightness::*;
+    assert_eq!(Brightness::from_percent(0), Zero);
+    assert_eq!(Brightness::from_percent(10), Low);
+    assert_eq!(Brightness::from_percent(50), Half);
+    assert_eq!(Brightness::from_percent(100), Full);
+    assert_eq!(Brightness::from_percent(255), Full);
+    for b in [Zero, Low, Half, Full] {
+        assert_eq!(Brightness::from_percent(b.to_percent()), b);
+    }
+    assert_eq!(Custom(1).to_percent(), 100);
+    assert_eq!(Custom(Brightness::MAX_RAW).to_percent(), 33);
+    assert_eq!(Brightness::try_from("50%").unwrap(), Half);
+    assert!(Brightness::try_from("150%").is_err());
+}
+
+#[test]
+fn test_build_brightness_msg() {
+    assert_eq!(
+        build_brightness_msg(Brightness::Half.as_raw()),
+        [
+            0x05, 0x00, 0x3d, 0x03, 0x06, 0x00, 0x07, 0x00, 0x0d, 0x14, 0x03, 0x18, 0xad
+        ]
+    );
+    // the report read back by get_brightness starts the same way
+    let msg = build_brightness_msg(0x1f);
+    assert_eq!(msg[6..11], BRIGHTNESS_REPORT_HEADER);
+    assert_eq!(msg[11], 0x1f);
+    assert_eq!(parse_brightness_msg(&msg), Some(0x1f));
+    assert_eq!(parse_brightness_msg(&msg[1..]), None);
+}
+
+#[test]
+fn test_brightness_protocol() {
+    let default = BrightnessProtocol::default();
+    for raw in [0x00, 0x01, 0x18, 0x1f] {
+        assert_eq!(default.build(raw), build_brightness_msg(raw));
+    }
+
+    let preamble = [1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11];
+    let protocol = BrightnessProtocol::new(preamble, 12, Some(0)).unwrap();
+    let msg = protocol.build(0x18);
+    assert_eq!(msg[1..12], preamble);
+    assert_eq!(msg[12], 0x18);
+    assert_eq!(msg.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte)), 0);
+
+    assert!(BrightnessProtocol::new(preamble, 13, None).is_err());
+    assert!(BrightnessProtocol::new(preamble, 12, None).is_err());
+    assert!(BrightnessProtocol::new(preamble, 3, Some(3)).is_err());
+
+    let mut touchpad = TouchpadI2C::from_device(MockI2CDevice::default(), 1);
+    touchpad.set_protocol(protocol);
+    touchpad.set_brightness(Brightness::Half).unwrap();
+    assert_eq!(touchpad.dev.writes.pop().unwrap(), msg);
+
+    // the report read back is the one that the protocol writes
+    touchpad.dev.reads.push(msg[6..].to_vec());
+    assert_eq!(touchpad.get_brightness(false).unwrap(), Brightness::Half);
+    touchpad
+        .dev
+        .reads
+        .push(build_brightness_msg(0x18)[6..].to_vec());
+    assert!(touchpad.get_brightness(false).is_err());
+    let mut garbled = msg[6..].to_vec();
+    garbled[3] ^= 0xff;
+    touchpad.dev.reads.push(garbled);
+    assert!(touchpad.get_brightness(false).is_err());
+
+    // and one with the brightness before the report can't be read back at all
+    touchpad.set_protocol(BrightnessProtocol::new(preamble, 3, None).unwrap());
+    touchpad.dev.reads.push(msg[6..].to_vec());
+    assert!(touchpad.get_brightness(false).is_err());
+}
+
+#[test]
+fn test_set_brightness_payload() {
+    use Brightness::*;
+    let mut touchpad = TouchpadI2C::from_device(MockI2CDevice::default(), 1);
+    for (brightness, raw) in [
+        (Zero, 0x00),
+        (Low, 0x1f),
+        (Half, 0x18),
+        (Full, 0x01),
+        (Custom(5), 0x05),
+    ] {
+        touchpad.set_brightness(brightness).unwrap();
+        assert_eq!(
+            touchpad.dev.writes.pop().unwrap(),
+            [
+                0x05, 0x00, 0x3d, 0x03, 0x06, 0x00, 0x07, 0x00, 0x0d, 0x14, 0x03, raw, 0xad
+            ]
+        );
+    }
+    assert!(touchpad.dev.writes.is_empty());
+}
+
+#[test]
+fn test_open_error_context() {
+    let err = |kind: std::io::ErrorKind| open_error(LinuxI2CError::Io(kind.into()), 3).to_string();
+    assert_eq!(
+        err(NotFound),
+        "Unable to open Touchpad I2C at /dev/i2c-3. Is i2c-dev kernel module loaded?"
+    );
+    assert_eq!(
+        err(PermissionDenied),
+        "Unable to open Touchpad I2C at /dev/i2c-3. Do you have the permission to read /dev/i2c-*?"
+    );
+    assert_eq!(
+        err(std::io::ErrorKind::Other),
+        "Unable to open Touchpad I2C at /dev/i2c-3"
+    );
+}
+
+#[test]
+fn test_brightness_cycler() {
+    use Brightness::*;
+    let mut cycler = BrightnessCycler::new(Half);
+    assert_eq!(cycler.get(), Zero);
+    assert_eq!(cycler.toggle_off(), Half);
+    assert_eq!(cycler.cycle(), Full);
+    assert_eq!(cycler.toggle_off(), Zero);
+    assert_eq!(cycler.level(), Full);
+    assert_eq!(cycler.toggle_off(), Full);
+    for _ in 0..5 {
+        assert_ne!(cycler.cycle(), Zero);
+    }
+}
+
+#[test]
+fn test_device_error() {
+    use std::io::Error;
+    assert!(Error::from_raw_os_error(libc::EAGAIN).is_transient());
+    assert!(LinuxI2CError::Errno(libc::EREMOTEIO).is_transient());
+    assert!(!Error::from_raw_os_error(libc::ENODEV).is_transient());
+    assert!(!LinuxI2CError::Io(Error::from_raw_os_error(libc::EACCES)).is_transient());
+    assert!(LinuxI2CError::Io(Error::from_raw_os_error(libc::ENODEV)).is_disconnected());
+    assert!(LinuxI2CError::Errno(libc::ENXIO).is_disconnected());
+    assert!(!Error::from_raw_os_error(libc::EAGAIN).is_disconnected());
+}
+
+#[test]
+fn test_set_brightness_retry() {
+    let mut touchpad = TouchpadI2C::from_device(MockI2CDevice::default(), 1);
+    use std::io::ErrorKind::*;
+    touchpad.dev.write_errors = vec![WouldBlock, TimedOut];
+    touchpad.set_brightness(Brightness::Half).unwrap();
+    assert_eq!(touchpad.dev.writes.len(), 1);
+
+    touchpad.dev.write_errors = vec![WouldBlock; 3];
+    let err = touchpad
+        .set_brightness_with_retry(Brightness::Half, 3, Duration::ZERO)
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Could not set touchpad brightness to Half after 3 attempt(s)"
+    );
+    assert_eq!(touchpad.dev.writes.len(), 1);
+
+    touchpad.set_retry(5, Duration::ZERO);
+    touchpad.dev.write_errors = vec![WouldBlock; 4];
+    touchpad.set_brightness(Brightness::Low).unwrap();
+    assert_eq!(touchpad.dev.writes.len(), 2);
+
+    // permanent errors are not retried
+    touchpad.dev.write_errors = vec![PermissionDenied, WouldBlock];
+    let err = touchpad.set_brightness(Brightness::Full).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Could not set touchpad brightness to Full after 1 attempt(s)"
+    );
+    assert_eq!(touchpad.dev.write_errors, [WouldBlock]);
+}
+
+#[test]
+fn test_fade_steps() {
+    use Brightness::*;
+    let steps = fade_steps(Zero, Full, 100);
+    assert_eq!(steps.len(), 31);
+    assert_eq!(steps[0], Low);
+    assert_eq!(steps.last(), Some(&Full));
+    // capped and ending at the target
+    let steps = fade_steps(Full, Half, 4);
+    assert_eq!(steps.len(), 4);
+    assert_eq!(steps.last(), Some(&Half));
+    assert!(steps.windows(2).all(|w| w[0].as_raw() < w[1].as_raw()));
+    assert!(fade_steps(Half, Half, 10).is_empty());
+    assert_eq!(fade_steps(Zero, Full, 0), []);
+}
+
+#[test]
+fn test_set_brightness_faded() {
+    let mut touchpad = TouchpadI2C::from_device(MockI2CDevice::default(), 1);
+    // no known level to fade from
+    touchpad
+        .set_brightness_faded(Brightness::Full, Duration::from_millis(150))
+        .unwrap();
+    assert_eq!(touchpad.dev.writes.len(), 1);
+    assert!(touchpad.fade_timeout().is_none());
+
+    touchpad
+        .set_brightness_faded(Brightness::Low, Duration::from_millis(150))
+        .unwrap();
+    assert_eq!(touchpad.dev.writes.len(), 2);
+    while let Some(timeout) = touchpad.fade_timeout() {
+        std::thread::sleep(timeout);
+        touchpad.advance_fade().unwrap();
+    }
+    assert_eq!(touchpad.dev.writes.len(), 11);
+    assert_eq!(
+        touchpad.dev.writes.last().unwrap()[11],
+        Brightness::Low.as_raw()
+    );
+
+    // rejected steps jump straight to the target
+    touchpad
+        .set_brightness_faded(Brightness::Full, Duration::from_millis(150))
+        .unwrap();
+    touchpad.dev.write_errors = vec![std::io::ErrorKind::InvalidInput];
+    std::thread::sleep(TouchpadI2C::<MockI2CDevice>::FADE_INTERVAL);
+    touchpad.advance_fade().unwrap();
+    assert!(touchpad.fade_timeout().is_none());
+    assert_eq!(
+        touchpad.dev.writes.last().unwrap()[11],
+        Brightness::Full.as_raw()
+    );
+}
+
+#[test]
+fn test_read_firmware_version() {
+    let mut touchpad = TouchpadI2C::from_device(MockI2CDevice::default(), 1);
+    // an example HID descriptor using the expected registers
+    touchpad.dev.reads.push(vec![
+        0x1e, 0x00, 0x00, 0x01, 0x6b, 0x02, 0x02, 0x00, 0x03, 0x00, 0x20, 0x00, 0x04, 0x00, 0x11,
+        0x00, 0x05, 0x00, 0x06, 0x00, 0x6b, 0x04, 0x0d, 0x31, 0x02, 0x00, 0x00, 0x00, 0x00, 0x00,
+    ]);
+    assert_eq!(
+        touchpad.read_firmware_version().unwrap(),
+        [0x0d, 0x31, 0x02, 0x00]
+    );
+    assert_eq!(touchpad.dev.writes, [[0x01, 0x00]]);
+    assert!(touchpad.read_firmware_version().is_err());
+}
+
+#[test]
+fn test_identify_device() {
+    let mut touchpad = TouchpadI2C::from_device(MockI2CDevice::default(), 1);
+    let mut desc = vec![0; HID_DESC_LENGTH];
+    desc[22..26].copy_from_slice(&[0x0d, 0x31, 0x02, 0x00]);
+    touchpad.dev.reads.push(desc.clone());
+    let models = || Ok(vec!["ZenBook UX433FA_UX433FA".to_owned()]);
+    let info = touchpad.identify_device(models).unwrap();
+    assert_eq!(info.models, ["ZenBook UX433FA_UX433FA"]);
+    assert_eq!(info.layout, Some(SupportedLayout::UX433FA));
+    assert_eq!(info.i2c_id, 1);
+    assert_eq!(info.firmware_version, [0x0d, 0x31, 0x02, 0x00]);
+    // a model that can't be read or detected is left out, the firmware isn't
+    touchpad.dev.reads.push(desc);
+    let info = touchpad.identify_device(|| Err(anyhow!("No DMI"))).unwrap();
+    assert!(info.models.is_empty());
+    assert_eq!(info.layout, None);
+    assert_eq!(info.firmware_version, [0x0d, 0x31, 0x02, 0x00]);
+    // without a touchpad to answer, there is nothing to identify
+    assert!(touchpad.identify_device(models).is_err());
+
+    let info = DeviceInfo {
+        models: vec!["ZenBook UX433FA_UX433FA".to_owned(), "UX433FA".to_owned()],
+        layout: Some(SupportedLayout::UX433FA),
+        i2c_id: 1,
+        firmware_version: [0x0d, 0x31, 0x02, 0x00],
+    };
+    assert_eq!(
+        info.to_string(),
+        "Model: ZenBook UX433FA_UX433FA / UX433FA\n\
+        Layout: UX433FA\n\
+        Touchpad I2C: /dev/i2c-1\n\
+        Firmware version: [0d, 31, 02, 00]\n"
+    );
+    assert_eq!(
+        serde_json::to_value(&info).unwrap()["layout"],
+        serde_json::json!("UX433FA")
+    );
+}
+
+#[test]
+fn test_set_brightness_reopen() {
+    use std::io::ErrorKind::NotConnected;
+    let mut touchpad = TouchpadI2C::from_device(MockI2CDevice::default(), 1);
+    touchpad.dev.write_errors = vec![NotConnected];
+    touchpad.set_brightness(Brightness::Half).unwrap();
+    assert_eq!(touchpad.dev.reopened, 1);
+    assert_eq!(touchpad.dev.writes.len(), 1);
+
+    // only reopened once per write
+    touchpad.dev.write_errors = vec![NotConnected, NotConnected];
+    assert!(touchpad.set_brightness(Brightness::Half).is_err());
+    assert_eq!(touchpad.dev.reopened, 2);
+
+    touchpad.dev.reopen_fails = true;
+    touchpad.dev.write_errors = vec![NotConnected];
+    let err = touchpad.set_brightness(Brightness::Full).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Could not set touchpad brightness to Full after 1 attempt(s)"
+    );
+}
+
+#[test]
+fn test_enable_numpad() {
+    let mut touchpad = TouchpadI2C::from_device(MockI2CDevice::default(), 1);
+    let raw = |touchpad: &TouchpadI2C<MockI2CDevice>| touchpad.dev.writes.last().unwrap()[11];
+    touchpad.set_brightness(Brightness::Half).unwrap();
+    touchpad.enable_numpad(false).unwrap();
+    assert_eq!(raw(&touchpad), Brightness::Zero.as_raw());
+
+    // not written while disabled
+    touchpad.set_brightness(Brightness::Low).unwrap();
+    touchpad
+        .set_brightness_faded(Brightness::Full, Duration::from_millis(150))
+        .unwrap();
+    assert_eq!(touchpad.dev.writes.len(), 2);
+    assert_eq!(touchpad.fade_timeout(), None);
+    touchpad.enable_numpad(false).unwrap();
+    assert_eq!(touchpad.dev.writes.len(), 2);
+
+    touchpad.enable_numpad(true).unwrap();
+    assert_eq!(raw(&touchpad), Brightness::Full.as_raw());
+    touchpad.enable_numpad(false).unwrap();
+    touchpad.enable_numpad(true).unwrap();
+    assert_eq!(raw(&touchpad), Brightness::Full.as_raw());
+    assert_eq!(touchpad.dev.writes.len(), 5);
+}
+
+#[test]
+fn test_turn_off() {
+    let mut touchpad = TouchpadI2C::from_device(MockI2CDevice::default(), 1);
+    touchpad
+        .set_brightness_faded(Brightness::Half, Duration::from_millis(150))
+        .unwrap();
+    touchpad.turn_off().unwrap();
+    assert_eq!(touchpad.fade_timeout(), None);
+    assert_eq!(
+        touchpad.dev.writes.last().unwrap()[11],
+        Brightness::Zero.as_raw()
+    );
+
+    // written even if disabled
+    touchpad.enable_numpad(false).unwrap();
+    touchpad.turn_off().unwrap();
+    assert_eq!(touchpad.dev.writes.len(), 4);
+
+    touchpad.dev.write_errors = vec![std::io::ErrorKind::PermissionDenied];
+    assert!(touchpad.turn_off().is_err());
+}
+
+#[test]
+fn test_restore() {
+    let mut touchpad = TouchpadI2C::from_device(MockI2CDevice::default(), 1);
+    touchpad.restore(Duration::ZERO).unwrap();
+    assert!(touchpad.dev.writes.is_empty());
+
+    touchpad.set_brightness(Brightness::Half).unwrap();
+    // not transient, but retried anyway
+    touchpad.dev.write_errors = vec![std::io::ErrorKind::PermissionDenied; 2];
+    touchpad.restore(Duration::from_secs(5)).unwrap();
+    assert_eq!(touchpad.dev.writes.len(), 2);
+    assert_eq!(
+        touchpad.dev.writes.last().unwrap()[11],
+        Brightness::Half.as_raw()
+    );
+
+    touchpad
+        .set_brightness_faded(Brightness::Low, Duration::from_millis(150))
+        .unwrap();
+    touchpad.restore(Duration::ZERO).unwrap();
+    assert_eq!(touchpad.fade_timeout(), None);
+    assert_eq!(
+        touchpad.dev.writes.last().unwrap()[11],
+        Brightness::Low.as_raw()
+    );
+
+    touchpad.enable_numpad(false).unwrap();
+    touchpad.restore(Duration::ZERO).unwrap();
+    assert_eq!(
+        touchpad.dev.writes.last().unwrap()[11],
+        Brightness::Zero.as_raw()
+    );
+
+    touchpad.dev.write_errors = vec![std::io::ErrorKind::PermissionDenied];
+    assert!(touchpad.restore(Duration::ZERO).is_err());
+}
+
+#[test]
+fn test_is_device_lost() {
+    let lost = |err: std::io::Error| is_device_lost(&Error::new(err).context("Setting brightness"));
+    assert!(lost(std::io::Error::from_raw_os_error(libc::ENODEV)));
+    assert!(lost(std::io::Error::from_raw_os_error(libc::EREMOTEIO)));
+    assert!(!lost(std::io::Error::from_raw_os_error(libc::EBUSY)));
+    assert!(!lost(std::io::ErrorKind::PermissionDenied.into()));
+    assert!(is_device_lost(&Error::new(LinuxI2CError::Errno(
+        libc::ENXIO
+    ))));
+    assert!(!is_device_lost(&anyhow!("Invalid config")));
+}
+
+#[test]
+fn test_get_brightness() {
+    let mut touchpad = TouchpadI2C::from_device(MockI2CDevice::default(), 1);
+    touchpad
+        .dev
+        .reads
+        .push(vec![0x07, 0x00, 0x0d, 0x14, 0x03, 0x18, 0xad]);
+    assert_eq!(touchpad.get_brightness(true).unwrap(), Brightness::Half);
+    assert_eq!(touchpad.dev.writes, [[0x05, 0x00, 0x3d, 0x02, 0x06, 0x00]]);
+    touchpad.set_brightness(Brightness::Low).unwrap();
+    assert_eq!(touchpad.get_brightness(true).unwrap(), Brightness::Low);
+    assert_eq!(touchpad.dev.writes.len(), 2);
+
+    touchpad
+        .dev
+        .reads
+        .push(vec![0x07, 0x00, 0x0d, 0x14, 0x03, 0x00, 0xad]);
+    assert_eq!(touchpad.get_brightness(false).unwrap(), Brightness::Zero);
+    // the firmware was reset, but Low is still the one to restore
+    assert_eq!(touchpad.get_brightness(true).unwrap(), Brightness::Low);
+
+    touchpad
+        .dev
+        .reads
+        .push(vec![0x07, 0x00, 0x0d, 0x14, 0x03, 0x40, 0xad]);
+    assert!(touchpad.get_brightness(false).is_err());
+    touchpad
+        .dev
+        .reads
+        .push(vec![0x07, 0x00, 0x0e, 0x00, 0x00, 0x00, 0x00]);
+    assert!(touchpad.get_brightness(false).is_err());
+    // garbled on the bus
+    touchpad
+        .dev
+        .reads
+        .push(vec![0x07, 0x00, 0x0d, 0x14, 0x03, 0x18, 0xff]);
+    let err = touchpad.get_brightness(false).unwrap_err();
+    assert!(err.to_string().starts_with("Corrupted"), "{:#}", err);
+}
+
+#[test]
+fn test_set_brightness_raw() {
+    let mut touchpad = TouchpadI2C::from_device(MockI2CDevice::default(), 1);
+    touchpad.set_brightness_raw(0x40).unwrap();
+    assert_eq!(touchpad.dev.writes.len(), 1);
+    assert_eq!(touchpad.dev.writes[0][11], 0x40);
+    assert_eq!(touchpad.current, None);
+
+    touchpad.set_brightness_raw(24).unwrap();
+    assert_eq!(touchpad.dev.writes[1][11], 24);
+    assert_eq!(touchpad.current, Some(Brightness::Half));
+
+    touchpad.dev.write_errors = vec![std::io::ErrorKind::PermissionDenied];
+    let err = touchpad.set_brightness_raw(0xff).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Could not set touchpad brightness to 0xff after 1 attempt(s)"
+    );
+}
+
+#[test]
+fn test_brightness_serde() {
+    use Brightness::*;
+    for b in [Zero, Low, Half, Full, Custom(20)] {
+        let json = serde_json::to_string(&b).unwrap();
+        assert_eq!(serde_json::from_str::<Brightness>(&json).unwrap(), b);
+    }
+    assert_eq!(serde_json::to_string(&Half).unwrap(), "\"Half\"");
+    assert_eq!(serde_json::to_string(&Custom(20)).unwrap(), "20");
+    assert_eq!(
+        serde_json::from_str::<Brightness>("\"HALF\"").unwrap(),
+        Half
+    );
+    assert!(serde_json::from_str::<Brightness>("\"dim\"").is_err());
+}
+
+#[test]
+fn test_reset() {
+    let mut touchpad = TouchpadI2C::from_device(MockI2CDevice::default(), 1);
+    touchpad.set_brightness_raw(0x40).unwrap();
+    touchpad.reset().unwrap();
+    assert_eq!(
+        touchpad.dev.writes[1],
+        [
+            0x05, 0x00, 0x3d, 0x03, 0x06, 0x00, 0x07, 0x00, 0x0d, 0x14, 0x03, 0x00, 0xad
+        ]
+    );
+    assert_eq!(touchpad.get_brightness(true).unwrap(), Brightness::Zero);
+
+    touchpad.set_brightness(Brightness::Half).unwrap();
+    assert_eq!(touchpad.dev.writes.len(), 3);
+    assert_eq!(touchpad.dev.writes[2][11], Brightness::Half.as_raw());
+    assert_eq!(touchpad.get_brightness(true).unwrap(), Brightness::Half);
+}
@@ -0,0 +1,76 @@
+use std::io::ErrorKind;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+
+/// Where `generate-udev-rules` writes by default.
+pub(crate) const DEFAULT_RULES_PATH: &str = "/etc/udev/rules.d/99-asus-numpad.rules";
+
+const HEADER: &str = "# Generated by `asus-numpad generate-udev-rules`.\n\
+    # Lets the input group set the numpad brightness over I2C.\n";
+
+/// The rule giving the `input` group access to `/dev/i2c-{i2c_id}`.
+fn i2c_rule(i2c_id: u32) -> String {
+    format!(
+        "KERNEL==\"i2c-{}\", SUBSYSTEM==\"i2c-dev\", GROUP=\"input\", MODE=\"0660\"",
+        i2c_id
+    )
+}
+
+/// Add the rule for `i2c_id` to the `existing` rules, unless it is already there.
+fn merge_rules(existing: &str, i2c_id: u32) -> String {
+    let rule = i2c_rule(i2c_id);
+    if existing.lines().any(|line| line.trim() == rule) {
+        return existing.to_owned();
+    }
+    let mut rules = if existing.trim().is_empty() {
+        HEADER.to_owned()
+    } else {
+        existing.to_owned()
+    };
+    if !rules.ends_with('\n') {
+        rules.push('\n');
+    }
+    rules.push_str(&rule);
+    rules.push('\n');
+    rules
+}
+
+/// Write the rule for `i2c_id` to `path`, keeping any other rules in it.
+///
+/// Running it again for the same bus leaves the file as it is.
+pub(crate) fn write_rules(path: &Path, i2c_id: u32) -> Result<()> {
+    let existing = match std::fs::read_to_string(path) {
+        Ok(existing) => existing,
+        Err(err) if err.kind() == ErrorKind::NotFound => String::new(),
+        Err(err) => return Err(err).with_context(|| format!("Could not read {}", path.display())),
+    };
+    let rules = merge_rules(&existing, i2c_id);
+    if rules != existing {
+        std::fs::write(path, rules)
+            .with_context(|| format!("Could not write {}", path.display()))?;
+    }
+    Ok(())
+}
+
+#[test]
+fn test_merge_rules() {
+    let rules = merge_rules("", 3);
+    assert!(rules.starts_with(HEADER));
+    assert!(
+        rules.ends_with(
+            "KERNEL==\"i2c-3\", SUBSYSTEM==\"i2c-dev\", GROUP=\"input\", MODE=\"0660\"\n"
+        )
+    );
+    assert_eq!(merge_rules(&rules, 3), rules);
+
+    let rules = merge_rules(&rules, 5);
+    assert_eq!(rules.matches("KERNEL==").count(), 2);
+    assert_eq!(rules.matches(HEADER).count(), 1);
+
+    let other = "KERNEL==\"uinput\", GROUP=\"uinput\", MODE:=\"0660\"";
+    assert_eq!(
+        merge_rules(other, 3),
+        format!("{}\n{}\n", other, i2c_rule(3))
+    );
+}